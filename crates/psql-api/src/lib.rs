@@ -1,5 +1,5 @@
 use psql::http::{
-    plan::{Method, Query},
+    plan::{quote_ident_with, Dialect, Method, Query},
     NewQuery,
 };
 use reqwest::Client;
@@ -14,6 +14,8 @@ pub enum DBDialect {
     Mysql,
     #[serde(rename = "sqlite")]
     Sqlite,
+    #[serde(rename = "postgres")]
+    Postgres,
     #[serde(rename = "unknown")]
     Unknown,
 }
@@ -24,16 +26,32 @@ impl DBDialect {
             Self::Mysql
         } else if uri.starts_with("sqlite") {
             Self::Sqlite
+        } else if uri.starts_with("postgres://") || uri.starts_with("postgresql://") {
+            Self::Postgres
         } else {
             Self::Unknown
         }
     }
+
+    /// quote identifier, unknown dialect uses ansi double quote
+    pub fn quote_ident(&self, ident: &str) -> String {
+        match self {
+            Self::Mysql => Dialect::Mysql.quote_ident(ident),
+            Self::Sqlite => Dialect::Sqlite.quote_ident(ident),
+            Self::Postgres => Dialect::Postgres.quote_ident(ident),
+            Self::Unknown => quote_ident_with(ident, '"'),
+        }
+    }
 }
 
 type Resp = reqwest::Result<reqwest::Response>;
 
-fn not_support_sql(name: &str, op: &str) -> String {
-    format!("SELECT 'error' AS `status`, '{name} do not support {op} operation' AS `msg`")
+fn not_support_sql(dialect: &DBDialect, name: &str, op: &str) -> String {
+    format!(
+        "SELECT 'error' AS {}, '{name} do not support {op} operation' AS {}",
+        dialect.quote_ident("status"),
+        dialect.quote_ident("msg")
+    )
 }
 
 fn meta_tags() -> Vec<String> {
@@ -45,11 +63,16 @@ pub fn schema_query(dialect: &DBDialect, conn: &str) -> NewQuery {
     let sql = match dialect {
         DBDialect::Mysql => "SELECT DATABASE() AS `db`".to_string(),
         DBDialect::Sqlite => format!(
-            "SELECT '{conn}' AS `db`, 'sqlite do not support database() function!' as `msg`"
+            "SELECT '{conn}' AS {}, 'sqlite do not support database() function!' as {}",
+            dialect.quote_ident("db"),
+            dialect.quote_ident("msg")
+        ),
+        DBDialect::Postgres => r#"SELECT current_database() AS "db""#.to_string(),
+        DBDialect::Unknown => format!(
+            "SELECT '{conn}' AS {}, 'unknown database dialect' as {}",
+            dialect.quote_ident("db"),
+            dialect.quote_ident("msg")
         ),
-        DBDialect::Unknown => {
-            format!("SELECT '{conn}' AS `db`, 'unknown database dialect' as `msg`")
-        }
     };
     NewQuery {
         name: "schema".to_string(),
@@ -60,6 +83,7 @@ pub fn schema_query(dialect: &DBDialect, conn: &str) -> NewQuery {
             sql,
             path: format!("{conn}/__meta/schema"),
             tags: meta_tags(),
+            ..Default::default()
         },
     }
 }
@@ -67,17 +91,25 @@ pub fn schema_query(dialect: &DBDialect, conn: &str) -> NewQuery {
 /// list database all table query params
 pub fn tables_query(dialect: &DBDialect, conn: &str) -> NewQuery {
     let sql = match dialect {
-        DBDialect::Mysql => format!(
-            r#"SELECT `table_name` AS `name`, `engine`
-        FROM information_schema.tables"
+        DBDialect::Mysql => r#"SELECT `table_name` AS `name`, `engine`
+        FROM information_schema.tables
         WHERE `table_type` = 'BASE TABLE' AND `table_schema` = DATABASE()"#
-        ),
-        DBDialect::Sqlite => format!(
-            r#"SELECT `tbl_name` AS `name`
-        FROM sqlite_master
-        WHERE type = 'table' AND `tbl_name` not like 'sqlite_%'"#
-        ),
-        DBDialect::Unknown => not_support_sql(conn, "list table"),
+            .to_string(),
+        DBDialect::Sqlite => r#"SELECT `name`,
+            `opts` LIKE '%)STRICT' OR `opts` LIKE '%)STRICT,WITHOUTROWID' OR `opts` LIKE '%,STRICT' AS `strict`,
+            `opts` LIKE '%)WITHOUTROWID' OR `opts` LIKE '%)WITHOUTROWID,STRICT' OR `opts` LIKE '%,WITHOUTROWID' AS `without_rowid`
+        FROM (
+            SELECT `tbl_name` AS `name`,
+                upper(replace(replace(replace(replace(`sql`, ' ', ''), char(9), ''), char(10), ''), char(13), '')) AS `opts`
+            FROM sqlite_master
+            WHERE type = 'table' AND `tbl_name` not like 'sqlite_%'
+        )"#
+            .to_string(),
+        DBDialect::Postgres => r#"SELECT "table_name" AS "name", NULL AS "engine"
+        FROM information_schema.tables
+        WHERE "table_type" = 'BASE TABLE' AND "table_schema" = current_schema()"#
+            .to_string(),
+        DBDialect::Unknown => not_support_sql(dialect, conn, "list table"),
     };
     NewQuery {
         name: "tables".to_string(),
@@ -88,6 +120,7 @@ pub fn tables_query(dialect: &DBDialect, conn: &str) -> NewQuery {
             sql,
             path: format!("{conn}/__meta/tables"),
             tags: meta_tags(),
+            ..Default::default()
         },
     }
 }
@@ -95,20 +128,29 @@ pub fn tables_query(dialect: &DBDialect, conn: &str) -> NewQuery {
 /// get table indexes query params
 pub fn table_index_query(dialect: &DBDialect, conn: &str) -> NewQuery {
     let sql = match dialect {
-        DBDialect::Mysql => format!(
-            r#"--? table: str // 表名
+        DBDialect::Mysql => r#"--? table: str // 表名
         select
             TABLE_SCHEMA AS `db`, TABLE_NAME AS `table`, NON_UNIQUE AS `can_duplicate`, INDEX_NAME AS `name`, COLUMN_NAME AS `column_name`, INDEX_TYPE AS `type`
         from information_schema.STATISTICS
         where table_name = @table AND TABLE_SCHEMA = DATABASE()"#
-        ),
-        DBDialect::Sqlite => format!(
-            r#"--? table: str // 表名
+        .to_string(),
+        DBDialect::Sqlite => r#"--? table: str // 表名
         SELECT `name`
         FROM sqlite_master
         WHERE type = 'index' AND tbl_name = @table"#
-        ),
-        DBDialect::Unknown => not_support_sql(conn, "get table index"),
+        .to_string(),
+        DBDialect::Postgres => r#"--? table: str // 表名
+        SELECT
+            n.nspname AS "db", t.relname AS "table", NOT ix.indisunique AS "can_duplicate", i.relname AS "name", a.attname AS "column_name", am.amname AS "type"
+        FROM pg_index ix
+            JOIN pg_class t ON t.oid = ix.indrelid
+            JOIN pg_class i ON i.oid = ix.indexrelid
+            JOIN pg_namespace n ON n.oid = t.relnamespace
+            JOIN pg_am am ON am.oid = i.relam
+            JOIN pg_attribute a ON a.attrelid = t.oid AND array_position(string_to_array(ix.indkey::text, ' '), a.attnum::text) IS NOT NULL
+        WHERE t.relname = @table AND n.nspname = current_schema()"#
+        .to_string(),
+        DBDialect::Unknown => not_support_sql(dialect, conn, "get table index"),
     };
     NewQuery {
         name: "table_index".to_string(),
@@ -119,6 +161,7 @@ pub fn table_index_query(dialect: &DBDialect, conn: &str) -> NewQuery {
             sql,
             path: format!("{conn}/__meta/table_index"),
             tags: meta_tags(),
+            ..Default::default()
         },
     }
 }
@@ -126,19 +169,34 @@ pub fn table_index_query(dialect: &DBDialect, conn: &str) -> NewQuery {
 /// list table columns query params
 pub fn table_column_query(dialect: &DBDialect, conn: &str) -> NewQuery {
     let sql = match dialect {
-        DBDialect::Mysql => format!(
-            r#"--? table: str // 表名称
+        DBDialect::Mysql => r#"--? table: str // 表名称
         select
             TABLE_SCHEMA AS `db`, COLUMN_NAME AS `column_name`, COLUMN_DEFAULT AS `default_value`, IS_NULLABLE AS `is_nullable`, DATA_TYPE AS `type`, COLUMN_KEY AS `pk`
         from information_schema.columns
         where table_name = @table AND `TABLE_SCHEMA` = DATABASE() "#
-        ),
-        DBDialect::Sqlite => format!(
-            r#"--? table: str // 表名称
-        SELECT `name` AS `column_name`, `dflt_value` AS `default_value`, `notnull` AS `is_nullable`, `type`, `pk`
-        FROM pragma_table_info(@table)"#
-        ),
-        DBDialect::Unknown => not_support_sql(conn, "get table columns"),
+        .to_string(),
+        DBDialect::Sqlite => r#"--? table: str // 表名称
+        SELECT `name` AS `column_name`, `dflt_value` AS `default_value`, `notnull` AS `is_nullable`, `type`, `pk`,
+            CASE `hidden` WHEN 2 THEN 'virtual' WHEN 3 THEN 'stored' ELSE NULL END AS `generated`
+        FROM pragma_table_xinfo(@table)
+        WHERE `hidden` != 1"#
+        .to_string(),
+        DBDialect::Postgres => r#"--? table: str // 表名称
+        SELECT
+            c.table_schema AS "db", c.column_name AS "column_name", c.column_default AS "default_value", c.is_nullable AS "is_nullable", c.data_type AS "type",
+            CASE WHEN EXISTS (
+                SELECT 1
+                FROM information_schema.table_constraints tc
+                    JOIN information_schema.key_column_usage kcu
+                        ON kcu.constraint_name = tc.constraint_name AND kcu.constraint_schema = tc.constraint_schema
+                WHERE tc.constraint_type = 'PRIMARY KEY' AND kcu.table_schema = c.table_schema
+                    AND kcu.table_name = c.table_name AND kcu.column_name = c.column_name
+            ) THEN 'PRI' ELSE '' END AS "pk"
+        FROM information_schema.columns c
+        WHERE c.table_name = @table AND c.table_schema = current_schema()
+        ORDER BY c.ordinal_position"#
+        .to_string(),
+        DBDialect::Unknown => not_support_sql(dialect, conn, "get table columns"),
     };
     NewQuery {
         name: "table_column".to_string(),
@@ -149,25 +207,34 @@ pub fn table_column_query(dialect: &DBDialect, conn: &str) -> NewQuery {
             sql,
             path: format!("{conn}/__meta/table_column"),
             tags: meta_tags(),
+            ..Default::default()
         },
     }
 }
 
 pub fn table_fk_query(dialect: &DBDialect, conn: &str) -> NewQuery {
     let sql = match dialect {
-        DBDialect::Mysql => format!(
-            r#"--? table: str // 表名称
+        DBDialect::Mysql => r#"--? table: str // 表名称
         SELECT
             CONSTRAINT_SCHEMA AS `db`, CONSTRAINT_NAME AS `name`, UPDATE_RULE as `update_rule`, DELETE_RULE as `delete_rule`, TABLE_NAME as `table`, REFERENCED_TABLE_NAME as `referenced_table`
         FROM information_schema.REFERENTIAL_CONSTRAINTS
         WHERE `db` = DATABASE() AND `TABLE_NAME` = @table"#
-        ),
-        DBDialect::Sqlite => format!(
-            r#"--? table: str // 表名称
+        .to_string(),
+        DBDialect::Sqlite => r#"--? table: str // 表名称
         SELECT `from` AS `name`, @table AS `table`, `table` AS `referenced_table`
         FROM pragma_foreign_key_list(@table)"#
-        ),
-        DBDialect::Unknown => not_support_sql(conn, "get table foreign key"),
+        .to_string(),
+        DBDialect::Postgres => r#"--? table: str // 表名称
+        SELECT
+            rc.constraint_schema AS "db", rc.constraint_name AS "name", rc.update_rule AS "update_rule", rc.delete_rule AS "delete_rule", tc.table_name AS "table", ccu.table_name AS "referenced_table"
+        FROM information_schema.referential_constraints rc
+            JOIN information_schema.table_constraints tc
+                ON tc.constraint_name = rc.constraint_name AND tc.constraint_schema = rc.constraint_schema
+            JOIN information_schema.constraint_table_usage ccu
+                ON ccu.constraint_name = rc.constraint_name AND ccu.constraint_schema = rc.constraint_schema
+        WHERE rc.constraint_schema = current_schema() AND tc.table_name = @table"#
+        .to_string(),
+        DBDialect::Unknown => not_support_sql(dialect, conn, "get table foreign key"),
     };
     NewQuery {
         name: "table_fk".to_string(),
@@ -178,28 +245,37 @@ pub fn table_fk_query(dialect: &DBDialect, conn: &str) -> NewQuery {
             sql,
             path: format!("{conn}/__meta/table_fk"),
             tags: meta_tags(),
+            ..Default::default()
         },
     }
 }
 
 pub fn all_fk_query(dialect: &DBDialect, conn: &str) -> NewQuery {
     let sql = match dialect {
-        DBDialect::Mysql => format!(
-            r#"select
+        DBDialect::Mysql => r#"select
         CONSTRAINT_SCHEMA AS `db`, CONSTRAINT_NAME AS `name`, UPDATE_RULE as `update_rule`, DELETE_RULE as `delete_rule`, TABLE_NAME as `table`, REFERENCED_TABLE_NAME as `referenced_table`
         from information_schema.REFERENTIAL_CONSTRAINTS
         WHERE `CONSTRAINT_SCHEMA` = DATABASE()"#
-        ),
-        DBDialect::Sqlite => format!(
-            r#"SELECT
+        .to_string(),
+        DBDialect::Sqlite => r#"SELECT
         p.`from` as `name`, m.name AS `table`, p.`table` AS `referenced_table`
     FROM
         sqlite_master m
         JOIN pragma_foreign_key_list(m.name) p ON m.name != p.`table`
     WHERE m.type = 'table'
     ORDER BY m.name"#
-        ),
-        DBDialect::Unknown => not_support_sql(conn, "get all foreign keys"),
+        .to_string(),
+        DBDialect::Postgres => r#"SELECT
+        rc.constraint_schema AS "db", rc.constraint_name AS "name", rc.update_rule AS "update_rule", rc.delete_rule AS "delete_rule", tc.table_name AS "table", ccu.table_name AS "referenced_table"
+        FROM information_schema.referential_constraints rc
+            JOIN information_schema.table_constraints tc
+                ON tc.constraint_name = rc.constraint_name AND tc.constraint_schema = rc.constraint_schema
+            JOIN information_schema.constraint_table_usage ccu
+                ON ccu.constraint_name = rc.constraint_name AND ccu.constraint_schema = rc.constraint_schema
+        WHERE rc.constraint_schema = current_schema()
+        ORDER BY tc.table_name"#
+        .to_string(),
+        DBDialect::Unknown => not_support_sql(dialect, conn, "get all foreign keys"),
     };
     NewQuery {
         name: "fk".to_string(),
@@ -210,6 +286,7 @@ pub fn all_fk_query(dialect: &DBDialect, conn: &str) -> NewQuery {
             sql,
             path: format!("{conn}/__meta/fk"),
             tags: meta_tags(),
+            ..Default::default()
         },
     }
 }
@@ -234,7 +311,7 @@ pub async fn add_conn(client: &Client, base_url: &str, name: &str, db_uri: &str)
         .send()
         .await?;
     let dialect = DBDialect::detect(db_uri);
-    let _r = add_query(
+    add_query(
         client,
         base_url,
         vec![
@@ -246,7 +323,8 @@ pub async fn add_conn(client: &Client, base_url: &str, name: &str, db_uri: &str)
             all_fk_query(&dialect, name),
         ],
     )
-    .await?;
+    .await?
+    .error_for_status()?;
     Ok(resp)
 }
 
@@ -306,6 +384,239 @@ mod tests {
     use super::*;
     const BASE: &str = "http://localhost:8888";
 
+    #[test]
+    fn detect_dialect() {
+        assert!(matches!(
+            DBDialect::detect("mysql://u@db/app"),
+            DBDialect::Mysql
+        ));
+        assert!(matches!(
+            DBDialect::detect("sqlite:local.db"),
+            DBDialect::Sqlite
+        ));
+        assert!(matches!(
+            DBDialect::detect("postgres://u@db/app"),
+            DBDialect::Postgres
+        ));
+        assert!(matches!(
+            DBDialect::detect("postgresql://u@db/app"),
+            DBDialect::Postgres
+        ));
+        assert!(matches!(
+            DBDialect::detect("mssql://u@db"),
+            DBDialect::Unknown
+        ));
+    }
+
+    #[test]
+    fn meta_queries_parse() {
+        let builders: [fn(&DBDialect, &str) -> NewQuery; 6] = [
+            schema_query,
+            tables_query,
+            table_index_query,
+            table_column_query,
+            table_fk_query,
+            all_fk_query,
+        ];
+        for (db, dialect) in [
+            (DBDialect::Mysql, Dialect::Mysql),
+            (DBDialect::Sqlite, Dialect::Sqlite),
+            (DBDialect::Postgres, Dialect::Postgres),
+        ] {
+            for build in builders {
+                let new_query = build(&db, "local");
+                let stmts = new_query
+                    .query
+                    .read_sql(&dialect)
+                    .and_then(|prog| prog.sample_statements(dialect.sqlparser_dialect().as_ref()))
+                    .unwrap_or_else(|e| panic!("{:?} {}: {}", db, new_query.name, e));
+                assert_eq!(stmts.len(), 1, "{:?} {}", db, new_query.name);
+            }
+        }
+    }
+
+    /// output column names of meta query, aliases or plain column names
+    fn meta_columns(db: &DBDialect, dialect: Dialect, new_query: &NewQuery) -> Vec<String> {
+        use sqlparser::ast::{Expr, SelectItem, SetExpr, Statement};
+        let stmts = new_query
+            .query
+            .read_sql(&dialect)
+            .and_then(|prog| prog.sample_statements(dialect.sqlparser_dialect().as_ref()))
+            .unwrap_or_else(|e| panic!("{:?} {}: {}", db, new_query.name, e));
+        let select = match &stmts[0] {
+            Statement::Query(query) => match &query.body {
+                SetExpr::Select(select) => select,
+                body => panic!("{:?} {}: not a select {}", db, new_query.name, body),
+            },
+            stmt => panic!("{:?} {}: not a query {}", db, new_query.name, stmt),
+        };
+        select
+            .projection
+            .iter()
+            .map(|item| match item {
+                SelectItem::ExprWithAlias { alias, .. } => alias.value.clone(),
+                SelectItem::UnnamedExpr(Expr::Identifier(ident)) => ident.value.clone(),
+                SelectItem::UnnamedExpr(Expr::CompoundIdentifier(idents)) => {
+                    idents.last().unwrap().value.clone()
+                }
+                item => panic!("{:?} {}: unnamed column {}", db, new_query.name, item),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn postgres_meta_columns_match_mysql() {
+        let builders: [fn(&DBDialect, &str) -> NewQuery; 6] = [
+            schema_query,
+            tables_query,
+            table_index_query,
+            table_column_query,
+            table_fk_query,
+            all_fk_query,
+        ];
+        for build in builders {
+            let mysql = build(&DBDialect::Mysql, "local");
+            let pg = build(&DBDialect::Postgres, "local");
+            assert_eq!(
+                meta_columns(&DBDialect::Postgres, Dialect::Postgres, &pg),
+                meta_columns(&DBDialect::Mysql, Dialect::Mysql, &mysql),
+                "{}",
+                pg.name
+            );
+        }
+    }
+
+    /// run postgres meta queries on a server, e.g.
+    /// `PSQL_TEST_PG=postgres://postgres@127.0.0.1/postgres`, skipped if not set
+    #[tokio::test]
+    async fn postgres_meta_queries() {
+        let uri = match std::env::var("PSQL_TEST_PG") {
+            Ok(uri) => uri,
+            Err(_) => return,
+        };
+        let schema = format!("psql_api_{}", std::process::id());
+        let setup = format!(
+            "CREATE SCHEMA {schema};
+            CREATE TABLE {schema}.users (id int PRIMARY KEY, name text NOT NULL DEFAULT '');
+            CREATE TABLE {schema}.orders (id int PRIMARY KEY, user_id int REFERENCES {schema}.users (id) ON DELETE CASCADE);
+            CREATE INDEX orders_user ON {schema}.orders (user_id);"
+        );
+        std::process::Command::new("psql")
+            .args([uri.as_str(), "-v", "ON_ERROR_STOP=1", "-qc", setup.as_str()])
+            .status()
+            .map(|status| assert!(status.success()))
+            .unwrap();
+        let sep = if uri.contains('?') { '&' } else { '?' };
+        let conn_uri = format!("{uri}{sep}options=-c%20search_path%3D{schema}");
+        let (base, stop) = spawn_server().await;
+        let client = reqwest::Client::new();
+        let resp = add_conn(&client, &base, "pg", &conn_uri).await.unwrap();
+        assert_eq!(resp.status(), reqwest::StatusCode::CREATED);
+        let rows = |resp: Resp| async move {
+            let resp = resp.unwrap();
+            assert_eq!(resp.status(), reqwest::StatusCode::OK);
+            resp.json::<Vec<serde_json::Value>>().await.unwrap()
+        };
+
+        let mut tables = rows(db_tables(&client, &base, "pg").await).await;
+        tables.sort_by_key(|t| t["name"].to_string());
+        assert_eq!(
+            tables,
+            [
+                json!({"name": "orders", "engine": null}),
+                json!({"name": "users", "engine": null})
+            ]
+        );
+        let columns = rows(table_columns(&client, &base, "pg", "users").await).await;
+        assert_eq!(columns.len(), 2);
+        assert_eq!(columns[0]["db"], schema.as_str());
+        assert_eq!(columns[0]["column_name"], "id");
+        assert_eq!(columns[0]["is_nullable"], "NO");
+        assert_eq!(columns[0]["type"], "integer");
+        assert_eq!(columns[0]["pk"], "PRI");
+        assert_eq!(columns[1]["column_name"], "name");
+        assert_eq!(columns[1]["default_value"], "''::text");
+        assert_eq!(columns[1]["pk"], "");
+        let mut indexes = rows(table_indexes(&client, &base, "pg", "orders").await).await;
+        indexes.sort_by_key(|i| i["name"].to_string());
+        assert_eq!(indexes.len(), 2);
+        assert_eq!(indexes[0]["name"], "orders_pkey");
+        assert_eq!(indexes[0]["can_duplicate"], false);
+        assert_eq!(indexes[1]["name"], "orders_user");
+        assert_eq!(indexes[1]["column_name"], "user_id");
+        assert_eq!(indexes[1]["can_duplicate"], true);
+        assert_eq!(indexes[1]["type"], "btree");
+        let fks = rows(table_fk(&client, &base, "pg", "orders").await).await;
+        assert_eq!(fks.len(), 1);
+        assert_eq!(fks[0]["table"], "orders");
+        assert_eq!(fks[0]["referenced_table"], "users");
+        assert_eq!(fks[0]["delete_rule"], "CASCADE");
+        assert_eq!(rows(db_fk(&client, &base, "pg").await).await, fks);
+
+        stop.send(()).unwrap();
+        let drop = format!("DROP SCHEMA {schema} CASCADE");
+        std::process::Command::new("psql")
+            .args([uri.as_str(), "-qc", drop.as_str()])
+            .status()
+            .ok();
+    }
+
+    /// serve an empty plan on a free local port until `stop` is sent
+    async fn spawn_server() -> (String, tokio::sync::oneshot::Sender<()>) {
+        use psql::http::{run_dynamic_http_until, Plan};
+        use std::collections::HashMap;
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let plan = Plan {
+            address: vec![([127, 0, 0, 1], port).into()],
+            ..Default::default()
+        };
+        let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+        tokio::spawn(run_dynamic_http_until(
+            plan,
+            HashMap::new(),
+            HashMap::new(),
+            HashMap::new(),
+            None,
+            async {
+                stopped.await.ok();
+            },
+        ));
+        let base = format!("http://127.0.0.1:{}", port);
+        for _ in 0..50 {
+            if reqwest::get(format!("{base}/index")).await.is_ok() {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+        (base, stop)
+    }
+
+    #[tokio::test]
+    async fn add_conn_end_to_end() {
+        let (base, stop) = spawn_server().await;
+        let db = std::env::temp_dir().join(format!("psql-api-{}.db", std::process::id()));
+        let uri = format!("sqlite:{}?mode=rwc", db.display());
+        let client = reqwest::Client::new();
+
+        let resp = add_conn(&client, &base, "local", &uri).await.unwrap();
+        assert_eq!(resp.status(), reqwest::StatusCode::CREATED);
+        let resp = db_tables(&client, &base, "local").await.unwrap();
+        assert_eq!(resp.status(), reqwest::StatusCode::OK);
+        let tables = resp.json::<serde_json::Value>().await.unwrap();
+        assert!(tables.is_array(), "{}", tables);
+        let resp = table_columns(&client, &base, "local", "missing")
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), reqwest::StatusCode::OK);
+
+        stop.send(()).unwrap();
+        std::fs::remove_file(db).ok();
+    }
+
     #[tokio::test]
     async fn add() {
         let client = reqwest::Client::new();
@@ -11,11 +11,29 @@ fn main() {
 select name from t where age=@age and name like @pattern and addr in @addrs and scores in @pp
 ";
     pretty_env_logger::init();
+    let args = std::env::args().collect::<Vec<String>>();
+    if args.get(1).map(String::as_str) == Some("serve") {
+        #[cfg(feature = "http")]
+        serve::run(&args[2..]);
+        #[cfg(not(feature = "http"))]
+        {
+            println!("serve mode requires http feature");
+            exit(1);
+        }
+    }
     let dialect = MySqlDialect {};
     let prog = Program::parse(&dialect, sql).unwrap();
     let mut opts = getopts::Options::new();
     prog.add_options(&mut opts);
-    match prog.get_matches(&opts, &std::env::args().collect::<Vec<String>>()) {
+    if args.iter().any(|arg| arg == "--dump-params") {
+        println!("{:#}", prog.params_json());
+        exit(0);
+    }
+    if args.iter().any(|arg| arg == "-h" || arg == "--help") {
+        println!("{}", prog.usage_string(&opts));
+        exit(0);
+    }
+    match prog.get_matches(&opts, &args) {
         Ok(values) => match prog.render(&dialect, &values) {
             Ok(stmts) => {
                 println!(
@@ -33,8 +51,116 @@ select name from t where age=@age and name like @pattern and addr in @addrs and
         },
         Err(e) => {
             println!("{}\n", e);
-            println!("{}", opts.usage("PSQL"));
+            println!("{}", prog.usage_string(&opts));
+            exit(1);
+        }
+    }
+}
+
+/// serve a single sql file as one endpoint, without plan.toml
+#[cfg(feature = "http")]
+mod serve {
+    use std::{net::SocketAddr, path::Path, process::exit};
+
+    use psql::http::{
+        plan::{Dialect, Method, Query},
+        run_dynamic_http, Plan,
+    };
+
+    /// usage: `cli serve <file.sql> --conn <uri> [--path <path>] [--method <method>] [--addr <addr>]`
+    pub fn run(args: &[String]) -> ! {
+        let mut opts = getopts::Options::new();
+        opts.reqopt("", "conn", "database uri", "URI");
+        opts.optopt("", "path", "api path, default to sql file name", "PATH");
+        opts.optopt("", "method", "http method, default to GET", "METHOD");
+        opts.optopt(
+            "",
+            "addr",
+            "bind address, default to 127.0.0.1:12345",
+            "ADDR",
+        );
+        let usage = opts.usage("Usage: cli serve <file.sql> [options]");
+        let matches = match opts.parse(args) {
+            Ok(matches) if matches.free.len() == 1 => matches,
+            Ok(_) => {
+                println!("{}", usage);
+                exit(1);
+            }
+            Err(e) => {
+                println!("{}\n\n{}", e, usage);
+                exit(1);
+            }
+        };
+        let file = matches.free[0].clone();
+        let uri = matches.opt_str("conn").unwrap();
+        let path = matches.opt_str("path").unwrap_or_else(|| {
+            Path::new(&file)
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().to_string())
+                .unwrap_or_else(|| "query".to_string())
+        });
+        let method = match matches.opt_str("method") {
+            Some(method) => match serde_json::from_value::<Method>(method.to_uppercase().into()) {
+                Ok(method) => method,
+                Err(_) => {
+                    println!("invalid method {}", method);
+                    exit(1);
+                }
+            },
+            None => Method::Get,
+        };
+        let address = match matches
+            .opt_str("addr")
+            .map(|addr| addr.parse::<SocketAddr>())
+        {
+            Some(Ok(addr)) => vec![addr],
+            Some(Err(e)) => {
+                println!("invalid addr {}", e);
+                exit(1);
+            }
+            None => Plan::default().address,
+        };
+        let query = Query {
+            conn: "default".to_string(),
+            method,
+            summary: Some(file.clone()),
+            sql: format!("@{}", file),
+            path,
+            ..Default::default()
+        };
+        let dialect = Dialect::from_uri(&uri);
+        if let Err(e) = query.read_sql(&dialect) {
+            println!("{}", e);
             exit(1);
         }
+        let mut plan = Plan {
+            title: file,
+            address,
+            ..Default::default()
+        };
+        match dialect {
+            Dialect::Mysql => plan.mysql_conns.insert("default".to_string(), uri),
+            Dialect::Sqlite => plan.sqlite_conns.insert("default".to_string(), uri),
+            Dialect::Postgres => plan.pg_conns.insert("default".to_string(), uri),
+        };
+        plan.queries.insert("query".to_string(), query);
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let code = rt.block_on(async move {
+            match plan.create_connections().await {
+                Ok((mysql_conns, sqlite_conns, pg_conns)) => {
+                    run_dynamic_http(plan, mysql_conns, sqlite_conns, pg_conns, None)
+                        .await
+                        .map_or(1, |_| 0)
+                }
+                Err(e) => {
+                    println!("{}", e);
+                    1
+                }
+            }
+        });
+        exit(code)
     }
 }
@@ -0,0 +1,39 @@
+//! embed psql server in another program, e.g. a test, along with custom routes
+use psql::http::{spawn_dynamic_http, Plan};
+use warp::{Filter, Reply};
+
+#[tokio::main]
+async fn main() {
+    pretty_env_logger::init();
+    let plan: Plan = toml::from_str(
+        r#"
+title = "embed"
+address = ["127.0.0.1:0"]
+[sqlite_conns]
+local = "sqlite::memory:"
+[queries.now]
+conn = "local"
+sql = "select datetime('now') as now"
+path = "now"
+"#,
+    )
+    .unwrap();
+    let (mysql_conns, sqlite_conns, pg_conns) = plan.create_connections().await.unwrap();
+    let extra = warp::path("ping")
+        .map(|| Box::new("pong") as Box<dyn Reply>)
+        .boxed();
+    let server =
+        spawn_dynamic_http(plan, mysql_conns, sqlite_conns, pg_conns, Some(extra)).unwrap();
+    let addr = server.addrs()[0];
+    for path in ["ping", "api/now"] {
+        let body = reqwest::get(format!("http://{}/{}", addr, path))
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        println!("{}: {}", path, body);
+    }
+    // pools are closed when server stops, dropping `server` also stops it
+    server.shutdown().await;
+}
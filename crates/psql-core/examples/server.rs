@@ -1,21 +1,24 @@
-use std::{fs::File, io::Read, path::PathBuf, process::exit};
+use std::process::exit;
 
-use psql::http::{run_dynamic_http, Plan};
+use psql::http::{run_dynamic_http, typescript, Plan};
 use schemars::schema_for;
 use structopt::StructOpt;
 
 /// PSQL http service demo
 #[derive(Clone, StructOpt)]
 struct Args {
-    /// plan.toml file path
+    /// plan.toml file path, `-` to read from stdin or a http(s) url
     #[structopt(short, long, default_value = "plan.toml")]
-    plan: PathBuf,
+    plan: String,
     /// print plan.toml json schema and exit
     #[structopt(short, long)]
     show_schema: bool,
     /// print generated openapi json and exit
     #[structopt(short = "o", long = "show_doc")]
     show_openapi_doc: bool,
+    /// print generated typescript types of queries and exit
+    #[structopt(short = "t", long = "show_ts")]
+    show_typescript: bool,
 }
 
 #[tokio::main]
@@ -27,40 +30,46 @@ async fn main() -> Result<(), ()> {
         println!("{}", serde_json::to_string_pretty(&schema).unwrap());
         std::process::exit(0);
     }
-    match File::open(&args.plan) {
-        Ok(mut file) => {
-            let mut content = String::new();
-            match file.read_to_string(&mut content) {
-                Ok(_) => match toml::from_str::<Plan>(&content) {
-                    Ok(plan) => {
-                        let doc = plan.openapi_doc();
-                        if args.show_openapi_doc {
-                            println!("{}", serde_json::to_string_pretty(&doc).unwrap());
-                            std::process::exit(0);
-                        }
-                        match plan.create_connections().await {
-                            Ok((mysql_conns, sqlite_conns)) => {
-                                run_dynamic_http(plan, mysql_conns, sqlite_conns).await
-                            }
-                            Err(e) => {
-                                println!("{}", e);
-                                std::process::exit(1);
-                            }
-                        }
+    match Plan::load(&args.plan).await {
+        Ok(mut plan) => {
+            if let Err(problems) = plan.validate() {
+                println!("plan {} has {} problem(s):", args.plan, problems.len());
+                for (name, e) in problems {
+                    println!("  query {}: {}", name, e);
+                }
+                exit(1);
+            }
+            let doc = plan.openapi_doc();
+            if args.show_openapi_doc {
+                println!("{}", serde_json::to_string_pretty(&doc).unwrap());
+                std::process::exit(0);
+            }
+            if args.show_typescript {
+                match typescript::generate(&plan) {
+                    Ok(ts) => {
+                        print!("{}", ts);
+                        exit(0);
                     }
                     Err(e) => {
-                        println!("invalid config file {:#?}", e);
+                        println!("{}", e);
                         exit(1);
                     }
-                },
+                }
+            }
+            match plan.create_connections().await {
+                Ok((mysql_conns, sqlite_conns, pg_conns)) => {
+                    plan.load_star_columns(&mysql_conns, &sqlite_conns, &pg_conns)
+                        .await;
+                    run_dynamic_http(plan, mysql_conns, sqlite_conns, pg_conns, None).await
+                }
                 Err(e) => {
-                    println!("{:#?}", e);
-                    exit(1);
+                    println!("{}", e);
+                    std::process::exit(1);
                 }
             }
         }
         Err(e) => {
-            println!("{:#}", e);
+            println!("{}", e);
             exit(1);
         }
     }
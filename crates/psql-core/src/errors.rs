@@ -13,15 +13,43 @@ pub enum PSqlError {
     UnusedParams(HashSet<String>),
     #[error("missing params {0:?}")]
     MissingParams(HashSet<String>),
+    #[error("too many params, at most {0} allowed")]
+    TooManyParams(usize),
     #[error("duplicated param {0}")]
     DuplicatedParam(String),
+    #[error("param {0} is used as both single value and array")]
+    ConflictingUsage(String),
     #[error("missing context value {0}")]
     MissingContextValue(String),
     #[error("{0}")]
     ParseError(sqlparser::parser::ParserError),
+    #[error("comment `--{0}` looks like a param definition, remove spaces before `?`")]
+    MisplacedParamMark(String),
     #[error("param line parse error {0}")]
     ParamParseError(String),
-    #[error("invalid arg value {0} for {1:?}")]
+    #[error("param {0} refers to unknown param {1}")]
+    UnknownParamRef(String, String),
+    #[error("param {0} requires {1}")]
+    ParamRequires(String, String),
+    #[error("param {0} conflicts with {1}")]
+    ParamConflicts(String, String),
+    #[error("default of param {0} references itself through other params")]
+    DefaultCycle(String),
+    #[error("invalid default of param {0}: {1}")]
+    InvalidDefault(String, String),
+    #[error("invalid pattern of param {0}: {1}")]
+    InvalidPattern(String, String),
+    #[error("invalid value {0} for {1}, expect one of {2}")]
+    InvalidChoice(String, String, String),
+    #[error("invalid choices {0} of param {1}, expect a non-empty list like {{a, b}} of values matching param type")]
+    InvalidChoices(String, String),
+    #[error("invalid sort option of param {0}: {1}")]
+    InvalidSortOption(String, String),
+    #[error("{0} of param {1} is less than min {2}")]
+    BelowMin(f64, String, f64),
+    #[error("{0} of param {1} is greater than max {2}")]
+    AboveMax(f64, String, f64),
+    #[error("invalid arg value {0} for {1:?}{}", .1.format_hint())]
     InvalidArgValue(String, InnerTy),
     #[error("{0:?}")]
     TokenizeError(sqlparser::tokenizer::TokenizerError),
@@ -29,4 +57,10 @@ pub enum PSqlError {
     ExpectEndOfStatement(Token),
     #[error("read sql file {0} failed {1}")]
     ReadSQLError(String, String),
+    #[error("read plan {0} failed {1}")]
+    ReadPlanError(String, String),
+    #[error("invalid plan {0}: {1}")]
+    InvalidPlan(String, String),
+    #[error("unknown connection {0}")]
+    UnknownConn(String),
 }
@@ -0,0 +1,162 @@
+//! query access audit log, written as json lines
+use std::{
+    collections::{HashMap, HashSet},
+    fs::OpenOptions,
+    io::Write,
+    sync::{Arc, Mutex},
+};
+
+use serde::Serialize;
+
+use super::{plan::Audit, RequestInfo};
+use crate::parser::ParamValue;
+
+pub(super) const REDACTED: &str = "***";
+
+#[derive(Debug, Serialize)]
+struct AuditEntry<'a> {
+    timestamp: String,
+    ip: Option<String>,
+    query: &'a str,
+    /// label of query connection
+    conn: &'a str,
+    params: serde_json::Map<String, serde_json::Value>,
+    rows: Option<usize>,
+    status: u16,
+}
+
+/// audit sink, separated from application log, it's written on blocking threads so file
+/// I/O doesn't stall async handlers
+pub struct AuditLog {
+    sink: Arc<Mutex<Box<dyn Write + Send>>>,
+    redact: HashSet<String>,
+}
+
+impl AuditLog {
+    /// open audit sink, `-` stands for stdout, other value is a file path opened in append mode
+    pub fn open(config: &Audit) -> std::io::Result<Self> {
+        let sink: Box<dyn Write + Send> = if config.sink == "-" {
+            Box::new(std::io::stdout())
+        } else {
+            Box::new(
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&config.sink)?,
+            )
+        };
+        Ok(Self {
+            sink: Arc::new(Mutex::new(sink)),
+            redact: config.redact.iter().cloned().collect(),
+        })
+    }
+
+    /// write one entry, failure is logged but never fails the request
+    pub(crate) async fn record(
+        &self,
+        query: &str,
+        conn: &str,
+        req: &RequestInfo,
+        context: &HashMap<String, ParamValue>,
+        rows: Option<usize>,
+        status: u16,
+    ) {
+        let params = context
+            .iter()
+            .map(|(name, val)| {
+                let val = if self.redact.contains(name) {
+                    serde_json::Value::String(REDACTED.to_string())
+                } else {
+                    val.clone().into()
+                };
+                (name.clone(), val)
+            })
+            .collect();
+        let entry = AuditEntry {
+            timestamp: chrono::Utc::now().to_rfc3339(),
+            ip: req.remote.map(|addr| addr.ip().to_string()),
+            query,
+            conn,
+            params,
+            rows,
+            status,
+        };
+        let mut line = match serde_json::to_vec(&entry) {
+            Ok(line) => line,
+            Err(e) => {
+                log::error!("write audit log failed {}", e);
+                return;
+            }
+        };
+        line.push(b'\n');
+        let sink = self.sink.clone();
+        let written = tokio::task::spawn_blocking(move || {
+            let mut sink = match sink.lock() {
+                Ok(sink) => sink,
+                Err(poisoned) => poisoned.into_inner(),
+            };
+            sink.write_all(&line).and_then(|_| sink.flush())
+        })
+        .await
+        .map_err(std::io::Error::from)
+        .and_then(|written| written);
+        if let Err(e) = written {
+            log::error!("write audit log failed {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn record_entries() {
+        let path = std::env::temp_dir().join(format!("psql-audit-{}.jsonl", std::process::id()));
+        let config = Audit {
+            sink: path.to_string_lossy().into_owned(),
+            redact: vec!["password".to_string()],
+        };
+        let audit = AuditLog::open(&config).unwrap();
+        let req = RequestInfo {
+            path: "/api/login".to_string(),
+            qs: String::new(),
+            headers: Default::default(),
+            remote: Some("10.0.0.1:4000".parse().unwrap()),
+        };
+        let context = HashMap::from([
+            ("user".to_string(), ParamValue::Str("bob".to_string())),
+            (
+                "password".to_string(),
+                ParamValue::Str("s3cret".to_string()),
+            ),
+        ]);
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            audit
+                .record("login", "main", &req, &context, Some(1), 200)
+                .await;
+            audit
+                .record("login", "main", &req, &context, None, 500)
+                .await;
+        });
+        let written = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+        assert!(!written.contains("s3cret"), "{}", written);
+        let entries = written
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0]["query"], "login");
+        assert_eq!(entries[0]["conn"], "main");
+        assert_eq!(entries[0]["ip"], "10.0.0.1");
+        assert_eq!(entries[0]["params"]["user"], "bob");
+        assert_eq!(entries[0]["params"]["password"], REDACTED);
+        assert_eq!(entries[0]["rows"], 1);
+        assert_eq!(entries[1]["status"], 500);
+    }
+}
@@ -0,0 +1,215 @@
+use sqlx::mysql::MySqlDatabaseError;
+use warp::{http::StatusCode, reply::Response, Rejection, Reply};
+
+use super::{json_reply, ApiMsg};
+
+/// http layer error, rendered as [`ApiMsg`] json with matching status code
+///
+/// handlers return it as rejection and [`handle_rejection`] renders it,
+/// handlers which need the response itself, e.g. to audit it, render it by [`Reply`]
+#[derive(Debug, Clone)]
+pub enum ApiError {
+    /// invalid request params, body or sql
+    BadRequest(String),
+    /// missing or invalid api key
+    Unauthorized(String),
+    NotFound(String),
+    /// request body exceeds limit bytes
+    PayloadTooLarge(u64),
+    /// request body content type is not accepted
+    UnsupportedMediaType(String),
+    /// database is unavailable or busy, client may retry after some seconds
+    Unavailable {
+        msg: String,
+        retry_after: Option<u64>,
+    },
+    /// sql is rejected by database as invalid, e.g. syntax error of its dialect
+    Unprocessable(String),
+    /// query doesn't finish in its timeout milliseconds
+    Timeout(u64),
+    /// statement fails in database, or response can't be built, e.g. a column value fails to decode
+    Internal(String),
+}
+
+impl warp::reject::Reject for ApiError {}
+
+impl ApiError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ApiError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            ApiError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            ApiError::UnsupportedMediaType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            ApiError::Unavailable { .. } => StatusCode::SERVICE_UNAVAILABLE,
+            ApiError::Unprocessable(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            ApiError::Timeout(_) => StatusCode::GATEWAY_TIMEOUT,
+            ApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// error of statement failed in database, `422` for syntax errors, `500` otherwise
+    pub fn database(e: &sqlx::Error, msg: String) -> ApiError {
+        let syntax = match e {
+            sqlx::Error::Database(db) => match db.try_downcast_ref::<MySqlDatabaseError>() {
+                // ER_PARSE_ERROR
+                Some(mysql) => mysql.number() == 1064,
+                None => db.message().contains("syntax error"),
+            },
+            _ => false,
+        };
+        if syntax {
+            ApiError::Unprocessable(msg)
+        } else {
+            ApiError::Internal(msg)
+        }
+    }
+
+    pub fn msg(&self) -> String {
+        match self {
+            ApiError::BadRequest(msg)
+            | ApiError::Unauthorized(msg)
+            | ApiError::NotFound(msg)
+            | ApiError::UnsupportedMediaType(msg)
+            | ApiError::Unavailable { msg, .. }
+            | ApiError::Unprocessable(msg)
+            | ApiError::Internal(msg) => msg.clone(),
+            ApiError::PayloadTooLarge(limit) => format!("request body exceeds {} bytes", limit),
+            ApiError::Timeout(ms) => format!("query doesn't finish in {} ms", ms),
+        }
+    }
+
+    /// render error as json, indented if `pretty`
+    pub fn reply(&self, pretty: bool) -> Response {
+        let status = self.status();
+        let msg = ApiMsg {
+            msg: self.msg(),
+            code: status.as_u16(),
+        };
+        let mut resp = warp::reply::with_status(json_reply(&msg, pretty), status).into_response();
+        if let ApiError::Unavailable {
+            retry_after: Some(secs),
+            ..
+        } = self
+        {
+            resp.headers_mut().insert("retry-after", (*secs).into());
+        }
+        // tell client how long server waited, retry after as long in seconds
+        if let ApiError::Timeout(ms) = self {
+            resp.headers_mut().insert("x-timeout-ms", (*ms).into());
+            resp.headers_mut()
+                .insert("retry-after", ms.div_ceil(1000).max(1).into());
+        }
+        resp
+    }
+}
+
+impl Reply for ApiError {
+    fn into_response(self) -> Response {
+        self.reply(false)
+    }
+}
+
+/// render [`ApiError`] rejections, other rejections are passed on
+pub async fn handle_rejection(pretty: bool, err: Rejection) -> Result<Response, Rejection> {
+    match err.find::<ApiError>() {
+        Some(e) => Ok(e.reply(pretty)),
+        None => Err(err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn database_error_status() {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let (syntax, missing) = rt.block_on(async {
+            let pool = sqlx::SqlitePool::connect("sqlite::memory:").await.unwrap();
+            let syntax = sqlx::query("selec 1").execute(&pool).await.unwrap_err();
+            let missing = sqlx::query("select * from missing")
+                .execute(&pool)
+                .await
+                .unwrap_err();
+            (syntax, missing)
+        });
+        let syntax = ApiError::database(&syntax, "syntax".to_string());
+        let missing = ApiError::database(&missing, "missing".to_string());
+        assert_eq!(syntax.status().as_u16(), 422);
+        assert_eq!(missing.status().as_u16(), 500);
+        let timeout = ApiError::Timeout(1500).reply(false);
+        assert_eq!(timeout.status().as_u16(), 504);
+        assert_eq!(timeout.headers()["x-timeout-ms"], "1500");
+        assert_eq!(timeout.headers()["retry-after"], "2");
+        for err in [
+            syntax,
+            missing,
+            ApiError::BadRequest("bad".to_string()),
+            ApiError::Timeout(1500),
+        ] {
+            let resp = err.reply(false);
+            let status = resp.status().as_u16();
+            let body = rt
+                .block_on(warp::hyper::body::to_bytes(resp.into_body()))
+                .unwrap();
+            let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(body["code"], status);
+            assert_eq!(body["msg"], err.msg());
+        }
+    }
+
+    #[test]
+    fn rejection_mapping() {
+        let statuses = [
+            (ApiError::BadRequest("bad".to_string()), 400),
+            (ApiError::Unauthorized("key".to_string()), 401),
+            (ApiError::NotFound("none".to_string()), 404),
+            (ApiError::PayloadTooLarge(1024), 413),
+            (ApiError::UnsupportedMediaType("xml".to_string()), 415),
+            (ApiError::Unprocessable("syntax".to_string()), 422),
+            (ApiError::Internal("boom".to_string()), 500),
+            (
+                ApiError::Unavailable {
+                    msg: "busy".to_string(),
+                    retry_after: None,
+                },
+                503,
+            ),
+            (ApiError::Timeout(10), 504),
+        ];
+        for (err, status) in statuses {
+            assert_eq!(err.status().as_u16(), status, "{:?}", err);
+        }
+        assert_eq!(
+            ApiError::PayloadTooLarge(1024).msg(),
+            "request body exceeds 1024 bytes"
+        );
+        let busy = ApiError::Unavailable {
+            msg: "busy".to_string(),
+            retry_after: Some(3),
+        };
+        assert_eq!(busy.reply(false).headers()["retry-after"], "3");
+
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            // api errors are rendered with their status, pretty one is indented
+            let rejection = warp::reject::custom(ApiError::NotFound("no query".to_string()));
+            let resp = handle_rejection(true, rejection).await.unwrap();
+            assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+            let body = warp::hyper::body::to_bytes(resp.into_body()).await.unwrap();
+            let body = String::from_utf8_lossy(&body);
+            assert!(body.contains("\n  \"msg\": \"no query\""), "{}", body);
+            // other rejections are left to warp
+            assert!(handle_rejection(false, warp::reject::not_found())
+                .await
+                .is_err());
+        });
+    }
+}
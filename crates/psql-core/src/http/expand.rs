@@ -0,0 +1,293 @@
+//! expand `*` and `t.*` of select projection into explicit columns, which are
+//! read from live table schema when server starts
+//!
+//! only selects whose relations are all plain tables are expanded, `*` of
+//! subquery, table function or unknown table is kept as is. Columns shared by
+//! `USING` or `NATURAL` join are listed once by `*`, like databases do.
+
+use std::collections::{HashMap, HashSet};
+
+use sqlparser::ast::{
+    Expr, Ident, JoinConstraint, JoinOperator, ObjectName, Select, SelectItem, SetExpr, Statement,
+    TableFactor,
+};
+
+use super::output::ColumnDesc;
+
+/// columns of tables, keyed by table name as written in sql
+pub type TableColumns = HashMap<String, Vec<ColumnDesc>>;
+
+/// relation of select, table name and alias
+type Relation<'a> = (&'a ObjectName, Option<&'a Ident>);
+
+/// plain tables of select, `None` if any relation is not a plain table
+fn relations(select: &Select) -> Option<Vec<Relation<'_>>> {
+    select
+        .from
+        .iter()
+        .flat_map(|table| {
+            std::iter::once(&table.relation).chain(table.joins.iter().map(|join| &join.relation))
+        })
+        .map(|relation| match relation {
+            TableFactor::Table {
+                name, alias, args, ..
+            } if args.is_empty() => Some((name, alias.as_ref().map(|alias| &alias.name))),
+            _ => None,
+        })
+        .collect()
+}
+
+/// lowercased names of columns each relation shares with earlier ones of the same `FROM` item
+/// by `USING` or `NATURAL` join, in order of [`relations`]. `*` lists them only from the
+/// first relation having them
+fn join_columns(select: &Select, columns: &TableColumns) -> Vec<HashSet<String>> {
+    let names = |relation: &TableFactor| match relation {
+        TableFactor::Table { name, .. } => columns
+            .get(&name.to_string())
+            .map(|cols| cols.iter().map(|col| col.name.to_lowercase()).collect())
+            .unwrap_or_default(),
+        _ => HashSet::new(),
+    };
+    let mut shared = vec![];
+    for table in select.from.iter() {
+        let mut seen = names(&table.relation);
+        shared.push(HashSet::new());
+        for join in table.joins.iter() {
+            let right = names(&join.relation);
+            let constraint = match &join.join_operator {
+                JoinOperator::Inner(constraint)
+                | JoinOperator::LeftOuter(constraint)
+                | JoinOperator::RightOuter(constraint)
+                | JoinOperator::FullOuter(constraint) => Some(constraint),
+                _ => None,
+            };
+            shared.push(match constraint {
+                Some(JoinConstraint::Using(idents)) => idents
+                    .iter()
+                    .map(|ident| ident.value.to_lowercase())
+                    .collect(),
+                Some(JoinConstraint::Natural) => right.intersection(&seen).cloned().collect(),
+                _ => HashSet::new(),
+            });
+            seen.extend(right);
+        }
+    }
+    shared
+}
+
+fn has_star(select: &Select) -> bool {
+    select.projection.iter().any(|item| {
+        matches!(
+            item,
+            SelectItem::Wildcard | SelectItem::QualifiedWildcard(_)
+        )
+    })
+}
+
+/// selects of statement, including those of union and nested query body
+fn selects(stmt: &mut Statement) -> Vec<&mut Select> {
+    fn walk<'a>(body: &'a mut SetExpr, found: &mut Vec<&'a mut Select>) {
+        match body {
+            SetExpr::Select(select) => found.push(select),
+            SetExpr::Query(query) => walk(&mut query.body, found),
+            SetExpr::SetOperation { left, right, .. } => {
+                walk(left, found);
+                walk(right, found);
+            }
+            SetExpr::Values(_) | SetExpr::Insert(_) => {}
+        }
+    }
+    let mut found = vec![];
+    if let Statement::Query(query) = stmt {
+        walk(&mut query.body, &mut found);
+    }
+    found
+}
+
+/// names of tables whose columns are needed to expand `*` of statement
+pub fn star_tables(stmt: &Statement) -> Vec<String> {
+    let mut stmt = stmt.clone();
+    let mut seen = HashSet::new();
+    selects(&mut stmt)
+        .into_iter()
+        .filter(|select| has_star(select))
+        .filter_map(|select| relations(select))
+        .flatten()
+        .map(|(name, _)| name.to_string())
+        .filter(|name| seen.insert(name.clone()))
+        .collect()
+}
+
+/// whether `t.*` refers to relation, by alias or by table name
+fn refers_to(qualifier: &ObjectName, (name, alias): Relation) -> bool {
+    match (qualifier.0.as_slice(), alias) {
+        ([ident], Some(alias)) => ident.value == alias.value,
+        (_, Some(_)) => false,
+        ([ident], None) => name.0.last().is_some_and(|last| last.value == ident.value),
+        (_, None) => qualifier == name,
+    }
+}
+
+/// column expression, quoted with `quote` unless it's a plain identifier
+fn column_expr(qualifier: Option<Vec<Ident>>, column: &str, quote: char) -> Expr {
+    let plain = column
+        .chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && column
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_');
+    let ident = if plain {
+        Ident::new(column)
+    } else {
+        Ident::with_quote(quote, column)
+    };
+    match qualifier {
+        Some(mut idents) => {
+            idents.push(ident);
+            Expr::CompoundIdentifier(idents)
+        }
+        None => Expr::Identifier(ident),
+    }
+}
+
+/// projection with `*` replaced, `None` if some of them can't be expanded
+fn expand_select(select: &Select, columns: &TableColumns, quote: char) -> Option<Vec<SelectItem>> {
+    let relations = relations(select)?;
+    let shared = join_columns(select, columns);
+    let none = HashSet::new();
+    // columns are qualified when select reads more than one table
+    let qualify = relations.len() > 1;
+    let table_columns =
+        |(name, alias): Relation, qualifier: Option<Vec<Ident>>, skip: &HashSet<String>| {
+            let qualifier = qualifier.or_else(|| {
+                qualify.then(|| match alias {
+                    Some(alias) => vec![alias.clone()],
+                    None => name.0.clone(),
+                })
+            });
+            columns.get(&name.to_string()).map(|cols| {
+                cols.iter()
+                    .filter(|col| !skip.contains(&col.name.to_lowercase()))
+                    .map(|col| {
+                        SelectItem::UnnamedExpr(column_expr(qualifier.clone(), &col.name, quote))
+                    })
+                    .collect::<Vec<_>>()
+            })
+        };
+    let mut projection = vec![];
+    for item in select.projection.iter() {
+        match item {
+            SelectItem::Wildcard => {
+                for (relation, skip) in relations.iter().zip(shared.iter()) {
+                    projection.extend(table_columns(*relation, None, skip)?);
+                }
+            }
+            SelectItem::QualifiedWildcard(qualifier) => {
+                let relation = relations
+                    .iter()
+                    .find(|relation| refers_to(qualifier, **relation))?;
+                // `t.*` has all columns of `t`, shared ones included
+                projection.extend(table_columns(*relation, Some(qualifier.0.clone()), &none)?);
+            }
+            item => projection.push(item.clone()),
+        }
+    }
+    Some(projection)
+}
+
+/// replace `*` and `t.*` of statement with table columns, `false` if some of them are kept
+pub fn expand(stmt: &mut Statement, columns: &TableColumns, quote: char) -> bool {
+    let mut expanded = true;
+    for select in selects(stmt).into_iter().filter(|select| has_star(select)) {
+        match expand_select(select, columns, quote) {
+            Some(projection) => select.projection = projection,
+            None => expanded = false,
+        }
+    }
+    expanded
+}
+
+/// projection of leftmost select, `None` if it has wildcard
+pub fn projection(body: &SetExpr) -> Option<&[SelectItem]> {
+    match body {
+        SetExpr::Select(select) => (!has_star(select)).then_some(select.projection.as_slice()),
+        SetExpr::Query(q) => projection(&q.body),
+        SetExpr::SetOperation { left, .. } => projection(left),
+        SetExpr::Values(_) | SetExpr::Insert(_) => None,
+    }
+}
+
+/// output column name of select item, alias or last part of identifier
+pub fn column_name(item: &SelectItem) -> String {
+    match item {
+        SelectItem::ExprWithAlias { alias, .. } => alias.value.clone(),
+        SelectItem::UnnamedExpr(Expr::Identifier(ident)) => ident.value.clone(),
+        SelectItem::UnnamedExpr(Expr::CompoundIdentifier(idents)) => {
+            idents.last().unwrap().value.clone()
+        }
+        SelectItem::UnnamedExpr(expr) => expr.to_string(),
+        SelectItem::Wildcard | SelectItem::QualifiedWildcard(_) => unreachable!(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlparser::{dialect::GenericDialect, parser::Parser};
+
+    fn expanded(sql: &str) -> String {
+        let desc = |names: &[&str]| {
+            names
+                .iter()
+                .map(|name| ColumnDesc {
+                    name: name.to_string(),
+                    ty: "INTEGER".to_string(),
+                    nullable: None,
+                })
+                .collect()
+        };
+        let columns = TableColumns::from([
+            ("a".to_string(), desc(&["id", "name"])),
+            ("b".to_string(), desc(&["id", "a_id", "Name"])),
+        ]);
+        let mut stmt = Parser::parse_sql(&GenericDialect {}, sql)
+            .unwrap()
+            .remove(0);
+        assert!(expand(&mut stmt, &columns, '"'));
+        stmt.to_string()
+    }
+
+    #[test]
+    fn expand_stars() {
+        assert_eq!(expanded("SELECT * FROM a"), "SELECT id, name FROM a");
+        assert_eq!(
+            expanded("SELECT * FROM a JOIN b ON a.id = b.a_id"),
+            "SELECT a.id, a.name, b.id, b.a_id, b.Name FROM a JOIN b ON a.id = b.a_id"
+        );
+        assert_eq!(
+            expanded("SELECT x.*, 1 AS one FROM a AS x"),
+            "SELECT x.id, x.name, 1 AS one FROM a AS x"
+        );
+    }
+
+    #[test]
+    fn expand_shared_join_columns() {
+        assert_eq!(
+            expanded("SELECT * FROM a JOIN b USING (id)"),
+            "SELECT a.id, a.name, b.a_id, b.Name FROM a JOIN b USING(id)"
+        );
+        assert_eq!(
+            expanded("SELECT * FROM a LEFT JOIN b USING (ID, name)"),
+            "SELECT a.id, a.name, b.a_id FROM a LEFT JOIN b USING(ID, name)"
+        );
+        assert_eq!(
+            expanded("SELECT * FROM a NATURAL JOIN b"),
+            "SELECT a.id, a.name, b.a_id FROM a NATURAL JOIN b"
+        );
+        assert_eq!(
+            expanded("SELECT b.* FROM a JOIN b USING (id)"),
+            "SELECT b.id, b.a_id, b.Name FROM a JOIN b USING(id)"
+        );
+    }
+}
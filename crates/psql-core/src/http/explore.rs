@@ -1,10 +1,263 @@
-use std::{convert::Infallible, ops::Deref, sync::Arc};
+use std::{
+    collections::{BTreeMap, HashMap},
+    convert::Infallible,
+    sync::Arc,
+    time::Duration,
+};
 
-use futures::lock::Mutex;
+use futures::{future, lock::Mutex, FutureExt};
+use serde::{Deserialize, Serialize};
+use sqlx::{MySqlPool, PgPool, SqlitePool};
+use warp::http::{HeaderMap, StatusCode};
 
-use super::Plan;
+use super::{
+    error::ApiError,
+    plan::{self, Dialect},
+    unauthorized, Plan,
+};
+use crate::token::VariableToken;
 
 pub async fn status(plan_db: Arc<Mutex<Plan>>) -> Result<impl warp::Reply, Infallible> {
+    let plan = plan_db.lock().await.redacted();
+    Ok(warp::reply::json(&plan))
+}
+
+/// max time to wait for a connection to answer ping
+const PING_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// connection status, uri is not exposed since it may contain password
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConnStatus {
+    pub name: String,
+    /// `conn_labels` entry, name if not set
+    pub label: String,
+    pub dialect: Dialect,
+    /// connection has a live pool and answers ping in time
+    pub healthy: bool,
+}
+
+async fn ping<DB: sqlx::Database>(pool: Option<sqlx::Pool<DB>>) -> bool {
+    use sqlx::Connection;
+    let pool = match pool {
+        Some(pool) => pool,
+        None => return false,
+    };
+    let ping = async {
+        let mut conn = pool.acquire().await?;
+        conn.ping().await
+    };
+    matches!(tokio::time::timeout(PING_TIMEOUT, ping).await, Ok(Ok(())))
+}
+
+/// list plan connections with dialect and health
+pub async fn conns(
+    headers: HeaderMap,
+    plan_db: Arc<Mutex<Plan>>,
+    mysql_dbs: Arc<Mutex<HashMap<String, MySqlPool>>>,
+    sqlite_dbs: Arc<Mutex<HashMap<String, SqlitePool>>>,
+    pg_dbs: Arc<Mutex<HashMap<String, PgPool>>>,
+) -> Result<impl warp::Reply, warp::Rejection> {
     let plan = plan_db.lock().await;
-    Ok(warp::reply::json(plan.deref()))
+    let key = headers
+        .get(plan::API_KEY_HEADER)
+        .and_then(|v| v.to_str().ok());
+    if !plan.authorized(key) {
+        return Err(unauthorized().into());
+    }
+    let mut names = plan
+        .mysql_conns
+        .keys()
+        .map(|name| (name.clone(), Dialect::Mysql))
+        .chain(
+            plan.sqlite_conns
+                .keys()
+                .map(|name| (name.clone(), Dialect::Sqlite)),
+        )
+        .chain(
+            plan.pg_conns
+                .keys()
+                .map(|name| (name.clone(), Dialect::Postgres)),
+        )
+        .map(|(name, dialect)| (plan.conn_label(&name).to_string(), name, dialect))
+        .collect::<Vec<_>>();
+    drop(plan);
+    names.sort_by(|a, b| a.1.cmp(&b.1));
+    let mut status = vec![];
+    for (label, name, dialect) in names {
+        // pools are cloned out first, map lock must not be held while pinging
+        let healthy = match dialect {
+            Dialect::Mysql => {
+                let pool = mysql_dbs.lock().await.get(&name).cloned();
+                ping(pool).await
+            }
+            Dialect::Sqlite => {
+                let pool = sqlite_dbs.lock().await.get(&name).cloned();
+                ping(pool).await
+            }
+            Dialect::Postgres => {
+                let pool = pg_dbs.lock().await.get(&name).cloned();
+                ping(pool).await
+            }
+        };
+        status.push(ConnStatus {
+            name,
+            label,
+            dialect,
+            healthy,
+        });
+    }
+    Ok(warp::reply::json(&status))
+}
+
+/// run `SELECT 1` on pool, `"ok"` or `"error: ..."` if it fails or doesn't answer in time
+async fn select_one<DB>(pool: sqlx::Pool<DB>) -> String
+where
+    DB: sqlx::Database,
+    for<'c> &'c mut DB::Connection: sqlx::Executor<'c, Database = DB>,
+    for<'a> <DB as sqlx::database::HasArguments<'a>>::Arguments: sqlx::IntoArguments<'a, DB>,
+{
+    match tokio::time::timeout(PING_TIMEOUT, sqlx::query("SELECT 1").execute(&pool)).await {
+        Ok(Ok(_)) => "ok".to_string(),
+        Ok(Err(e)) => format!("error: {}", e),
+        Err(_) => format!("error: no answer in {:?}", PING_TIMEOUT),
+    }
+}
+
+/// readiness probe, checks every live pool at the same time and responds result keyed by
+/// connection name, 200 if all of them pass, 503 otherwise
+pub async fn health(
+    mysql_dbs: Arc<Mutex<HashMap<String, MySqlPool>>>,
+    sqlite_dbs: Arc<Mutex<HashMap<String, SqlitePool>>>,
+    pg_dbs: Arc<Mutex<HashMap<String, PgPool>>>,
+) -> Result<impl warp::Reply, Infallible> {
+    let mysql = mysql_dbs.lock().await.clone();
+    let sqlite = sqlite_dbs.lock().await.clone();
+    let pg = pg_dbs.lock().await.clone();
+    let checks = mysql
+        .into_iter()
+        .map(|(name, pool)| select_one(pool).map(|result| (name, result)).boxed())
+        .chain(
+            sqlite
+                .into_iter()
+                .map(|(name, pool)| select_one(pool).map(|result| (name, result)).boxed()),
+        )
+        .chain(
+            pg.into_iter()
+                .map(|(name, pool)| select_one(pool).map(|result| (name, result)).boxed()),
+        );
+    let results = future::join_all(checks)
+        .await
+        .into_iter()
+        .collect::<BTreeMap<_, _>>();
+    let code = if results.values().all(|result| result == "ok") {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    Ok(warp::reply::with_status(warp::reply::json(&results), code))
+}
+
+/// token of query program, `@name` variable or normal sql token
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum TokenDesc {
+    Var {
+        name: String,
+    },
+    Normal {
+        /// token as written back to sql
+        text: String,
+        /// tokenizer output, tells keyword, word and quoted string apart
+        token: String,
+    },
+}
+
+impl From<&VariableToken> for TokenDesc {
+    fn from(token: &VariableToken) -> Self {
+        match token {
+            VariableToken::Var(name) => TokenDesc::Var { name: name.clone() },
+            VariableToken::Normal(token) => TokenDesc::Normal {
+                text: token.to_string(),
+                token: format!("{:?}", token),
+            },
+        }
+    }
+}
+
+/// token stream of named query, for debugging how sql is split and which `@name` are variables
+pub async fn tokens(
+    name: String,
+    headers: HeaderMap,
+    plan_db: Arc<Mutex<Plan>>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let plan = plan_db.lock().await;
+    let key = headers
+        .get(plan::API_KEY_HEADER)
+        .and_then(|v| v.to_str().ok());
+    if !plan.authorized(key) {
+        return Err(unauthorized().into());
+    }
+    let query = plan
+        .queries
+        .get(&name)
+        .ok_or_else(|| ApiError::NotFound(format!("query {} not found", name)))?;
+    let prog = plan
+        .program(&name, query)
+        .map_err(|e| ApiError::BadRequest(e.to_string()))?;
+    let tokens = prog.tokens.iter().map(TokenDesc::from).collect::<Vec<_>>();
+    Ok(warp::reply::json(&tokens))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn health_probe() {
+        use crate::http::spawn_dynamic_http;
+        let text =
+            "title = 't'\naddress = ['127.0.0.1:0']\n[sqlite_conns]\nlocal = 'sqlite::memory:'\n\
+            other = 'sqlite::memory:'\n";
+        let plan = toml::from_str::<Plan>(text).unwrap();
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let responses = rt.block_on(async {
+            let local = sqlx::SqlitePool::connect("sqlite::memory:").await.unwrap();
+            let other = sqlx::SqlitePool::connect("sqlite::memory:").await.unwrap();
+            let sqlite_conns = [
+                ("local".to_string(), local),
+                ("other".to_string(), other.clone()),
+            ]
+            .into_iter()
+            .collect();
+            let handle =
+                spawn_dynamic_http(plan, HashMap::new(), sqlite_conns, HashMap::new(), None)
+                    .unwrap();
+            let url = format!("http://{}/api/__util/health", handle.addrs()[0]);
+            let mut responses = vec![];
+            for _ in 0..2 {
+                let resp = reqwest::get(&url).await.unwrap();
+                let status = resp.status().as_u16();
+                responses.push((
+                    status,
+                    serde_json::from_str::<serde_json::Value>(&resp.text().await.unwrap()).unwrap(),
+                ));
+                other.close().await;
+            }
+            handle.shutdown().await;
+            responses
+        });
+        assert_eq!(
+            responses[0],
+            (200, serde_json::json!({"local": "ok", "other": "ok"}))
+        );
+        assert_eq!(responses[1].0, 503);
+        assert_eq!(responses[1].1["local"], "ok");
+        assert!(responses[1].1["other"]
+            .as_str()
+            .unwrap()
+            .starts_with("error: "));
+    }
 }
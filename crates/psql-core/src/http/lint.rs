@@ -0,0 +1,168 @@
+//! sql lint of plan queries, run when plan is loaded
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use sqlparser::ast::{Query, SelectItem, SetExpr, Statement};
+
+/// how a lint rule violation is reported
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum LintLevel {
+    #[default]
+    Off,
+    /// log a warning and keep loading
+    Warn,
+    /// fail plan loading
+    Error,
+}
+
+/// lint rules checked against every query, all are off by default
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize, JsonSchema)]
+pub struct Lint {
+    /// `*` or `t.*` in select projection
+    #[serde(default)]
+    pub select_star: LintLevel,
+    /// `UPDATE` or `DELETE` without `WHERE`
+    #[serde(default)]
+    pub missing_where: LintLevel,
+    /// select from table without `WHERE` or `LIMIT`, which scans the whole table
+    #[serde(default)]
+    pub unbounded_select: LintLevel,
+}
+
+/// a broken lint rule
+#[derive(Debug, Clone)]
+pub struct Violation {
+    pub rule: &'static str,
+    pub level: LintLevel,
+    pub msg: String,
+}
+
+impl Lint {
+    /// violations of enabled rules in statement
+    pub fn check(&self, stmt: &Statement) -> Vec<Violation> {
+        let mut found = vec![];
+        let mut report = |rule, level, msg: &str| {
+            if level != LintLevel::Off {
+                found.push(Violation {
+                    rule,
+                    level,
+                    msg: msg.to_string(),
+                });
+            }
+        };
+        match stmt {
+            Statement::Query(query) => {
+                if has_star(&query.body) {
+                    report("select_star", self.select_star, "select *");
+                }
+                if unbounded(query) {
+                    report(
+                        "unbounded_select",
+                        self.unbounded_select,
+                        "select from table without where or limit",
+                    );
+                }
+            }
+            Statement::Insert { source, .. } if has_star(&source.body) => {
+                report("select_star", self.select_star, "insert from select *");
+            }
+            Statement::Update {
+                selection: None, ..
+            } => report("missing_where", self.missing_where, "update without where"),
+            Statement::Delete {
+                selection: None, ..
+            } => report("missing_where", self.missing_where, "delete without where"),
+            _ => {}
+        }
+        found
+    }
+}
+
+fn has_star(body: &SetExpr) -> bool {
+    match body {
+        SetExpr::Select(select) => select.projection.iter().any(|item| {
+            matches!(
+                item,
+                SelectItem::Wildcard | SelectItem::QualifiedWildcard(_)
+            )
+        }),
+        SetExpr::Query(query) => has_star(&query.body),
+        SetExpr::SetOperation { left, right, .. } => has_star(left) || has_star(right),
+        SetExpr::Values(_) | SetExpr::Insert(_) => false,
+    }
+}
+
+/// whether any select of body reads a table without filter, limit of `Query` bounds it
+fn scans_table(body: &SetExpr) -> bool {
+    match body {
+        SetExpr::Select(select) => !select.from.is_empty() && select.selection.is_none(),
+        SetExpr::Query(query) => unbounded(query),
+        SetExpr::SetOperation { left, right, .. } => scans_table(left) || scans_table(right),
+        SetExpr::Values(_) | SetExpr::Insert(_) => false,
+    }
+}
+
+fn unbounded(query: &Query) -> bool {
+    query.limit.is_none() && scans_table(&query.body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlparser::{dialect::GenericDialect, parser::Parser};
+
+    fn rules(lint: &Lint, sql: &str) -> Vec<(&'static str, LintLevel)> {
+        let stmt = Parser::parse_sql(&GenericDialect {}, sql)
+            .unwrap()
+            .remove(0);
+        lint.check(&stmt)
+            .into_iter()
+            .map(|violation| (violation.rule, violation.level))
+            .collect()
+    }
+
+    #[test]
+    fn lint_rules() {
+        let lint = Lint {
+            select_star: LintLevel::Warn,
+            missing_where: LintLevel::Error,
+            unbounded_select: LintLevel::Warn,
+        };
+        assert_eq!(
+            rules(&lint, "SELECT * FROM t"),
+            [
+                ("select_star", LintLevel::Warn),
+                ("unbounded_select", LintLevel::Warn)
+            ]
+        );
+        assert_eq!(
+            rules(&lint, "SELECT t.* FROM t WHERE id = 1"),
+            [("select_star", LintLevel::Warn)]
+        );
+        assert!(rules(&lint, "SELECT id FROM t LIMIT 10").is_empty());
+        assert!(rules(&lint, "SELECT 1").is_empty());
+        assert_eq!(
+            rules(
+                &lint,
+                "SELECT id FROM t UNION SELECT id FROM u WHERE id = 1"
+            ),
+            [("unbounded_select", LintLevel::Warn)]
+        );
+        assert_eq!(
+            rules(&lint, "INSERT INTO t SELECT * FROM u WHERE id = 1"),
+            [("select_star", LintLevel::Warn)]
+        );
+        assert_eq!(
+            rules(&lint, "UPDATE t SET a = 1"),
+            [("missing_where", LintLevel::Error)]
+        );
+        assert_eq!(
+            rules(&lint, "DELETE FROM t"),
+            [("missing_where", LintLevel::Error)]
+        );
+        assert!(rules(&lint, "DELETE FROM t WHERE id = 1").is_empty());
+        // rules are off by default
+        assert!(rules(&Lint::default(), "DELETE FROM t").is_empty());
+    }
+}
@@ -1,26 +1,43 @@
 use crate::{
+    errors::PSqlError,
     http::plan::Dialect,
-    parser::{ParamValue, Program},
+    parser::{InnerTy, ParamTy, ParamValue, Program, StatementKind},
+};
+use futures::{future, lock::Mutex, Future, StreamExt};
+use output::{
+    MultiOutputSer, PSqlRowListSer, PSqlRowMapSer, PSqlRowNestedSer, QueryOutput,
+    QueryOutputCsvSer, QueryOutputMapSer, SerOptions,
 };
-use futures::{future, lock::Mutex};
-use output::{QueryOutput, QueryOutputMapSer};
 pub use plan::Plan;
 use querystring::querify;
 use serde::{Deserialize, Serialize};
-use sqlparser::dialect::MySqlDialect;
-use sqlx::{Connection, MySqlPool, SqlitePool};
-use std::{collections::HashMap, convert::Infallible, sync::Arc};
+use sqlx::{mysql::MySqlRow, Connection, MySqlPool, PgPool, Row, SqlitePool};
+use std::{collections::HashMap, convert::Infallible, net::SocketAddr, sync::Arc, time::Duration};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
 use warp::{
-    hyper::{Method, StatusCode},
-    Filter,
+    filters::BoxedFilter,
+    http::{header, HeaderMap, HeaderValue},
+    hyper::{body::Buf, Method, StatusCode},
+    Filter, Reply,
 };
 
-use self::plan::{PlanDb, Query};
+use self::{
+    audit::AuditLog,
+    error::ApiError,
+    plan::{ErrorDetail, Hooks, PlanDb, Query},
+};
 
+pub mod audit;
+pub mod error;
+pub mod expand;
 pub mod explore;
 mod index;
+pub mod lint;
 pub mod output;
+mod pagination;
 pub mod plan;
+pub mod stream;
+pub mod typescript;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ApiMsg {
@@ -28,9 +45,13 @@ pub struct ApiMsg {
     pub code: u16,
 }
 
-async fn dynamic_doc(plan_db: PlanDb) -> Result<impl warp::Reply, Infallible> {
+async fn dynamic_doc(
+    lang: Option<String>,
+    plan_db: PlanDb,
+) -> Result<impl warp::Reply, Infallible> {
     let plan = plan_db.lock().await;
-    Ok(warp::reply::json(&plan.openapi_doc()))
+    let lang = lang.or_else(|| plan.locale.clone());
+    Ok(warp::reply::json(&plan.openapi_doc_for(lang.as_deref())))
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -41,19 +62,38 @@ pub struct NewQuery {
     pub query: Query,
 }
 
+/// queries are validated and parsed before any of them is added, so a malformed one rejects all
 async fn add_query(
+    headers: HeaderMap,
     new_queries: Vec<NewQuery>,
     plan_db: PlanDb,
-) -> Result<impl warp::Reply, Infallible> {
+) -> Result<warp::reply::Response, warp::Rejection> {
+    if let Err(resp) = check_api_key(&headers, &plan_db).await {
+        return Ok(resp);
+    }
     let mut plan = plan_db.lock().await;
-    new_queries.into_iter().for_each(|new_query| {
-        let NewQuery { name, query } = new_query;
+    let mut parsed = Vec::with_capacity(new_queries.len());
+    for NewQuery { name, query } in new_queries {
+        plan.validate_query(&name, &query)
+            .map_err(ApiError::BadRequest)?;
+        let prog = plan
+            .parse_query(&query)
+            .map_err(|e| ApiError::BadRequest(format!("query {}: {}", name, e)))?;
+        parsed.push((name, query, prog));
+    }
+    for (name, query, prog) in parsed {
+        plan.programs.insert(name.clone(), prog);
         plan.queries.insert(name, query);
-    });
-    Ok(warp::reply::json(&ApiMsg {
-        code: 201,
-        msg: "all queries added.".to_string(),
-    }))
+    }
+    let code = StatusCode::CREATED;
+    Ok(warp::reply::with_status(
+        warp::reply::json(&ApiMsg {
+            code: code.as_u16(),
+            msg: "all queries added.".to_string(),
+        }),
+        code,
+    )
+    .into_response())
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -62,43 +102,93 @@ pub struct NewConn {
     pub name: String,
 }
 
+impl NewConn {
+    /// same connection with password of uri masked, for responses
+    fn redacted(self) -> Self {
+        Self {
+            uri: plan::redact_dsn(&self.uri),
+            ..self
+        }
+    }
+}
+
 async fn add_conn(
+    headers: HeaderMap,
     new_conns: Vec<NewConn>,
     plan_db: Arc<Mutex<Plan>>,
     mysql_dbs: Arc<Mutex<HashMap<String, MySqlPool>>>,
     sqlite_dbs: Arc<Mutex<HashMap<String, SqlitePool>>>,
-) -> Result<impl warp::Reply, Infallible> {
+    pg_dbs: Arc<Mutex<HashMap<String, PgPool>>>,
+) -> Result<warp::reply::Response, Infallible> {
+    if let Err(resp) = check_api_key(&headers, &plan_db).await {
+        return Ok(resp);
+    }
     let mut failed = vec![];
     let mut ok = vec![];
     for new_conn in new_conns {
         let dialect = Dialect::from_uri(&new_conn.uri);
+        let pool = plan_db.lock().await.conn_pool(&new_conn.name);
         match dialect {
-            Dialect::Mysql => match sqlx::MySqlPool::connect(&new_conn.uri).await {
+            Dialect::Mysql => match pool
+                .apply(sqlx::mysql::MySqlPoolOptions::new())
+                .connect(&new_conn.uri)
+                .await
+            {
                 Ok(pool) => {
                     let mut mysql_dbs = mysql_dbs.lock().await;
                     mysql_dbs.insert(new_conn.name.clone(), pool);
                     let mut plan = plan_db.lock().await;
                     plan.mysql_conns
                         .insert(new_conn.name.clone(), new_conn.uri.clone());
-                    ok.push((new_conn, "ok".to_string()));
+                    ok.push((new_conn.redacted(), "ok".to_string()));
                 }
                 Err(e) => {
-                    failed.push((new_conn, e.to_string()));
+                    let msg = plan::conn_error(&new_conn.uri, e);
+                    failed.push((new_conn.redacted(), msg));
                 }
             },
-            Dialect::Sqlite => match sqlx::SqlitePool::connect(&new_conn.uri).await {
+            Dialect::Sqlite => match pool
+                .apply(sqlx::sqlite::SqlitePoolOptions::new())
+                .connect(&new_conn.uri)
+                .await
+            {
                 Ok(pool) => {
                     let mut sqlite_dbs = sqlite_dbs.lock().await;
                     sqlite_dbs.insert(new_conn.name.clone(), pool);
                     let mut plan = plan_db.lock().await;
                     plan.sqlite_conns
                         .insert(new_conn.name.clone(), new_conn.uri.clone());
-                    ok.push((new_conn, "ok".to_string()));
+                    ok.push((new_conn.redacted(), "ok".to_string()));
                 }
                 Err(e) => {
-                    failed.push((new_conn, e.to_string()));
+                    let msg = plan::conn_error(&new_conn.uri, e);
+                    failed.push((new_conn.redacted(), msg));
                 }
             },
+            Dialect::Postgres => match pool
+                .apply(sqlx::postgres::PgPoolOptions::new())
+                .connect(&new_conn.uri)
+                .await
+            {
+                Ok(pool) => {
+                    let mut pg_dbs = pg_dbs.lock().await;
+                    pg_dbs.insert(new_conn.name.clone(), pool);
+                    let mut plan = plan_db.lock().await;
+                    plan.pg_conns
+                        .insert(new_conn.name.clone(), new_conn.uri.clone());
+                    ok.push((new_conn.redacted(), "ok".to_string()));
+                }
+                Err(e) => {
+                    let msg = plan::conn_error(&new_conn.uri, e);
+                    failed.push((new_conn.redacted(), msg));
+                }
+            },
+        }
+    }
+    // queries of new connections are parsed with their dialect from now on
+    if !ok.is_empty() {
+        if let Err(e) = plan_db.lock().await.compile() {
+            log::error!("compile queries for new connections failed {}", e);
         }
     }
     if failed.is_empty() {
@@ -109,7 +199,8 @@ async fn add_conn(
                 code: code.as_u16(),
             }),
             code,
-        ))
+        )
+        .into_response())
     } else {
         let code = warp::http::StatusCode::BAD_REQUEST;
         let mut result = HashMap::with_capacity(2);
@@ -121,7 +212,8 @@ async fn add_conn(
                 code: code.as_u16(),
             }),
             code,
-        ))
+        )
+        .into_response())
     }
 }
 
@@ -130,97 +222,144 @@ pub struct NewConnUri {
     pub uri: String,
 }
 
-async fn test_conn(param: NewConnUri) -> Result<impl warp::Reply, Infallible> {
+async fn test_conn(
+    headers: HeaderMap,
+    param: NewConnUri,
+    plan_db: PlanDb,
+) -> Result<warp::reply::Response, warp::Rejection> {
+    if let Err(resp) = check_api_key(&headers, &plan_db).await {
+        return Ok(resp);
+    }
     let dialect = Dialect::from_uri(&param.uri);
-    let mut code = 200;
-    let msg = match dialect {
-        Dialect::Mysql => match sqlx::MySqlConnection::connect(&param.uri).await {
-            Ok(_) => "OK".to_string(),
-            Err(e) => {
-                code = 400;
-                e.to_string()
-            }
-        },
-        Dialect::Sqlite => match sqlx::SqliteConnection::connect(&param.uri).await {
-            Ok(_) => "OK".to_string(),
-            Err(e) => {
-                code = 400;
-                e.to_string()
-            }
-        },
+    let connected = match dialect {
+        Dialect::Mysql => sqlx::MySqlConnection::connect(&param.uri).await.map(|_| ()),
+        Dialect::Sqlite => sqlx::SqliteConnection::connect(&param.uri)
+            .await
+            .map(|_| ()),
+        Dialect::Postgres => sqlx::PgConnection::connect(&param.uri).await.map(|_| ()),
     };
-    Ok(warp::reply::json(&ApiMsg { msg, code }))
+    connected.map_err(|e| ApiError::BadRequest(plan::conn_error(&param.uri, e)))?;
+    Ok(warp::reply::json(&ApiMsg {
+        msg: "OK".to_string(),
+        code: StatusCode::OK.as_u16(),
+    })
+    .into_response())
+}
+
+/// single body value checked against param type, strings of other types, e.g. form values,
+/// are parsed like query string values
+fn body_value(ty: &InnerTy, value: &ParamValue) -> Option<ParamValue> {
+    match value {
+        value if ty.accepts(value) => Some(value.clone()),
+        ParamValue::Str(text) => ParamValue::from_arg_str(ty, text)
+            .ok()
+            .filter(|value| ty.accepts(value)),
+        _ => None,
+    }
 }
 
 fn get_context_from_body(
     body: &HashMap<String, ParamValue>,
     prog: &Program,
-) -> Result<HashMap<String, ParamValue>, ApiMsg> {
+    defaults: &HashMap<String, ParamValue>,
+) -> Result<HashMap<String, ParamValue>, ApiError> {
+    let given = prog
+        .params
+        .iter()
+        .map(|p| p.name.as_str())
+        .filter(|name| body.contains_key(*name))
+        .collect();
+    prog.check_constraints(&given)
+        .map_err(|e| ApiError::BadRequest(e.to_string()))?;
     let mut context: HashMap<String, ParamValue> = HashMap::new();
     for p in prog.params.iter() {
         let found = body.get(&p.name);
-        match (found, p.default.clone()) {
+        let default = defaults.get(&p.name).cloned().or_else(|| p.omitted_value());
+        match (found, default) {
+            (None, None) if p.default_from.is_some() => {}
             (None, None) => {
-                let code = warp::http::StatusCode::BAD_REQUEST;
-                let msg = ApiMsg {
-                    msg: format!("{} is required", p.name),
-                    code: code.as_u16(),
-                };
-                return Err(msg);
+                return Err(ApiError::BadRequest(format!("{} is required", p.name)));
             }
             (None, Some(default)) => {
                 context.insert(p.name.clone(), default);
             }
-            (Some(param), _) => match &p.ty {
-                crate::parser::ParamTy::Basic(_) => match param {
-                    ParamValue::Array(arr) => {
-                        let code = warp::http::StatusCode::BAD_REQUEST;
-                        let msg = ApiMsg {
-                            msg: format!("{} expect single value, got {}", p.name, arr.len()),
-                            code: code.as_u16(),
-                        };
-                        return Err(msg);
+            (Some(ParamValue::Null), _) if !p.nullable => {
+                return Err(ApiError::BadRequest(format!("{} is not nullable", p.name)));
+            }
+            (Some(ParamValue::Null), _) => {
+                context.insert(p.name.clone(), ParamValue::Null);
+            }
+            (Some(param), _) => {
+                let value = match (&p.ty, param) {
+                    (ParamTy::Basic(_), ParamValue::Array(arr)) => {
+                        return Err(ApiError::BadRequest(format!(
+                            "{} expect single value, got {}",
+                            p.name,
+                            arr.len()
+                        )));
                     }
-                    _ => {
-                        context.insert(p.name.clone(), param.clone());
+                    (ParamTy::Basic(ty), value) => body_value(ty, value),
+                    (ParamTy::Array(ty), ParamValue::Array(items)) => items
+                        .iter()
+                        .map(|item| body_value(ty, item))
+                        .collect::<Option<Vec<_>>>()
+                        .map(ParamValue::Array),
+                    (ParamTy::Array(_), _) => {
+                        return Err(ApiError::BadRequest(format!(
+                            "{} expect array, got single value",
+                            p.name
+                        )));
                     }
-                },
-                crate::parser::ParamTy::Array(_) => match param {
-                    ParamValue::Array(_) => {
-                        context.insert(p.name.clone(), param.clone());
+                };
+                match value {
+                    Some(value) => {
+                        context.insert(p.name.clone(), p.transform(value));
                     }
-                    _ => {
-                        let code = warp::http::StatusCode::BAD_REQUEST;
-                        let msg = ApiMsg {
-                            msg: format!("{} expect array, got single value", p.name),
-                            code: code.as_u16(),
-                        };
-                        return Err(msg);
+                    None => {
+                        return Err(ApiError::BadRequest(format!(
+                            "invalid value {} for {}{}",
+                            param,
+                            p.ty,
+                            p.ty.inner().format_hint()
+                        )));
                     }
-                },
-            },
+                }
+            }
         }
     }
+    prog.check_values(&context, &given)
+        .map_err(|e| ApiError::BadRequest(e.to_string()))?;
+    prog.resolve_defaults(&mut context)
+        .map_err(|e| ApiError::BadRequest(e.to_string()))?;
     Ok(context)
 }
 
-fn get_context_from_qs(qs: String, prog: &Program) -> Result<HashMap<String, ParamValue>, ApiMsg> {
+fn get_context_from_qs(
+    qs: String,
+    prog: &Program,
+    defaults: &HashMap<String, ParamValue>,
+) -> Result<HashMap<String, ParamValue>, ApiError> {
     let decoded = urlencoding::decode(&qs).unwrap();
     let qs_pairs = querify(&decoded);
+    let given = prog
+        .params
+        .iter()
+        .map(|p| p.name.as_str())
+        .filter(|name| qs_pairs.iter().any(|(k, _)| k == name))
+        .collect();
+    prog.check_constraints(&given)
+        .map_err(|e| ApiError::BadRequest(e.to_string()))?;
     let mut context: HashMap<String, ParamValue> = HashMap::new();
     for p in prog.params.iter() {
         let found = qs_pairs
             .iter()
             .filter(|(k, _)| *k == p.name)
             .collect::<Vec<&(&str, &str)>>();
-        match (found.is_empty(), p.default.clone()) {
+        let default = defaults.get(&p.name).cloned().or_else(|| p.omitted_value());
+        match (found.is_empty(), default) {
+            (true, None) if p.default_from.is_some() => {}
             (true, None) => {
-                let code = warp::http::StatusCode::BAD_REQUEST;
-                let msg = ApiMsg {
-                    msg: format!("{} is required", p.name),
-                    code: code.as_u16(),
-                };
-                return Err(msg);
+                return Err(ApiError::BadRequest(format!("{} is required", p.name)));
             }
             (true, Some(default)) => {
                 context.insert(p.name.clone(), default);
@@ -228,25 +367,24 @@ fn get_context_from_qs(qs: String, prog: &Program) -> Result<HashMap<String, Par
             (false, _) => match &p.ty {
                 crate::parser::ParamTy::Basic(inner_ty) => {
                     if found.len() > 1 {
-                        let code = warp::http::StatusCode::BAD_REQUEST;
-                        let msg = ApiMsg {
-                            msg: format!("{} expect single value, got {}", p.name, found.len()),
-                            code: code.as_u16(),
-                        };
-                        return Err(msg);
+                        return Err(ApiError::BadRequest(format!(
+                            "{} expect single value, got {}",
+                            p.name,
+                            found.len()
+                        )));
                     }
                     let raw_value = found.first().unwrap().1;
                     match ParamValue::from_arg_str(inner_ty, raw_value) {
                         Err(_) => {
-                            let code = warp::http::StatusCode::BAD_REQUEST;
-                            let msg = ApiMsg {
-                                msg: format!("invalid value `{}` for {:?}", raw_value, inner_ty),
-                                code: code.as_u16(),
-                            };
-                            return Err(msg);
+                            return Err(ApiError::BadRequest(format!(
+                                "invalid value `{}` for {:?}{}",
+                                raw_value,
+                                inner_ty,
+                                inner_ty.format_hint()
+                            )));
                         }
                         Ok(val) => {
-                            context.insert(p.name.clone(), val);
+                            context.insert(p.name.clone(), p.transform(val));
                         }
                     }
                 }
@@ -254,14 +392,14 @@ fn get_context_from_qs(qs: String, prog: &Program) -> Result<HashMap<String, Par
                     let mut parsed = vec![];
                     for (_, raw) in found {
                         match ParamValue::from_arg_str(inner_ty, raw) {
-                            Ok(val) => parsed.push(val),
+                            Ok(val) => parsed.push(p.transform(val)),
                             Err(_) => {
-                                let code = warp::http::StatusCode::BAD_REQUEST;
-                                let msg = ApiMsg {
-                                    msg: format!("invalid value `{}` for {:?}", raw, inner_ty),
-                                    code: code.as_u16(),
-                                };
-                                return Err(msg);
+                                return Err(ApiError::BadRequest(format!(
+                                    "invalid value `{}` for {:?}{}",
+                                    raw,
+                                    inner_ty,
+                                    inner_ty.format_hint()
+                                )));
                             }
                         }
                     }
@@ -270,231 +408,2732 @@ fn get_context_from_qs(qs: String, prog: &Program) -> Result<HashMap<String, Par
             },
         }
     }
+    prog.check_values(&context, &given)
+        .map_err(|e| ApiError::BadRequest(e.to_string()))?;
+    prog.resolve_defaults(&mut context)
+        .map_err(|e| ApiError::BadRequest(e.to_string()))?;
     Ok(context)
 }
 
-fn new_query_body() -> impl Filter<Extract = (Vec<NewQuery>,), Error = warp::Rejection> + Clone {
-    warp::body::content_length_limit(1024 * 16).and(warp::body::json())
+/// request body can not be read or decoded
+fn invalid_body(e: impl std::fmt::Display) -> warp::Rejection {
+    ApiError::BadRequest(format!("invalid request body: {}", e)).into()
 }
 
-async fn serve_with_context(
-    prog: &Program,
-    _plan_db: PlanDb,
-    query: &Query,
-    code: &mut warp::http::StatusCode,
-    context: HashMap<String, ParamValue>,
-    mysql_dbs: Arc<Mutex<HashMap<String, MySqlPool>>>,
-    sqlite_dbs: Arc<Mutex<HashMap<String, SqlitePool>>>,
-) -> Result<warp::reply::WithStatus<warp::reply::Json>, warp::Rejection> {
-    match prog.render(&MySqlDialect {}, &context) {
-        Ok(stmts) => {
-            if stmts.len() != 1 {
-                let msg = ApiMsg {
-                    msg: format!("expect 1 sql statement, got {}", stmts.len()),
-                    code: code.as_u16(),
-                };
-                return Ok(warp::reply::with_status(warp::reply::json(&msg), *code));
+/// read whole request body with at most `limit` bytes
+///
+/// unlike `warp::body::content_length_limit`, this filter also accepts
+/// `Transfer-Encoding: chunked` requests, which has no `Content-Length` header,
+/// and stops reading as soon as the limit is exceeded.
+fn limited_body(limit: u64) -> impl Filter<Extract = (Vec<u8>,), Error = warp::Rejection> + Clone {
+    warp::header::optional::<u64>("content-length")
+        .and(warp::body::stream())
+        .and_then(move |length, stream| read_body(length, stream, limit))
+}
+
+async fn read_body<S, B>(
+    length: Option<u64>,
+    stream: S,
+    limit: u64,
+) -> Result<Vec<u8>, warp::Rejection>
+where
+    S: futures::Stream<Item = Result<B, warp::Error>>,
+    B: Buf,
+{
+    if matches!(length, Some(length) if length > limit) {
+        return Err(ApiError::PayloadTooLarge(limit).into());
+    }
+    futures::pin_mut!(stream);
+    let mut body = vec![];
+    while let Some(chunk) = stream.next().await {
+        let mut chunk = chunk.map_err(invalid_body)?;
+        if (body.len() + chunk.remaining()) as u64 > limit {
+            return Err(ApiError::PayloadTooLarge(limit).into());
+        }
+        while chunk.has_remaining() {
+            let bytes = chunk.chunk();
+            let len = bytes.len();
+            body.extend_from_slice(bytes);
+            chunk.advance(len);
+        }
+    }
+    Ok(body)
+}
+
+/// json body with size limit
+fn json_body<T: serde::de::DeserializeOwned + Send>(
+    limit: u64,
+) -> impl Filter<Extract = (T,), Error = warp::Rejection> + Clone {
+    limited_body(limit).and_then(|body: Vec<u8>| async move {
+        serde_json::from_slice::<T>(&body).map_err(invalid_body)
+    })
+}
+
+/// query params of request body
+#[derive(Debug, Default)]
+struct BodyParams {
+    params: HashMap<String, ParamValue>,
+    /// raw text of form body, parsed as query string when a GET query is tunneled by POST
+    form: Option<String>,
+    /// binary body of blob query
+    blob: Option<Vec<u8>>,
+}
+
+/// query params from json or form body, empty body yields empty params, other content
+/// types are rejected by 415
+fn params_body(
+    limit: u64,
+) -> impl Filter<Extract = (BodyParams,), Error = warp::Rejection> + Clone {
+    warp::header::optional::<String>("content-type")
+        .and(limited_body(limit))
+        .and_then(|content_type: Option<String>, body: Vec<u8>| async move {
+            if body.is_empty() {
+                return Ok(BodyParams::default());
             }
-            let stmt = stmts.first().unwrap();
-            match mysql_dbs.lock().await.get(&query.conn) {
-                Some(pool) => {
-                    match sqlx::query(&stmt.to_string())
-                        .fetch_all(pool)
-                        .await
-                        .map(|rows| QueryOutput { rows })
-                    {
-                        Ok(output) => {
-                            let code = warp::http::StatusCode::OK;
-                            let json = warp::reply::json(&QueryOutputMapSer(&output));
-                            Ok(warp::reply::with_status(json, code))
-                        }
-                        Err(e) => {
-                            let msg = ApiMsg {
-                                msg: format!("SQL: {}\n{}", &stmt, e),
-                                code: code.as_u16(),
-                            };
-                            Ok(warp::reply::with_status(warp::reply::json(&msg), *code))
-                        }
-                    }
+            let json = |body: &[u8]| {
+                serde_json::from_slice(body)
+                    .map(|params| BodyParams {
+                        params,
+                        ..Default::default()
+                    })
+                    .map_err(|e| e.to_string())
+            };
+            let parsed = match content_type.as_deref() {
+                Some(ty) if ty.starts_with("application/x-www-form-urlencoded") => {
+                    serde_urlencoded::from_bytes(&body)
+                        .map(|params| BodyParams {
+                            params,
+                            form: Some(String::from_utf8_lossy(&body).into_owned()),
+                            blob: None,
+                        })
+                        .map_err(|e| e.to_string())
                 }
-                None => {
-                    let dbs = sqlite_dbs.lock().await;
-                    let pool = dbs.get(&query.conn).unwrap();
-                    match sqlx::query(&stmt.to_string())
-                        .fetch_all(pool)
-                        .await
-                        .map(|rows| QueryOutput { rows })
-                    {
-                        Ok(output) => {
-                            let code = warp::http::StatusCode::OK;
-                            let json = warp::reply::json(&QueryOutputMapSer(&output));
-                            Ok(warp::reply::with_status(json, code))
-                        }
-                        Err(e) => {
-                            let msg = ApiMsg {
-                                msg: format!("SQL: {}\n{}", &stmt, e),
-                                code: code.as_u16(),
-                            };
-                            Ok(warp::reply::with_status(warp::reply::json(&msg), *code))
-                        }
-                    }
+                None => json(&body),
+                Some(ty) if ty.starts_with("application/json") => json(&body),
+                Some(ty) => {
+                    return Err(ApiError::UnsupportedMediaType(format!(
+                        "content type {} is not accepted, expect application/json or application/x-www-form-urlencoded",
+                        ty
+                    ))
+                    .into())
                 }
-            }
+            };
+            parsed.map_err(invalid_body)
+        })
+}
+
+/// request info needed to build query response
+#[derive(Debug, Clone)]
+pub(crate) struct RequestInfo {
+    pub path: String,
+    pub qs: String,
+    pub headers: HeaderMap,
+    pub remote: Option<SocketAddr>,
+}
+
+impl RequestInfo {
+    /// whether a switch like `?__describe=1` is turned on in query string
+    pub fn flag(&self, name: &str) -> bool {
+        switch_on(&self.qs, name)
+    }
+
+    /// whether client asks for indented json
+    pub fn pretty(&self) -> bool {
+        wants_pretty(&self.qs, &self.headers)
+    }
+
+    /// whether client asks for csv rows, by `?__format=csv` or `Accept: text/csv`.
+    /// `__format` overrides the header, and the first of json and csv listed in
+    /// `Accept` wins
+    pub fn csv(&self) -> bool {
+        if let Some((_, format)) = querify(&self.qs).iter().find(|(k, _)| *k == "__format") {
+            return format.eq_ignore_ascii_case("csv");
+        }
+        self.accepts_first(&["text/csv", "application/json"]) == Some("text/csv")
+    }
+
+    /// whether client asks for rows streamed as ndjson, by `?__stream=1` or
+    /// `Accept: application/x-ndjson` listed before json
+    pub fn ndjson(&self) -> bool {
+        const NDJSON: &str = "application/x-ndjson";
+        self.flag("__stream") || self.accepts_first(&[NDJSON, "application/json"]) == Some(NDJSON)
+    }
+
+    /// the first of `types` listed in `Accept` header, parameters like `;q=` are ignored
+    fn accepts_first<'t>(&self, types: &[&'t str]) -> Option<&'t str> {
+        let accept = self.headers.get("accept").and_then(|v| v.to_str().ok())?;
+        accept
+            .split(',')
+            .map(|range| range.split(';').next().unwrap_or_default().trim())
+            .find_map(|range| types.iter().find(|ty| ty.eq_ignore_ascii_case(range)))
+            .copied()
+    }
+}
+
+fn switch_on(qs: &str, name: &str) -> bool {
+    querify(qs)
+        .iter()
+        .any(|(k, v)| *k == name && matches!(*v, "" | "1" | "true"))
+}
+
+/// `?__pretty=1` or `X-Pretty: 1` header
+fn wants_pretty(qs: &str, headers: &HeaderMap) -> bool {
+    let header = headers.get("x-pretty").and_then(|v| v.to_str().ok());
+    switch_on(qs, "__pretty") || matches!(header, Some("" | "1" | "true"))
+}
+
+fn pretty_flag() -> impl Filter<Extract = (bool,), Error = Infallible> + Clone {
+    warp::query::raw()
+        .or(warp::any().map(String::new))
+        .unify()
+        .and(warp::header::headers_cloned())
+        .map(|qs: String, headers: HeaderMap| wants_pretty(&qs, &headers))
+}
+
+/// json response, indented if `pretty`, compact by default
+pub(crate) fn json_reply<T: Serialize>(value: &T, pretty: bool) -> warp::reply::Response {
+    let body = if pretty {
+        serde_json::to_string_pretty(value)
+    } else {
+        serde_json::to_string(value)
+    };
+    match body {
+        Ok(body) => {
+            warp::reply::with_header(body, "content-type", "application/json").into_response()
         }
         Err(e) => {
-            let msg = ApiMsg {
-                msg: format!("{:#?}", e),
-                code: code.as_u16(),
+            log::error!("serialize json response failed {}", e);
+            ApiError::Internal(format!("serialize response failed: {}", e)).reply(pretty)
+        }
+    }
+}
+
+/// csv response of rows, errors are still json
+fn csv_reply<R: Row>(output: &QueryOutputCsvSer<R>, pretty: bool) -> warp::reply::Response
+where
+    for<'a> PSqlRowListSer<'a, R>: Serialize,
+{
+    match output.to_csv() {
+        Ok(body) => warp::reply::with_header(body, "content-type", "text/csv; charset=utf-8")
+            .into_response(),
+        Err(e) => {
+            log::error!("serialize csv response failed {}", e);
+            ApiError::Internal(format!("serialize response failed: {}", e)).reply(pretty)
+        }
+    }
+}
+
+/// admission control of a connection, at most `max_concurrent` queries run at the same time
+#[derive(Clone)]
+pub(crate) struct ConnLimiter {
+    semaphore: Arc<Semaphore>,
+    wait: Duration,
+}
+
+type Limiters = Arc<HashMap<String, ConnLimiter>>;
+
+impl ConnLimiter {
+    fn new(limit: &plan::ConnLimit) -> Self {
+        Self {
+            semaphore: Arc::new(Semaphore::new(limit.max_concurrent.max(1))),
+            wait: Duration::from_millis(limit.wait_ms),
+        }
+    }
+
+    /// wait for a query slot, error if it takes longer than configured wait time
+    async fn acquire(&self) -> Result<OwnedSemaphorePermit, ()> {
+        match tokio::time::timeout(self.wait, self.semaphore.clone().acquire_owned()).await {
+            Ok(Ok(permit)) => Ok(permit),
+            _ => Err(()),
+        }
+    }
+}
+
+/// too many in-flight queries on connection
+fn conn_busy(conn: &str) -> ApiError {
+    ApiError::Unavailable {
+        msg: format!("connection {} is busy, retry later", conn),
+        retry_after: Some(POOL_RETRY_AFTER),
+    }
+}
+
+/// query refers to a connection plan doesn't declare, plan checks refuse such queries so
+/// this only guards against a plan changed without them
+fn conn_not_found(conn: &str) -> ApiError {
+    ApiError::Internal(format!("connection '{}' not found", conn))
+}
+
+/// query's connection has no live pool, e.g. failed to connect at startup
+fn conn_unavailable(conn: &str) -> ApiError {
+    ApiError::Unavailable {
+        msg: format!("connection {} unavailable", conn),
+        retry_after: None,
+    }
+}
+
+/// reply column descriptions instead of query result
+fn describe_reply(
+    described: Result<Vec<output::ColumnDesc>, sqlx::Error>,
+    conn: &str,
+    stmt: &sqlparser::ast::Statement,
+    detail: ErrorDetail,
+    pretty: bool,
+) -> warp::reply::Response {
+    match described {
+        Ok(columns) => json_reply(&columns, pretty),
+        Err(e) => db_error(conn, stmt, e, detail).reply(pretty),
+    }
+}
+
+/// seconds client should wait before retry when connection pool is exhausted
+const POOL_RETRY_AFTER: u64 = 1;
+
+/// database error, full error is always logged,
+/// pool timeout is 503 with `Retry-After` since it's not client's fault,
+/// sql syntax error is 422 and other failures are 500
+fn db_error(
+    conn: &str,
+    stmt: &sqlparser::ast::Statement,
+    e: sqlx::Error,
+    detail: ErrorDetail,
+) -> ApiError {
+    log::error!("SQL on connection {}: {}\n{}", conn, stmt, e);
+    if let sqlx::Error::PoolTimedOut = e {
+        return ApiError::Unavailable {
+            msg: "no database connection available, retry later".to_string(),
+            retry_after: Some(POOL_RETRY_AFTER),
+        };
+    }
+    let msg = match detail {
+        ErrorDetail::Full => format!("SQL: {}\n{}", stmt, e),
+        ErrorDetail::Minimal => "query failed".to_string(),
+    };
+    ApiError::database(&e, msg)
+}
+
+/// error of rendering query with request params, full error is always logged and only
+/// sent to client with full error detail, like database error
+fn render_error(name: &str, e: PSqlError, detail: ErrorDetail) -> ApiError {
+    log::warn!("render query {}: {:#?}", name, e);
+    let msg = match detail {
+        ErrorDetail::Full => format!("{:#?}", e),
+        ErrorDetail::Minimal => "query can't be rendered with given params".to_string(),
+    };
+    ApiError::BadRequest(msg)
+}
+
+/// result of insert, update or delete statement
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WriteOutput {
+    pub rows_affected: u64,
+    /// generated id of inserted row, mysql and sqlite only
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_insert_id: Option<u64>,
+}
+
+impl WriteOutput {
+    /// keep `last_insert_id` only for insert statement which inserted rows, mysql reports 0
+    /// and sqlite the connection's previous rowid otherwise
+    pub(crate) fn new(
+        stmt: &sqlparser::ast::Statement,
+        rows_affected: u64,
+        last_insert_id: Option<u64>,
+    ) -> Self {
+        let inserted = StatementKind::of(stmt) == StatementKind::Insert && rows_affected > 0;
+        Self {
+            rows_affected,
+            last_insert_id: last_insert_id.filter(|id| inserted && *id > 0),
+        }
+    }
+}
+
+/// whether statement modifies rows instead of returning them
+fn is_write(stmt: &sqlparser::ast::Statement) -> bool {
+    matches!(
+        StatementKind::of(stmt),
+        StatementKind::Insert | StatementKind::Update | StatementKind::Delete
+    )
+}
+
+/// reply affected rows of write statement
+fn write_reply(
+    written: Result<WriteOutput, sqlx::Error>,
+    conn: &str,
+    stmt: &sqlparser::ast::Statement,
+    query: &Query,
+    detail: ErrorDetail,
+    pretty: bool,
+) -> (warp::reply::Response, Option<usize>) {
+    match written {
+        Ok(output) if output.rows_affected == 0 && query.no_content_on_empty => {
+            let resp = warp::reply::with_status(warp::reply(), StatusCode::NO_CONTENT);
+            (resp.into_response(), Some(0))
+        }
+        Ok(output) => {
+            let resp = json_reply(&output, pretty);
+            (resp, Some(output.rows_affected as usize))
+        }
+        Err(e) => (db_error(conn, stmt, e, detail).reply(pretty), None),
+    }
+}
+
+/// reply the only column of the only row, as raw text of `response_content_type` if set,
+/// otherwise as json value
+fn scalar_reply<R: Row>(
+    output: &QueryOutput<R>,
+    query: &Query,
+    options: SerOptions,
+    pretty: bool,
+) -> warp::reply::Response
+where
+    for<'a> PSqlRowListSer<'a, R>: Serialize,
+{
+    let value = match output.rows.as_slice() {
+        [row] if row.columns().len() == 1 => {
+            serde_json::to_value(PSqlRowListSer(row, options)).map(|mut values| values[0].take())
+        }
+        rows => {
+            let columns = rows.first().map_or(0, |row| row.columns().len());
+            let msg = format!(
+                "expect a single value, got {} rows of {} columns",
+                rows.len(),
+                columns
+            );
+            return ApiError::BadRequest(msg).reply(pretty);
+        }
+    };
+    match (value, &query.response_content_type) {
+        (Ok(value), None) => json_reply(&value, pretty),
+        (Ok(value), Some(content_type)) => {
+            let body = match value {
+                serde_json::Value::String(text) => text,
+                serde_json::Value::Null => String::new(),
+                value => value.to_string(),
             };
-            Ok(warp::reply::with_status(warp::reply::json(&msg), *code))
+            warp::reply::with_header(body, "content-type", content_type.as_str()).into_response()
+        }
+        (Err(e), _) => {
+            log::error!("serialize scalar response failed {}", e);
+            ApiError::Internal(format!("serialize response failed: {}", e)).reply(pretty)
         }
     }
 }
 
-async fn serve_query(
-    method: Method,
-    qs: String,
-    path: warp::path::FullPath,
-    json_body: HashMap<String, ParamValue>,
-    plan_db: PlanDb,
-    mysql_dbs: Arc<Mutex<HashMap<String, MySqlPool>>>,
-    sqlite_dbs: Arc<Mutex<HashMap<String, SqlitePool>>>,
-) -> Result<impl warp::Reply, warp::Rejection> {
-    let plan = plan_db.lock().await;
-    let all_paths: Vec<(String, Query)> = plan
-        .queries
-        .values()
-        .map(|q| (q.path.clone(), q.clone()))
-        .collect();
-    match all_paths.iter().position(|p| path.as_str().ends_with(&p.0)) {
-        Some(idx) => {
-            let query = &all_paths.get(idx).unwrap().1;
-            let prog = query.read_sql().unwrap();
-            let mut code = warp::http::StatusCode::BAD_REQUEST;
-            let may_be_context = match method {
-                Method::POST | Method::PUT | Method::DELETE => {
-                    get_context_from_body(&json_body, &prog)
+/// reply fetched rows, or the only value of them for scalar query
+fn rows_reply<R: Row>(
+    output: QueryOutput<R>,
+    query: &Query,
+    options: SerOptions,
+    context: &HashMap<String, ParamValue>,
+    req: &RequestInfo,
+    pretty: bool,
+) -> (warp::reply::Response, Option<usize>)
+where
+    for<'a> QueryOutputMapSer<'a, R>: Serialize,
+    for<'a> PSqlRowListSer<'a, R>: Serialize,
+{
+    let rows = output.rows.len();
+    if query.scalar {
+        return (scalar_reply(&output, query, options, pretty), Some(rows));
+    }
+    let reply = if req.csv() {
+        csv_reply(&QueryOutputCsvSer(&output, options), pretty)
+    } else {
+        json_reply(&QueryOutputMapSer(&output, options), pretty)
+    };
+    (with_links(reply, query, context, req, rows), Some(rows))
+}
+
+/// reply rows as ndjson lines streamed while they are fetched, see [`stream::ndjson_lines`].
+/// An error before the first row gets the usual error response, later ones end the body early
+#[allow(clippy::too_many_arguments)]
+async fn stream_reply<DB>(
+    pool: sqlx::Pool<DB>,
+    sql: &str,
+    values: &[ParamValue],
+    blob: Option<(&Vec<u8>, usize)>,
+    options: SerOptions<'_>,
+    permit: Option<OwnedSemaphorePermit>,
+    conn: &str,
+    stmt: &sqlparser::ast::Statement,
+    detail: ErrorDetail,
+    pretty: bool,
+) -> warp::reply::Response
+where
+    DB: sqlx::Database,
+    for<'c> &'c mut DB::Connection: sqlx::Executor<'c, Database = DB>,
+    for<'a> <DB as sqlx::database::HasArguments<'a>>::Arguments: sqlx::IntoArguments<'a, DB>,
+    for<'q> String: sqlx::Encode<'q, DB> + sqlx::Type<DB>,
+    for<'q> i64: sqlx::Encode<'q, DB> + sqlx::Type<DB>,
+    for<'q> f64: sqlx::Encode<'q, DB> + sqlx::Type<DB>,
+    for<'q> bool: sqlx::Encode<'q, DB> + sqlx::Type<DB>,
+    for<'q> Option<String>: sqlx::Encode<'q, DB> + sqlx::Type<DB>,
+    for<'q> Vec<u8>: sqlx::Encode<'q, DB> + sqlx::Type<DB>,
+    for<'a> PSqlRowMapSer<'a, DB::Row>: Serialize,
+    for<'a> PSqlRowNestedSer<'a, DB::Row>: Serialize,
+{
+    let blob = blob.map(|(blob, count)| (blob.clone(), count));
+    let lines = stream::ndjson_lines(
+        pool,
+        sql.to_string(),
+        values.to_vec(),
+        blob,
+        options,
+        permit,
+    )
+    .await;
+    match lines {
+        Ok(lines) => {
+            let lines = lines.inspect(|line| {
+                if let Err(e) = line {
+                    log::error!("stream rows failed {}", e);
                 }
-                _ => get_context_from_qs(qs, &prog),
-            };
-            match may_be_context {
-                Ok(context) => {
-                    serve_with_context(
-                        &prog,
-                        plan_db.clone(),
-                        query,
-                        &mut code,
-                        context,
-                        mysql_dbs,
-                        sqlite_dbs,
-                    )
-                    .await
+            });
+            let mut resp = warp::reply::Response::new(warp::hyper::Body::wrap_stream(lines));
+            resp.headers_mut().insert(
+                header::CONTENT_TYPE,
+                HeaderValue::from_static("application/x-ndjson"),
+            );
+            resp
+        }
+        Err(stream::StreamError::Database { source, .. }) => {
+            db_error(conn, stmt, source, detail).reply(pretty)
+        }
+        Err(e) => {
+            log::error!("stream rows failed {}", e);
+            ApiError::Internal(format!("serialize response failed: {}", e)).reply(pretty)
+        }
+    }
+}
+
+/// run query between its hooks and after `leading` statements in one transaction, which is
+/// rolled back if any of them fails
+async fn run_in_transaction<'q, DB>(
+    pool: &sqlx::Pool<DB>,
+    hooks: &Hooks,
+    leading: &[String],
+    query: sqlx::query::Query<'q, DB, <DB as sqlx::database::HasArguments<'q>>::Arguments>,
+    write: bool,
+) -> Result<sqlx::Either<DB::QueryResult, Vec<DB::Row>>, sqlx::Error>
+where
+    DB: sqlx::Database,
+    for<'c> &'c mut DB::Connection: sqlx::Executor<'c, Database = DB>,
+    for<'a> <DB as sqlx::database::HasArguments<'a>>::Arguments: sqlx::IntoArguments<'a, DB>,
+{
+    let mut tx = pool.begin().await?;
+    for sql in hooks.before.iter().chain(leading) {
+        sqlx::query(sql).execute(&mut *tx).await?;
+    }
+    let done = if write {
+        sqlx::Either::Left(query.execute(&mut *tx).await?)
+    } else {
+        sqlx::Either::Right(query.fetch_all(&mut *tx).await?)
+    };
+    for sql in hooks.after.iter() {
+        sqlx::query(sql).execute(&mut *tx).await?;
+    }
+    tx.commit().await?;
+    Ok(done)
+}
+
+/// rendered query with request state, which is run the same way on every dialect
+struct Dispatch<'a> {
+    query: &'a Query,
+    sql: &'a str,
+    values: &'a [ParamValue],
+    /// statements run before the last one of transactional query
+    leading: &'a [String],
+    blob: Option<(&'a Vec<u8>, usize)>,
+    hooks: &'a Hooks,
+    stmt: &'a sqlparser::ast::Statement,
+    write: bool,
+    describe: bool,
+    stream: bool,
+    options: SerOptions<'a>,
+    context: &'a HashMap<String, ParamValue>,
+    req: &'a RequestInfo,
+    label: &'a str,
+    detail: ErrorDetail,
+    pretty: bool,
+}
+
+/// describe, stream, run in transaction, execute or fetch query on `pool`. `affected` reads
+/// rows affected and last insert id from write result, which differ between dialects
+async fn dispatch_query<DB>(
+    pool: sqlx::Pool<DB>,
+    permit: Option<OwnedSemaphorePermit>,
+    affected: fn(&DB::QueryResult) -> (u64, Option<u64>),
+    d: &Dispatch<'_>,
+) -> (warp::reply::Response, Option<usize>)
+where
+    DB: sqlx::Database,
+    for<'c> &'c mut DB::Connection: sqlx::Executor<'c, Database = DB>,
+    for<'c> &'c sqlx::Pool<DB>: sqlx::Executor<'c, Database = DB>,
+    for<'a> <DB as sqlx::database::HasArguments<'a>>::Arguments: sqlx::IntoArguments<'a, DB>,
+    for<'q> String: sqlx::Encode<'q, DB> + sqlx::Type<DB>,
+    for<'q> i64: sqlx::Encode<'q, DB> + sqlx::Type<DB>,
+    for<'q> f64: sqlx::Encode<'q, DB> + sqlx::Type<DB>,
+    for<'q> bool: sqlx::Encode<'q, DB> + sqlx::Type<DB>,
+    for<'q> Option<String>: sqlx::Encode<'q, DB> + sqlx::Type<DB>,
+    for<'q> Vec<u8>: sqlx::Encode<'q, DB> + sqlx::Type<DB>,
+    for<'a> PSqlRowMapSer<'a, DB::Row>: Serialize,
+    for<'a> PSqlRowNestedSer<'a, DB::Row>: Serialize,
+    for<'a> QueryOutputMapSer<'a, DB::Row>: Serialize,
+    for<'a> PSqlRowListSer<'a, DB::Row>: Serialize,
+{
+    let (query, stmt, label, detail, pretty) = (d.query, d.stmt, d.label, d.detail, d.pretty);
+    let written = |done: &DB::QueryResult| {
+        let (rows_affected, last_insert_id) = affected(done);
+        WriteOutput::new(stmt, rows_affected, last_insert_id)
+    };
+    if d.describe {
+        let described = output::describe(&pool, d.sql).await;
+        return (describe_reply(described, label, stmt, detail, pretty), None);
+    }
+    if d.stream {
+        let resp = stream_reply(
+            pool, d.sql, d.values, d.blob, d.options, permit, label, stmt, detail, pretty,
+        );
+        return (resp.await, None);
+    }
+    let bound = bind_values(bind_blob(sqlx::query(d.sql), d.blob), d.values);
+    if !d.hooks.is_empty() || query.transactional {
+        return match run_in_transaction(&pool, d.hooks, d.leading, bound, d.write).await {
+            Ok(sqlx::Either::Left(done)) => {
+                write_reply(Ok(written(&done)), label, stmt, query, detail, pretty)
+            }
+            Ok(sqlx::Either::Right(rows)) => rows_reply(
+                QueryOutput { rows },
+                query,
+                d.options,
+                d.context,
+                d.req,
+                pretty,
+            ),
+            Err(e) => (db_error(label, stmt, e, detail).reply(pretty), None),
+        };
+    }
+    if d.write {
+        let done = bound.execute(&pool).await.map(|done| written(&done));
+        return write_reply(done, label, stmt, query, detail, pretty);
+    }
+    match bound.fetch_all(&pool).await {
+        Ok(rows) => rows_reply(
+            QueryOutput { rows },
+            query,
+            d.options,
+            d.context,
+            d.req,
+            pretty,
+        ),
+        Err(e) => (db_error(label, stmt, e, detail).reply(pretty), None),
+    }
+}
+
+/// attach pagination `Link` header to successful response
+fn with_links(
+    reply: impl Reply,
+    query: &Query,
+    context: &HashMap<String, ParamValue>,
+    req: &RequestInfo,
+    rows: usize,
+) -> warp::reply::Response {
+    let mut resp = reply.into_response();
+    let link = match (&query.pagination, &query.method) {
+        (Some(pagination), plan::Method::Get) => {
+            pagination::link_header(pagination, context, req, rows)
+        }
+        _ => None,
+    };
+    if let Some(link) = link.and_then(|link| link.parse().ok()) {
+        resp.headers_mut().insert("link", link);
+    }
+    resp
+}
+
+/// rendered in place of blob param, then replaced by `?` placeholder which blob is bound to
+const BLOB_MARKER: &str = "__psql_blob__";
+
+/// replace blob markers with placeholders, which are numbered on postgres, and count them.
+/// Queries taking blob are sent as rendered sql, so blob is the only bound value. Markers in
+/// quoted literals are values of other params and kept as is
+fn blob_placeholders(sql: &str, dialect: &Dialect) -> (String, usize) {
+    let mut out = String::with_capacity(sql.len());
+    let mut count = 0;
+    let mut quote = None;
+    let mut rest = sql;
+    while let Some(c) = rest.chars().next() {
+        match quote {
+            None if rest.starts_with(BLOB_MARKER) => {
+                count += 1;
+                match dialect {
+                    Dialect::Mysql | Dialect::Sqlite => out.push('?'),
+                    Dialect::Postgres => out.push_str(&format!("${}", count)),
                 }
-                Err(msg) => Ok(warp::reply::with_status(
-                    warp::reply::json(&msg),
-                    StatusCode::from_u16(msg.code).unwrap(),
-                )),
+                rest = &rest[BLOB_MARKER.len()..];
+                continue;
             }
+            // doubled quote inside literal closes and reopens it, which keeps the state right
+            None if matches!(c, '\'' | '"' | '`') => quote = Some(c),
+            Some(q) if c == q => quote = None,
+            _ => {}
         }
-        None => {
-            let status = warp::http::StatusCode::BAD_REQUEST;
-            let msg = ApiMsg {
-                msg: format!("{} not found", path.as_str()),
-                code: 404,
-            };
-            Ok(warp::reply::with_status(warp::reply::json(&msg), status))
+        out.push(c);
+        rest = &rest[c.len_utf8()..];
+    }
+    (out, count)
+}
+
+/// bind blob to each of its placeholders, blob param may be used more than once
+fn bind_blob<'q, DB: sqlx::Database>(
+    mut query: sqlx::query::Query<'q, DB, <DB as sqlx::database::HasArguments<'q>>::Arguments>,
+    blob: Option<(&Vec<u8>, usize)>,
+) -> sqlx::query::Query<'q, DB, <DB as sqlx::database::HasArguments<'q>>::Arguments>
+where
+    Vec<u8>: sqlx::Encode<'q, DB> + sqlx::Type<DB>,
+{
+    if let Some((blob, count)) = blob {
+        for _ in 0..count {
+            query = query.bind(blob.clone());
         }
     }
+    query
 }
 
-pub async fn run_dynamic_http(
-    plan: Plan,
-    mysql_conns: HashMap<String, sqlx::MySqlPool>,
-    sqlite_conns: HashMap<String, sqlx::SqlitePool>,
-) -> Result<(), ()> {
-    let prefix = plan.prefix.clone();
-    let query_prefix = prefix.clone();
-    let doc_path = plan.doc_path.clone();
-    let mysql_dbs = Arc::new(Mutex::new(mysql_conns));
-    let sqlite_dbs = Arc::new(Mutex::new(sqlite_conns));
-    let plan_db = Arc::new(Mutex::new(plan.clone()));
-    let plan_doc = plan_db.clone();
-    let doc_route = warp::get()
-        .and(warp::path(prefix.clone()))
-        .and(warp::path(plan.doc_path.clone()))
-        .and(warp::any().map(move || plan_doc.clone()))
-        .and_then(dynamic_doc);
-    let index = warp::get()
-        .and(warp::path("index"))
-        .and(warp::any().map(move || format!("{}/{}", &prefix.clone(), &doc_path)))
-        .and_then(index::serve_index);
-    let favicon = warp::get()
-        .and(warp::path("favicon.ico"))
-        .and_then(index::favicon);
-    let plan_c = plan_db.clone();
-    let explore_status_route = warp::get()
-        .and(warp::path(query_prefix.clone()))
+/// run sql by text protocol, which allows several statements, and split rows into
+/// result sets. Database ends every set with a query result, so empty sets are kept
+async fn fetch_result_sets(
+    pool: &MySqlPool,
+    sql: &str,
+) -> Result<Vec<QueryOutput<MySqlRow>>, sqlx::Error> {
+    use futures::TryStreamExt;
+    use sqlx::{Either, Executor};
+    let mut sets = vec![];
+    let mut rows = vec![];
+    let mut results = pool.fetch_many(sql);
+    while let Some(result) = results.try_next().await? {
+        match result {
+            Either::Left(_) => sets.push(QueryOutput {
+                rows: std::mem::take(&mut rows),
+            }),
+            Either::Right(row) => rows.push(row),
+        }
+    }
+    Ok(sets)
+}
+
+/// bind values of prepared statement in placeholder order, integral numbers are bound
+/// as integer, so they can be used by `LIMIT` too
+fn bind_values<'q, DB: sqlx::Database>(
+    mut query: sqlx::query::Query<'q, DB, <DB as sqlx::database::HasArguments<'q>>::Arguments>,
+    values: &[ParamValue],
+) -> sqlx::query::Query<'q, DB, <DB as sqlx::database::HasArguments<'q>>::Arguments>
+where
+    String: sqlx::Encode<'q, DB> + sqlx::Type<DB>,
+    i64: sqlx::Encode<'q, DB> + sqlx::Type<DB>,
+    f64: sqlx::Encode<'q, DB> + sqlx::Type<DB>,
+    bool: sqlx::Encode<'q, DB> + sqlx::Type<DB>,
+    Option<String>: sqlx::Encode<'q, DB> + sqlx::Type<DB>,
+{
+    // max integer f64 holds exactly
+    const MAX_EXACT: f64 = 9007199254740992.0;
+    for value in values {
+        query = match value {
+            ParamValue::Str(val) | ParamValue::Raw(val) | ParamValue::Ident(val) => {
+                query.bind(val.clone())
+            }
+            ParamValue::Num(num) if num.fract() == 0.0 && num.abs() <= MAX_EXACT => {
+                query.bind(*num as i64)
+            }
+            ParamValue::Num(num) => query.bind(*num),
+            ParamValue::Bool(val) => query.bind(*val),
+            ParamValue::Null => query.bind(None::<String>),
+            ParamValue::Array(items) => bind_values(query, items),
+        };
+    }
+    query
+}
+
+/// prepend `/* query: <name> */` to sql, so it can be traced in database and sqlx logs
+fn tag_sql(name: &str, sql: impl std::fmt::Display) -> String {
+    format!("/* query: {} */ {}", name.replace("*/", "* /"), sql)
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn serve_with_context(
+    name: &str,
+    prog: &Program,
+    dialect: &Dialect,
+    plan_db: PlanDb,
+    query: &Query,
+    detail: ErrorDetail,
+    tag_queries: bool,
+    context: HashMap<String, ParamValue>,
+    blob: Option<Vec<u8>>,
+    req: &RequestInfo,
+    audit: Option<Arc<AuditLog>>,
+    mysql_dbs: Arc<Mutex<HashMap<String, MySqlPool>>>,
+    sqlite_dbs: Arc<Mutex<HashMap<String, SqlitePool>>>,
+    pg_dbs: Arc<Mutex<HashMap<String, PgPool>>>,
+    limiter: Option<ConnLimiter>,
+) -> Result<warp::reply::Response, warp::Rejection> {
+    let mut permit = match &limiter {
+        Some(limiter) => limiter.acquire().await.map(Some),
+        None => Ok(None),
+    };
+    let pretty = req.pretty();
+    let (label, declared, offset, datetime_format, decimal_as_number) = {
+        let plan = plan_db.lock().await;
+        let declared = plan.conn_dialect(&query.conn).is_some();
+        let offset = plan.conn_offset(&query.conn);
+        let label = plan.conn_label(&query.conn).to_string();
+        let datetime_format = plan.datetime_format.clone();
+        (
+            label,
+            declared,
+            offset,
+            datetime_format,
+            plan.decimal_as_number,
+        )
+    };
+    let options = SerOptions {
+        offset: Some(offset),
+        datetime_format: datetime_format.as_deref(),
+        decimal_as_number,
+        ..query.ser_options()
+    };
+    let rendered = prog
+        .render(dialect.sqlparser_dialect().as_ref(), &context)
+        .map(|mut stmts| {
+            query.expand_stars(&mut stmts, dialect);
+            stmts
+        })
+        .and_then(|stmts| Ok((stmts, query.render_hooks(prog, dialect, &context)?)))
+        .and_then(|(stmts, hooks)| {
+            let prepared = if query.prepared(prog) {
+                Some(prog.render_prepared(dialect.sqlparser_dialect().as_ref(), &context)?)
+            } else {
+                None
+            };
+            Ok((stmts, hooks, prepared))
+        });
+    let (resp, rows) = match rendered {
+        _ if !declared => (conn_not_found(&query.conn).reply(pretty), None),
+        _ if permit.is_err() => (conn_busy(&query.conn).reply(pretty), None),
+        Ok((stmts, _, _))
+            if stmts.is_empty()
+                || (stmts.len() != 1 && !query.multi_result && !query.transactional) =>
+        {
+            let e = ApiError::BadRequest(format!("expect 1 sql statement, got {}", stmts.len()));
+            (e.reply(pretty), None)
+        }
+        Ok((stmts, hooks, prepared)) => {
+            // transactional query responds its last statement
+            let stmt = if query.transactional {
+                stmts.last().unwrap()
+            } else {
+                stmts.first().unwrap()
+            };
+            let (sql, values, leading) = match prepared {
+                Some((sql, values)) if tag_queries => (tag_sql(name, sql.trim()), values, vec![]),
+                Some((sql, values)) => (sql.trim().to_string(), values, vec![]),
+                None => {
+                    let mut texts = stmts
+                        .iter()
+                        .map(|stmt| {
+                            if tag_queries {
+                                tag_sql(name, stmt)
+                            } else {
+                                stmt.to_string()
+                            }
+                        })
+                        .collect::<Vec<_>>();
+                    if query.transactional {
+                        (texts.pop().unwrap(), vec![], texts)
+                    } else {
+                        (texts.join(";\n"), vec![], vec![])
+                    }
+                }
+            };
+            let (sql, count) = match query.blob {
+                Some(_) => blob_placeholders(&sql, dialect),
+                None => (sql, 0),
+            };
+            let blob = blob.as_ref().map(|blob| (blob, count));
+            let describe = req.flag("__describe");
+            let write = is_write(stmt);
+            // rows streamed as they are fetched, only plain reads outside of transaction. Pages
+            // are buffered, as their `Link` header and audit record need the row count
+            let stream = req.ndjson()
+                && !req.csv()
+                && !write
+                && !query.scalar
+                && !query.multi_result
+                && !query.transactional
+                && query.pagination.is_none()
+                && hooks.is_empty();
+            let run = async {
+                let dispatch = Dispatch {
+                    query,
+                    sql: &sql,
+                    values: &values,
+                    leading: &leading,
+                    blob,
+                    hooks: &hooks,
+                    stmt,
+                    write,
+                    describe,
+                    stream,
+                    options,
+                    context: &context,
+                    req,
+                    label: &label,
+                    detail,
+                    pretty,
+                };
+                // streamed rows hold the slot until the last one is sent
+                let permit = permit.as_mut().ok().and_then(Option::take);
+                // bind pool first so map lock is released before query runs
+                match dialect {
+                    Dialect::Mysql => {
+                        let pool = mysql_dbs.lock().await.get(&query.conn).cloned();
+                        match pool {
+                            Some(pool) if query.multi_result && !describe => {
+                                match fetch_result_sets(&pool, &sql).await {
+                                    Ok(sets) => {
+                                        let reply =
+                                            json_reply(&MultiOutputSer(&sets, options), pretty);
+                                        let rows = sets.iter().map(|set| set.rows.len()).sum();
+                                        (reply, Some(rows))
+                                    }
+                                    Err(e) => {
+                                        (db_error(&label, stmt, e, detail).reply(pretty), None)
+                                    }
+                                }
+                            }
+                            Some(pool) => {
+                                let affected = |done: &sqlx::mysql::MySqlQueryResult| {
+                                    (done.rows_affected(), Some(done.last_insert_id()))
+                                };
+                                dispatch_query(pool, permit, affected, &dispatch).await
+                            }
+                            None => (conn_unavailable(&query.conn).reply(pretty), None),
+                        }
+                    }
+                    Dialect::Sqlite => {
+                        let pool = sqlite_dbs.lock().await.get(&query.conn).cloned();
+                        match pool {
+                            Some(pool) => {
+                                let affected = |done: &sqlx::sqlite::SqliteQueryResult| {
+                                    (done.rows_affected(), Some(done.last_insert_rowid() as u64))
+                                };
+                                dispatch_query(pool, permit, affected, &dispatch).await
+                            }
+                            None => (conn_unavailable(&query.conn).reply(pretty), None),
+                        }
+                    }
+                    Dialect::Postgres => {
+                        let pool = pg_dbs.lock().await.get(&query.conn).cloned();
+                        match pool {
+                            Some(pool) => {
+                                let affected = |done: &sqlx::postgres::PgQueryResult| {
+                                    (done.rows_affected(), None)
+                                };
+                                dispatch_query(pool, permit, affected, &dispatch).await
+                            }
+                            None => (conn_unavailable(&query.conn).reply(pretty), None),
+                        }
+                    }
+                }
+            };
+            match query.timeout_ms {
+                Some(ms) => tokio::time::timeout(Duration::from_millis(ms), run)
+                    .await
+                    .unwrap_or_else(|_| {
+                        log::warn!("query {} timed out after {} ms", name, ms);
+                        (ApiError::Timeout(ms).reply(pretty), None)
+                    }),
+                None => run.await,
+            }
+        }
+        Err(e) => (render_error(name, e, detail).reply(pretty), None),
+    };
+    let mut resp = resp;
+    if resp.status().is_success() {
+        if let Some(value) = query.cache_control().and_then(|v| v.parse().ok()) {
+            resp.headers_mut().insert("cache-control", value);
+        }
+    }
+    if let Some(audit) = audit {
+        audit
+            .record(name, &label, req, &context, rows, resp.status().as_u16())
+            .await;
+    }
+    Ok(resp)
+}
+
+/// header to invoke GET query by POST, for clients which can't send query string
+const METHOD_OVERRIDE_HEADER: &str = "X-HTTP-Method-Override";
+
+/// whether request is POST with `X-HTTP-Method-Override: GET`, other overrides are rejected
+fn tunneled_get(method: &Method, headers: &HeaderMap) -> Result<bool, ApiError> {
+    match headers.get(METHOD_OVERRIDE_HEADER) {
+        None => Ok(false),
+        Some(value) if method == Method::POST && value.as_bytes().eq_ignore_ascii_case(b"GET") => {
+            Ok(true)
+        }
+        Some(value) => Err(ApiError::BadRequest(format!(
+            "unsupported {} {:?} for {}",
+            METHOD_OVERRIDE_HEADER, value, method
+        ))),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn serve_query(
+    method: Method,
+    qs: String,
+    path: warp::path::FullPath,
+    headers: HeaderMap,
+    remote: Option<SocketAddr>,
+    body: BodyParams,
+    plan_db: PlanDb,
+    audit: Option<Arc<AuditLog>>,
+    mysql_dbs: Arc<Mutex<HashMap<String, MySqlPool>>>,
+    sqlite_dbs: Arc<Mutex<HashMap<String, SqlitePool>>>,
+    pg_dbs: Arc<Mutex<HashMap<String, PgPool>>>,
+    limiters: Limiters,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let plan = plan_db.lock().await;
+    // preflight requests don't carry api key, so they are answered before authorization
+    if method == Method::OPTIONS {
+        return options_reply(&plan, path.as_str());
+    }
+    let key = headers
+        .get(plan::API_KEY_HEADER)
+        .and_then(|v| v.to_str().ok());
+    if !plan.authorized(key) {
+        return Err(unauthorized().into());
+    }
+    let found = plan
+        .find_query(path.as_str())
+        .map(|(name, q)| (name.clone(), q.clone()));
+    match found {
+        Some((name, query)) => {
+            let (name, query) = (&name, &query);
+            let dialect = plan
+                .conn_dialect(&query.conn)
+                .ok_or_else(|| conn_not_found(&query.conn))?;
+            let prog = plan
+                .program(name, query)
+                .map_err(|e| ApiError::Internal(e.to_string()))?
+                .into_owned();
+            let tunneled = tunneled_get(&method, &headers)?;
+            if tunneled && query.method != plan::Method::Get {
+                return Err(ApiError::BadRequest(format!(
+                    "{} only applies to GET queries",
+                    METHOD_OVERRIDE_HEADER
+                ))
+                .into());
+            }
+            // tunneled form body is handled as if it's query string
+            let qs = match (tunneled, &body.form) {
+                (true, Some(form)) if qs.is_empty() => form.replace('+', "%20"),
+                (true, Some(form)) => format!("{}&{}", qs, form.replace('+', "%20")),
+                _ => qs,
+            };
+            // blob param is bound to body, drop it from query string
+            let qs = match &query.blob {
+                Some(blob) => qs
+                    .split('&')
+                    .filter(|pair| pair.split('=').next() != Some(blob.param.as_str()))
+                    .collect::<Vec<_>>()
+                    .join("&"),
+                None => qs,
+            };
+            let req = RequestInfo {
+                path: path.as_str().to_string(),
+                qs: qs.clone(),
+                headers,
+                remote,
+            };
+            // request value > connection param > param default
+            let mut defaults = plan.conn_param_values(&query.conn);
+            if let Some(blob) = &query.blob {
+                if body.blob.is_none() {
+                    return Err(ApiError::BadRequest(format!(
+                        "{} expects binary request body",
+                        name
+                    ))
+                    .into());
+                }
+                defaults.insert(blob.param.clone(), ParamValue::Raw(BLOB_MARKER.to_string()));
+            }
+            let context = match method {
+                _ if query.blob.is_some() => get_context_from_qs(qs, &prog, &defaults),
+                _ if tunneled && body.form.is_some() => get_context_from_qs(qs, &prog, &defaults),
+                Method::POST | Method::PUT | Method::DELETE => {
+                    get_context_from_body(&body.params, &prog, &defaults)
+                }
+                _ => get_context_from_qs(qs, &prog, &defaults),
+            }?;
+            let error_detail = plan.error_detail;
+            let tag_queries = plan.tag_queries;
+            // release plan before running query, so queries don't wait for each other
+            drop(plan);
+            serve_with_context(
+                name,
+                &prog,
+                &dialect,
+                plan_db.clone(),
+                query,
+                error_detail,
+                tag_queries,
+                context,
+                body.blob,
+                &req,
+                audit,
+                mysql_dbs,
+                sqlite_dbs,
+                pg_dbs,
+                limiters.get(&query.conn).cloned(),
+            )
+            .await
+        }
+        None => Err(ApiError::NotFound(format!("{} not found", path.as_str())).into()),
+    }
+}
+
+/// `204` with `Allow` header listing method of query matching path
+fn options_reply(plan: &Plan, path: &str) -> Result<warp::reply::Response, warp::Rejection> {
+    let (_, query) = plan
+        .find_query(path)
+        .ok_or_else(|| ApiError::NotFound(format!("{} not found", path)))?;
+    let allow = format!(
+        "{}, {}",
+        Method::from(query.method.clone()),
+        Method::OPTIONS
+    );
+    let mut resp = warp::reply::with_status(warp::reply(), StatusCode::NO_CONTENT).into_response();
+    resp.headers_mut()
+        .insert(header::ALLOW, HeaderValue::from_str(&allow).unwrap());
+    Ok(resp)
+}
+
+/// blob settings and body limit of blob query matching method and path,
+/// other requests are rejected so they fall through to `serve_query`
+async fn blob_query(
+    method: Method,
+    path: warp::path::FullPath,
+    plan_db: PlanDb,
+) -> Result<(plan::Blob, u64), warp::Rejection> {
+    let plan = plan_db.lock().await;
+    plan.find_query(path.as_str())
+        .map(|(_, q)| q)
+        .filter(|q| Method::from(q.method.clone()) == method)
+        .and_then(|q| q.blob.clone())
+        .map(|blob| {
+            let limit = blob.limit.unwrap_or(plan.body_limit);
+            (blob, limit)
+        })
+        .ok_or_else(warp::reject::not_found)
+}
+
+/// stream request body of blob query, errors are replied here instead of rejected,
+/// otherwise they would fall through to `serve_query`
+#[allow(clippy::too_many_arguments)]
+async fn serve_blob<S, B>(
+    (blob, limit): (plan::Blob, u64),
+    method: Method,
+    qs: String,
+    path: warp::path::FullPath,
+    headers: HeaderMap,
+    remote: Option<SocketAddr>,
+    length: Option<u64>,
+    stream: S,
+    plan_db: PlanDb,
+    audit: Option<Arc<AuditLog>>,
+    mysql_dbs: Arc<Mutex<HashMap<String, MySqlPool>>>,
+    sqlite_dbs: Arc<Mutex<HashMap<String, SqlitePool>>>,
+    pg_dbs: Arc<Mutex<HashMap<String, PgPool>>>,
+    limiters: Limiters,
+) -> Result<warp::reply::Response, warp::Rejection>
+where
+    S: futures::Stream<Item = Result<B, warp::Error>>,
+    B: Buf,
+{
+    let pretty = wants_pretty(&qs, &headers);
+    // check api key before reading body
+    let key = headers
+        .get(plan::API_KEY_HEADER)
+        .and_then(|v| v.to_str().ok());
+    if !plan_db.lock().await.authorized(key) {
+        return Ok(unauthorized().reply(pretty));
+    }
+    let content_type = headers
+        .get(warp::http::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok());
+    if !blob.accepts(content_type) {
+        return Ok(ApiError::UnsupportedMediaType(format!(
+            "content type {} is not accepted by {}",
+            content_type.unwrap_or("<none>"),
+            path.as_str()
+        ))
+        .reply(pretty));
+    }
+    let body = match read_body(length, stream, limit).await {
+        Ok(body) => BodyParams {
+            blob: Some(body),
+            ..Default::default()
+        },
+        Err(e) => return error::handle_rejection(pretty, e).await,
+    };
+    let served = serve_query(
+        method, qs, path, headers, remote, body, plan_db, audit, mysql_dbs, sqlite_dbs, pg_dbs,
+        limiters,
+    )
+    .await;
+    match served {
+        Ok(reply) => Ok(reply.into_response()),
+        Err(e) => error::handle_rejection(pretty, e).await,
+    }
+}
+
+/// default seconds between two runs of sse query
+const SSE_INTERVAL: u64 = 5;
+/// bounds of `__interval` seconds of sse query
+const SSE_INTERVAL_RANGE: std::ops::RangeInclusive<u64> = 1..=3600;
+
+/// re-run named query every `__interval` seconds and emit each result as sse event,
+/// stops when client disconnects
+#[allow(clippy::too_many_arguments)]
+async fn serve_sse(
+    name: String,
+    qs: String,
+    path: warp::path::FullPath,
+    headers: HeaderMap,
+    remote: Option<SocketAddr>,
+    plan_db: PlanDb,
+    audit: Option<Arc<AuditLog>>,
+    mysql_dbs: Arc<Mutex<HashMap<String, MySqlPool>>>,
+    sqlite_dbs: Arc<Mutex<HashMap<String, SqlitePool>>>,
+    pg_dbs: Arc<Mutex<HashMap<String, PgPool>>>,
+    limiters: Limiters,
+    shutdown: Shutdown,
+) -> Result<warp::reply::Response, warp::Rejection> {
+    let plan = plan_db.lock().await;
+    let key = headers
+        .get(plan::API_KEY_HEADER)
+        .and_then(|v| v.to_str().ok());
+    if !plan.authorized(key) {
+        return Err(unauthorized().into());
+    }
+    let query = match plan.queries.get(&name) {
+        Some(query) => query.clone(),
+        None => return Err(ApiError::NotFound(format!("query {} not found", name)).into()),
+    };
+    let interval = match querify(&qs).iter().find(|(k, _)| *k == "__interval") {
+        Some((_, v)) => match v.parse::<u64>() {
+            Ok(secs) if SSE_INTERVAL_RANGE.contains(&secs) => secs,
+            _ => {
+                let msg = format!(
+                    "__interval should be seconds in {}..={}",
+                    SSE_INTERVAL_RANGE.start(),
+                    SSE_INTERVAL_RANGE.end()
+                );
+                return Err(ApiError::BadRequest(msg).into());
+            }
+        },
+        None => SSE_INTERVAL,
+    };
+    let dialect = plan
+        .conn_dialect(&query.conn)
+        .ok_or_else(|| conn_not_found(&query.conn))?;
+    let prog = plan
+        .program(&name, &query)
+        .map_err(|e| ApiError::Internal(e.to_string()))?
+        .into_owned();
+    let defaults = plan.conn_param_values(&query.conn);
+    let error_detail = plan.error_detail;
+    let tag_queries = plan.tag_queries;
+    let limiter = limiters.get(&query.conn).cloned();
+    drop(plan);
+    let context = get_context_from_qs(qs.clone(), &prog, &defaults)?;
+    let req = RequestInfo {
+        path: path.as_str().to_string(),
+        qs,
+        headers,
+        remote,
+    };
+    let ticker = tokio::time::interval(std::time::Duration::from_secs(interval));
+    let events = futures::stream::unfold(Some(ticker), move |ticker| {
+        let (name, prog, dialect, query, context, req, limiter) = (
+            name.clone(),
+            prog.clone(),
+            dialect.clone(),
+            query.clone(),
+            context.clone(),
+            req.clone(),
+            limiter.clone(),
+        );
+        let (plan_db, audit, mysql_dbs, sqlite_dbs, pg_dbs) = (
+            plan_db.clone(),
+            audit.clone(),
+            mysql_dbs.clone(),
+            sqlite_dbs.clone(),
+            pg_dbs.clone(),
+        );
+        async move {
+            let mut ticker = ticker?;
+            ticker.tick().await;
+            let run = serve_with_context(
+                &name,
+                &prog,
+                &dialect,
+                plan_db,
+                &query,
+                error_detail,
+                tag_queries,
+                context,
+                None,
+                &req,
+                audit,
+                mysql_dbs,
+                sqlite_dbs,
+                pg_dbs,
+                limiter,
+            )
+            .await;
+            let (event, more) = sse_event(run).await;
+            Some((Ok::<_, Infallible>(event), more.then_some(ticker)))
+        }
+    });
+    // end stream on shutdown, otherwise graceful shutdown waits for it forever
+    let events = events.take_until(stopped(shutdown));
+    Ok(warp::sse::reply(warp::sse::keep_alive().stream(events)).into_response())
+}
+
+/// event of one sse run, `result` for success and `error` for failure, and whether stream
+/// goes on. A rejected run or unreadable body is sent as the last `error` event, in the same
+/// json as error response, so client knows why stream ends
+async fn sse_event(
+    run: Result<warp::reply::Response, warp::Rejection>,
+) -> (warp::sse::Event, bool) {
+    let body = match run {
+        Ok(resp) => {
+            let kind = if resp.status().is_success() {
+                "result"
+            } else {
+                "error"
+            };
+            match warp::hyper::body::to_bytes(resp.into_body()).await {
+                Ok(body) => Ok((kind, body)),
+                Err(e) => Err(ApiError::Internal(e.to_string())),
+            }
+        }
+        Err(rejection) => Err(rejection
+            .find::<ApiError>()
+            .cloned()
+            .unwrap_or_else(|| ApiError::Internal(format!("{:?}", rejection)))),
+    };
+    match body {
+        Ok((kind, body)) => {
+            let event = warp::sse::Event::default()
+                .event(kind)
+                .data(String::from_utf8_lossy(&body));
+            (event, true)
+        }
+        Err(err) => {
+            let msg = ApiMsg {
+                msg: err.msg(),
+                code: err.status().as_u16(),
+            };
+            let event = warp::sse::Event::default()
+                .event("error")
+                .data(serde_json::to_string(&msg).unwrap_or_default());
+            (event, false)
+        }
+    }
+}
+
+/// api key is required but missing or wrong
+fn unauthorized() -> ApiError {
+    ApiError::Unauthorized(format!(
+        "missing or invalid {} header",
+        plan::API_KEY_HEADER
+    ))
+}
+
+/// `401` reply when plan has api keys but request has no valid `X-API-Key` header, replied
+/// instead of rejected, otherwise the request would fall through to `serve_query`
+async fn check_api_key(headers: &HeaderMap, plan_db: &PlanDb) -> Result<(), warp::reply::Response> {
+    let key = headers
+        .get(plan::API_KEY_HEADER)
+        .and_then(|v| v.to_str().ok());
+    if plan_db.lock().await.authorized(key) {
+        Ok(())
+    } else {
+        Err(unauthorized().reply(wants_pretty("", headers)))
+    }
+}
+
+/// user defined routes served along with generated routes
+pub type ExtraRoutes = BoxedFilter<(Box<dyn Reply>,)>;
+
+/// shutdown signal shared by servers of every plan address
+type Shutdown = tokio::sync::watch::Receiver<bool>;
+
+/// resolves when shutdown is signaled
+async fn stopped(mut shutdown: Shutdown) {
+    while !*shutdown.borrow() {
+        if shutdown.changed().await.is_err() {
+            return;
+        }
+    }
+}
+
+fn extra_response(reply: Box<dyn Reply>) -> warp::reply::Response {
+    reply.into_response()
+}
+
+/// closes connection pools, explicitly after graceful shutdown
+/// or on drop when server future is cancelled, e.g. in tests
+struct PoolGuard {
+    mysql_dbs: Arc<Mutex<HashMap<String, MySqlPool>>>,
+    sqlite_dbs: Arc<Mutex<HashMap<String, SqlitePool>>>,
+    pg_dbs: Arc<Mutex<HashMap<String, PgPool>>>,
+}
+
+impl PoolGuard {
+    async fn close(&self) {
+        for pool in self.mysql_dbs.lock().await.values() {
+            pool.close().await;
+        }
+        for pool in self.sqlite_dbs.lock().await.values() {
+            pool.close().await;
+        }
+        for pool in self.pg_dbs.lock().await.values() {
+            pool.close().await;
+        }
+    }
+}
+
+impl Drop for PoolGuard {
+    fn drop(&mut self) {
+        let mysql_pools = match self.mysql_dbs.try_lock() {
+            Some(dbs) => dbs.values().filter(|p| !p.is_closed()).cloned().collect(),
+            None => vec![],
+        };
+        let sqlite_pools = match self.sqlite_dbs.try_lock() {
+            Some(dbs) => dbs.values().filter(|p| !p.is_closed()).cloned().collect(),
+            None => vec![],
+        };
+        let pg_pools = match self.pg_dbs.try_lock() {
+            Some(dbs) => dbs.values().filter(|p| !p.is_closed()).cloned().collect(),
+            None => vec![],
+        };
+        if mysql_pools.is_empty() && sqlite_pools.is_empty() && pg_pools.is_empty() {
+            return;
+        }
+        // closing is async, it can only be done in background of current runtime
+        if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+            runtime.spawn(async move {
+                for pool in mysql_pools {
+                    pool.close().await;
+                }
+                for pool in sqlite_pools {
+                    pool.close().await;
+                }
+                for pool in pg_pools {
+                    pool.close().await;
+                }
+            });
+        }
+    }
+}
+
+/// server spawned by [`spawn_dynamic_http`], server is shut down when it's dropped
+pub struct ServerHandle {
+    addrs: Vec<SocketAddr>,
+    stop: tokio::sync::oneshot::Sender<()>,
+    task: tokio::task::JoinHandle<()>,
+}
+
+impl ServerHandle {
+    /// bound addresses, in order of plan addresses, port 0 is replaced with actual port
+    pub fn addrs(&self) -> &[SocketAddr] {
+        &self.addrs
+    }
+
+    /// stop server, wait for in-flight requests to finish and connection pools to close
+    pub async fn shutdown(self) {
+        let _ = self.stop.send(());
+        if let Err(e) = self.task.await {
+            log::error!("server task failed {}", e);
+        }
+    }
+}
+
+/// bind plan addresses and serve in background until returned handle is shut down or dropped,
+/// server accepts requests once this returns
+pub fn spawn_dynamic_http(
+    plan: Plan,
+    mysql_conns: HashMap<String, sqlx::MySqlPool>,
+    sqlite_conns: HashMap<String, sqlx::SqlitePool>,
+    pg_conns: HashMap<String, sqlx::PgPool>,
+    extra: Option<ExtraRoutes>,
+) -> Result<ServerHandle, String> {
+    let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+    let shutdown = async move {
+        // sender dropped along with handle also means stop
+        let _ = stopped.await;
+    };
+    let (addrs, serve) =
+        bind_dynamic_http(plan, mysql_conns, sqlite_conns, pg_conns, extra, shutdown)?;
+    let task = tokio::spawn(serve);
+    Ok(ServerHandle { addrs, stop, task })
+}
+
+/// resolves on SIGINT (ctrl-c) or, on unix, SIGTERM which container orchestrators send
+/// before SIGKILL. A signal which can't be listened is logged and never resolves
+pub async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            log::error!("listen SIGINT failed {}", e);
+            future::pending::<()>().await;
+        }
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+            Err(e) => {
+                log::error!("listen SIGTERM failed {}", e);
+                future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = future::pending::<()>();
+    future::select(Box::pin(ctrl_c), Box::pin(terminate)).await;
+    log::info!("shutdown signal received, stop accepting requests");
+}
+
+/// serve plan queries on every plan address until [`shutdown_signal`], see
+/// [`run_dynamic_http_until`] for how server shuts down
+///
+/// `extra` routes are tried before generated query routes, so they can
+/// use any path except the builtin ones, e.g. `index` or `{prefix}/add_conn`.
+pub async fn run_dynamic_http(
+    plan: Plan,
+    mysql_conns: HashMap<String, sqlx::MySqlPool>,
+    sqlite_conns: HashMap<String, sqlx::SqlitePool>,
+    pg_conns: HashMap<String, sqlx::PgPool>,
+    extra: Option<ExtraRoutes>,
+) -> Result<(), ()> {
+    run_dynamic_http_until(
+        plan,
+        mysql_conns,
+        sqlite_conns,
+        pg_conns,
+        extra,
+        shutdown_signal(),
+    )
+    .await
+}
+
+/// same as [`run_dynamic_http`], but stop serving when `shutdown` resolves. New requests are
+/// refused then, in-flight requests are finished and connection pools are closed before
+/// return, in at most [`Plan::shutdown_timeout_secs`] if set
+pub async fn run_dynamic_http_until(
+    plan: Plan,
+    mysql_conns: HashMap<String, sqlx::MySqlPool>,
+    sqlite_conns: HashMap<String, sqlx::SqlitePool>,
+    pg_conns: HashMap<String, sqlx::PgPool>,
+    extra: Option<ExtraRoutes>,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+) -> Result<(), ()> {
+    let (_, serve) = bind_dynamic_http(plan, mysql_conns, sqlite_conns, pg_conns, extra, shutdown)
+        .map_err(|e| log::error!("{}", e))?;
+    serve.await;
+    Ok(())
+}
+
+/// bind every plan address, returned future serves until `shutdown` resolves
+/// and closes connection pools at last, giving up after shutdown timeout of plan
+fn bind_dynamic_http(
+    mut plan: Plan,
+    mysql_conns: HashMap<String, sqlx::MySqlPool>,
+    sqlite_conns: HashMap<String, sqlx::SqlitePool>,
+    pg_conns: HashMap<String, sqlx::PgPool>,
+    extra: Option<ExtraRoutes>,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+) -> Result<(Vec<SocketAddr>, impl Future<Output = ()> + Send), String> {
+    // nothing to serve, server future would return right away as if it stopped
+    if plan.address.is_empty() {
+        return Err("plan address is empty, nothing to serve".to_string());
+    }
+    plan.compile()?;
+    let (notify, stop) = tokio::sync::watch::channel(false);
+    let signal = async move {
+        shutdown.await;
+        let _ = notify.send(true);
+    };
+    let extra = extra.unwrap_or_else(|| {
+        warp::any()
+            .and_then(|| async { Err::<Box<dyn Reply>, _>(warp::reject::not_found()) })
+            .boxed()
+    });
+    // `Box<dyn Reply>` is boxed away, otherwise rustc fails to prove
+    // server future is `Send` when it's spawned
+    let extra = extra.map(extra_response).boxed();
+    let prefix = plan.prefix.clone();
+    let query_prefix = prefix.clone();
+    let body_limit = plan.body_limit;
+    let auth_enabled = plan.auth_enabled();
+    let response_headers = plan.response_header_map()?;
+    let cors = plan.cors_builder()?;
+    let doc_path = plan.doc_path.clone();
+    let mysql_dbs = Arc::new(Mutex::new(mysql_conns));
+    let sqlite_dbs = Arc::new(Mutex::new(sqlite_conns));
+    let pg_dbs = Arc::new(Mutex::new(pg_conns));
+    let pools = PoolGuard {
+        mysql_dbs: mysql_dbs.clone(),
+        sqlite_dbs: sqlite_dbs.clone(),
+        pg_dbs: pg_dbs.clone(),
+    };
+    let audit = plan
+        .audit
+        .as_ref()
+        .map(AuditLog::open)
+        .transpose()
+        .map_err(|e| format!("open audit log failed {}", e))?
+        .map(Arc::new);
+    let limiters: Limiters = Arc::new(
+        plan.conn_limits
+            .iter()
+            .map(|(conn, limit)| (conn.clone(), ConnLimiter::new(limit)))
+            .collect(),
+    );
+    let plan_db = Arc::new(Mutex::new(plan.clone()));
+    let plan_doc = plan_db.clone();
+    let doc_route = warp::get()
+        .and(warp::path(prefix.clone()))
+        .and(warp::path(plan.doc_path.clone()))
+        .and(warp::header::optional::<String>("accept-language"))
+        .and(warp::any().map(move || plan_doc.clone()))
+        .and_then(dynamic_doc);
+    let index = warp::get()
+        .and(warp::path("index"))
+        .and(warp::any().map(move || format!("{}/{}", &prefix.clone(), &doc_path)))
+        .and_then(index::serve_index);
+    let favicon = warp::get()
+        .and(warp::path("favicon.ico"))
+        .and_then(index::favicon);
+    let plan_c = plan_db.clone();
+    let explore_status_route = warp::get()
+        .and(warp::path(query_prefix.clone()))
         .and(warp::path!("explore" / "status"))
         .and(warp::any().map(move || plan_c.clone()))
         .and_then(explore::status);
+    let plan_c = plan_db.clone();
     let test_conn_route = warp::post()
         .and(warp::path(query_prefix.clone()))
         .and(warp::path!("__util" / "test_connective"))
-        .and(warp::body::json())
+        .and(warp::header::headers_cloned())
+        .and(json_body(body_limit))
+        .and(warp::any().map(move || plan_c.clone()))
         .and_then(test_conn);
     let plan_c = plan_db.clone();
+    let mysql_dbs_c = mysql_dbs.clone();
+    let sqlite_dbs_c = sqlite_dbs.clone();
+    let pg_dbs_c = pg_dbs.clone();
+    let conns_route = warp::get()
+        .and(warp::path(query_prefix.clone()))
+        .and(warp::path!("__util" / "conns"))
+        .and(warp::header::headers_cloned())
+        .and(warp::any().map(move || plan_c.clone()))
+        .and(warp::any().map(move || mysql_dbs_c.clone()))
+        .and(warp::any().map(move || sqlite_dbs_c.clone()))
+        .and(warp::any().map(move || pg_dbs_c.clone()))
+        .and_then(explore::conns);
+    let mysql_dbs_c = mysql_dbs.clone();
+    let sqlite_dbs_c = sqlite_dbs.clone();
+    let pg_dbs_c = pg_dbs.clone();
+    let health_route = warp::get()
+        .and(warp::path(query_prefix.clone()))
+        .and(warp::path!("__util" / "health"))
+        .and(warp::any().map(move || mysql_dbs_c.clone()))
+        .and(warp::any().map(move || sqlite_dbs_c.clone()))
+        .and(warp::any().map(move || pg_dbs_c.clone()))
+        .and_then(explore::health);
+    let plan_c = plan_db.clone();
+    // token stream shows sql of queries, so it's not served unless api keys guard it
+    let tokens_route = if auth_enabled {
+        warp::get()
+            .and(warp::path(query_prefix.clone()))
+            .and(warp::path!("__util" / "tokens" / String))
+            .and(warp::header::headers_cloned())
+            .and(warp::any().map(move || plan_c.clone()))
+            .and_then(explore::tokens)
+            .map(Reply::into_response)
+            .boxed()
+    } else {
+        warp::any()
+            .and_then(|| async { Err::<warp::reply::Response, _>(warp::reject::not_found()) })
+            .boxed()
+    };
+    let plan_c = plan_db.clone();
     let add_query_route = warp::post()
         .and(warp::path(query_prefix.clone()))
         .and(warp::path("add_query"))
-        .and(new_query_body())
+        .and(warp::header::headers_cloned())
+        .and(json_body(body_limit))
         .and(warp::any().map(move || plan_c.clone()))
         .and_then(add_query);
     let plan_db_c = plan_db.clone();
     let mysql_dbs_c = mysql_dbs.clone();
     let sqlite_dbs_c = sqlite_dbs.clone();
+    let pg_dbs_c = pg_dbs.clone();
     let add_conn_route = warp::post()
         .and(warp::path(query_prefix.clone()))
         .and(warp::path("add_conn"))
-        .and(warp::body::json())
+        .and(warp::header::headers_cloned())
+        .and(json_body(body_limit))
         .and(warp::any().map(move || plan_db_c.clone()))
         .and(warp::any().map(move || mysql_dbs_c.clone()))
         .and(warp::any().map(move || sqlite_dbs_c.clone()))
+        .and(warp::any().map(move || pg_dbs_c.clone()))
         .and_then(add_conn);
     let plan_c = plan_db.clone();
+    let audit_c = audit.clone();
+    let mysql_dbs_c = mysql_dbs.clone();
+    let sqlite_dbs_c = sqlite_dbs.clone();
+    let pg_dbs_c = pg_dbs.clone();
+    let limiters_c = limiters.clone();
+    let stop_c = stop.clone();
+    let sse_route = warp::get()
+        .and(warp::path(query_prefix.clone()))
+        .and(warp::path!("__sse" / String))
+        .and(warp::query::raw().or(warp::any().map(String::new)).unify())
+        .and(warp::path::full())
+        .and(warp::header::headers_cloned())
+        .and(warp::addr::remote())
+        .and(warp::any().map(move || plan_c.clone()))
+        .and(warp::any().map(move || audit_c.clone()))
+        .and(warp::any().map(move || mysql_dbs_c.clone()))
+        .and(warp::any().map(move || sqlite_dbs_c.clone()))
+        .and(warp::any().map(move || pg_dbs_c.clone()))
+        .and(warp::any().map(move || limiters_c.clone()))
+        .and(warp::any().map(move || stop_c.clone()))
+        .and_then(serve_sse);
+    let plan_c = plan_db.clone();
+    let plan_c2 = plan_db.clone();
+    let audit_c = audit.clone();
+    let mysql_dbs_c = mysql_dbs.clone();
+    let sqlite_dbs_c = sqlite_dbs.clone();
+    let pg_dbs_c = pg_dbs.clone();
+    let limiters_c = limiters.clone();
+    let blob_route = warp::any()
+        .and(warp::method())
+        .and(warp::path::full())
+        .and(warp::any().map(move || plan_c.clone()))
+        .and_then(blob_query)
+        .and(warp::method())
+        .and(warp::query::raw().or(warp::any().map(String::new)).unify())
+        .and(warp::path::full())
+        .and(warp::header::headers_cloned())
+        .and(warp::addr::remote())
+        .and(warp::header::optional::<u64>("content-length"))
+        .and(warp::body::stream())
+        .and(warp::any().map(move || plan_c2.clone()))
+        .and(warp::any().map(move || audit_c.clone()))
+        .and(warp::any().map(move || mysql_dbs_c.clone()))
+        .and(warp::any().map(move || sqlite_dbs_c.clone()))
+        .and(warp::any().map(move || pg_dbs_c.clone()))
+        .and(warp::any().map(move || limiters_c.clone()))
+        .and_then(serve_blob);
+    let plan_c = plan_db.clone();
     let query_route = warp::any()
         .and(warp::method())
         .and(warp::query::raw().or(warp::any().map(String::new)).unify())
         .and(warp::path::full())
-        .and(
-            warp::body::json()
-                .or(warp::body::form())
-                .unify()
-                .or(warp::any().map(HashMap::default))
-                .unify(),
-        )
+        .and(warp::header::headers_cloned())
+        .and(warp::addr::remote())
+        .and(params_body(body_limit))
         .and(warp::any().map(move || plan_c.clone()))
+        .and(warp::any().map(move || audit.clone()))
         .and(warp::any().map(move || mysql_dbs.clone()))
         .and(warp::any().map(move || sqlite_dbs.clone()))
+        .and(warp::any().map(move || pg_dbs.clone()))
+        .and(warp::any().map(move || limiters.clone()))
         .and_then(serve_query);
-    let fs = plan
-        .address
-        .iter()
-        .map(move |addr| {
-            warp::serve(
-                index
-                    .clone()
-                    .or(favicon)
-                    .or(explore_status_route.clone())
-                    .or(test_conn_route.clone())
-                    .or(doc_route.clone())
-                    .or(add_conn_route.clone())
-                    .or(add_query_route.clone())
-                    .or(query_route.clone()),
+    let routes = index
+        .or(favicon)
+        .or(explore_status_route)
+        .or(test_conn_route)
+        .or(conns_route)
+        .or(health_route)
+        .or(tokens_route)
+        .or(doc_route)
+        .or(add_conn_route)
+        .or(add_query_route)
+        .or(extra)
+        .or(sse_route)
+        .or(blob_route)
+        .or(query_route)
+        .map(|reply| Ok(Reply::into_response(reply)))
+        .recover(|err| async move { Ok::<_, Infallible>(Err(err)) })
+        .unify();
+    // rejections are rendered after routing, so error response can follow pretty flag too
+    let routes = pretty_flag().and(routes).and_then(
+        |pretty, routed: Result<warp::reply::Response, warp::Rejection>| async move {
+            match routed {
+                Ok(resp) => Ok(resp),
+                Err(err) => error::handle_rejection(pretty, err).await,
+            }
+        },
+    );
+    let routes = routes.with(warp::reply::with::headers(response_headers));
+    // cors wraps every route, so error responses carry CORS headers as well
+    let routes = match cors {
+        Some(cors) => routes.with(cors).map(Reply::into_response).boxed(),
+        None => routes.map(Reply::into_response).boxed(),
+    };
+    let mut addrs = vec![];
+    let mut servers = vec![];
+    for addr in plan.address.iter() {
+        let (addr, server) = warp::serve(routes.clone())
+            .try_bind_with_graceful_shutdown((addr.ip(), addr.port()), stopped(stop.clone()))
+            .map_err(|e| format!("bind {} failed {}", addr, e))?;
+        addrs.push(addr);
+        servers.push(server);
+    }
+    let timeout = plan.shutdown_timeout_secs;
+    let serve = async move {
+        let drain = async {
+            future::join(future::join_all(servers), signal).await;
+            pools.close().await;
+        };
+        let secs = match timeout {
+            Some(secs) => secs,
+            None => return drain.await,
+        };
+        let deadline = async move {
+            stopped(stop).await;
+            tokio::time::sleep(Duration::from_secs(secs)).await;
+        };
+        // pools left open are closed in background when guard is dropped
+        if let future::Either::Right(_) = future::select(Box::pin(drain), Box::pin(deadline)).await
+        {
+            log::warn!(
+                "shutdown not finished in {} seconds, in-flight requests are dropped",
+                secs
+            );
+        }
+    };
+    Ok((addrs, serve))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+
+    #[test]
+    fn write_output() {
+        let dialect = sqlparser::dialect::SQLiteDialect {};
+        let parse = |sql| {
+            sqlparser::parser::Parser::parse_sql(&dialect, sql)
+                .unwrap()
+                .remove(0)
+        };
+        let insert = parse("insert into t (name) values ('a')");
+        let update = parse("update t set name = 'b'");
+
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let outputs = rt.block_on(async {
+            let pool = sqlx::SqlitePool::connect("sqlite::memory:").await.unwrap();
+            sqlx::query("create table t (id INTEGER PRIMARY KEY, name TEXT)")
+                .execute(&pool)
+                .await
+                .unwrap();
+            let mut outputs = vec![];
+            for stmt in [&insert, &insert, &update] {
+                let done = sqlx::query(&stmt.to_string()).execute(&pool).await.unwrap();
+                let id = done.last_insert_rowid() as u64;
+                outputs.push(WriteOutput::new(stmt, done.rows_affected(), Some(id)));
+            }
+            outputs
+        });
+        let json = outputs
+            .iter()
+            .map(|output| serde_json::to_string(output).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            json,
+            vec![
+                r#"{"rows_affected":1,"last_insert_id":1}"#,
+                r#"{"rows_affected":1,"last_insert_id":2}"#,
+                r#"{"rows_affected":2}"#,
+            ]
+        );
+        // insert of no rows reports no id
+        assert_eq!(WriteOutput::new(&insert, 0, Some(2)).last_insert_id, None);
+    }
+
+    #[test]
+    fn transactional_query() {
+        let text = "title = 't'\naddress = ['127.0.0.1:0']\n[sqlite_conns]\nlocal = 'sqlite::memory:'\n\
+            [queries.add]\nconn = 'local'\npath = 'add'\ntransactional = true\n\
+            sql = \"--? name: str\\ninsert into t (name) values (@name);\\nselect last_insert_rowid() as id\"\n\
+            [queries.dup]\nconn = 'local'\npath = 'dup'\ntransactional = true\n\
+            sql = \"--? name: str\\ninsert into t (name) values (@name);\\ninsert into t (id, name) values (1, @name);\\nselect 1\"\n\
+            [queries.plain]\nconn = 'local'\npath = 'plain'\n\
+            sql = \"--? name: str\\ninsert into t (name) values (@name);\\nselect 1\"\n\
+            [queries.count]\nconn = 'local'\npath = 'count'\nsql = 'select count(*) as n from t'\n";
+        let plan = toml::from_str::<Plan>(text).unwrap();
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let responses = rt.block_on(async {
+            // a single connection, every connection of memory database has its own data
+            let pool = sqlx::sqlite::SqlitePoolOptions::new()
+                .max_connections(1)
+                .connect("sqlite::memory:")
+                .await
+                .unwrap();
+            sqlx::query("create table t (id INTEGER PRIMARY KEY, name TEXT)")
+                .execute(&pool)
+                .await
+                .unwrap();
+            let sqlite_conns = [("local".to_string(), pool)].into_iter().collect();
+            let handle =
+                spawn_dynamic_http(plan, HashMap::new(), sqlite_conns, HashMap::new(), None)
+                    .unwrap();
+            let base = format!("http://{}/api", handle.addrs()[0]);
+            let mut responses = vec![];
+            for path in ["add?name=a", "dup?name=b", "plain?name=c", "count"] {
+                let resp = reqwest::get(format!("{}/{}", base, path)).await.unwrap();
+                responses.push((resp.status().as_u16(), resp.text().await.unwrap()));
+            }
+            handle.shutdown().await;
+            responses
+        });
+        assert_eq!(responses[0], (200, r#"[{"id":1}]"#.to_string()));
+        // second insert of dup conflicts, so its first insert is rolled back
+        assert_eq!(responses[1].0, 500, "{}", responses[1].1);
+        assert_eq!(responses[2].0, 400);
+        assert!(responses[2].1.contains("expect 1 sql statement, got 2"));
+        assert_eq!(responses[3], (200, r#"[{"n":1}]"#.to_string()));
+    }
+
+    #[test]
+    fn empty_address() {
+        let plan = Plan {
+            address: vec![],
+            ..Default::default()
+        };
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let served = rt.block_on(async {
+            let serve = run_dynamic_http_until(
+                plan,
+                HashMap::new(),
+                HashMap::new(),
+                HashMap::new(),
+                None,
+                async {},
+            );
+            tokio::time::timeout(std::time::Duration::from_secs(5), serve).await
+        });
+        assert_eq!(served, Ok(Err(())));
+    }
+
+    #[test]
+    fn shutdown_timeout() {
+        let text = "title = 't'\naddress = ['127.0.0.1:0']\nshutdown_timeout_secs = 1\n\
+            [sqlite_conns]\nlocal = 'sqlite::memory:'\n\
+            [queries.slow]\nconn = 'local'\npath = 'slow'\n\
+            sql = 'with recursive c(x) as (select 1 union all select x + 1 from c where x < 100000000) select count(*) from c'\n";
+        let plan = toml::from_str::<Plan>(text).unwrap();
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let elapsed = rt.block_on(async {
+            let pool = sqlx::SqlitePool::connect("sqlite::memory:").await.unwrap();
+            let sqlite_conns = [("local".to_string(), pool)].into_iter().collect();
+            let handle =
+                spawn_dynamic_http(plan, HashMap::new(), sqlite_conns, HashMap::new(), None)
+                    .unwrap();
+            let url = format!("http://{}/api/slow", handle.addrs()[0]);
+            let slow = tokio::spawn(reqwest::get(url));
+            tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+            let start = std::time::Instant::now();
+            handle.shutdown().await;
+            slow.abort();
+            start.elapsed()
+        });
+        // shutdown gives up on the slow query instead of waiting for it
+        assert!(elapsed < std::time::Duration::from_secs(3), "{:?}", elapsed);
+    }
+
+    #[test]
+    fn body_param_types() {
+        let dialect = sqlparser::dialect::SQLiteDialect {};
+        let prog = Program::parse(
+            &dialect,
+            "--? limit: num = 10 (1..=100)\n--? day: date = '2024-01-31'\n--? ids: [num] = [1]\n\
+            select * from t where day = @day and id in @ids limit @limit",
+        )
+        .unwrap();
+        let context = |body: &str| {
+            let body = serde_json::from_str::<HashMap<String, ParamValue>>(body).unwrap();
+            get_context_from_body(&body, &prog, &HashMap::new())
+        };
+        assert_eq!(
+            context(r#"{"limit": 50, "ids": [1, "2"]}"#).unwrap(),
+            HashMap::from([
+                ("limit".to_string(), ParamValue::Num(50.0)),
+                ("day".to_string(), ParamValue::Str("2024-01-31".to_string())),
+                (
+                    "ids".to_string(),
+                    ParamValue::Array(vec![ParamValue::Num(1.0), ParamValue::Num(2.0)])
+                ),
+            ])
+        );
+        // strings are parsed like query string values, then checked against range
+        assert_eq!(
+            context(r#"{"limit": "50"}"#).unwrap()["limit"],
+            ParamValue::Num(50.0)
+        );
+        for body in [
+            r#"{"limit": "5000"}"#,
+            r#"{"limit": 5000}"#,
+            r#"{"limit": "ten"}"#,
+            r#"{"limit": true}"#,
+            r#"{"day": 20240131}"#,
+            r#"{"day": "2024-02-30"}"#,
+            r#"{"ids": [1, "x"]}"#,
+            r#"{"ids": [[1]]}"#,
+        ] {
+            assert!(
+                matches!(context(body), Err(ApiError::BadRequest(_))),
+                "{}",
+                body
+            );
+        }
+    }
+
+    #[test]
+    fn body_pattern() {
+        let dialect = sqlparser::dialect::SQLiteDialect {};
+        let prog = Program::parse(&dialect, "--? code: str /^[A-Z]{3}$/\nselect @code").unwrap();
+        let context = |body: &str| {
+            let body = serde_json::from_str::<HashMap<String, ParamValue>>(body).unwrap();
+            get_context_from_body(&body, &prog, &HashMap::new())
+        };
+        assert_eq!(
+            context(r#"{"code": "EUR"}"#).unwrap()["code"],
+            ParamValue::Str("EUR".to_string())
+        );
+        for body in [
+            r#"{"code": 12345}"#,
+            r#"{"code": true}"#,
+            r#"{"code": "EURO"}"#,
+        ] {
+            assert!(
+                matches!(context(body), Err(ApiError::BadRequest(_))),
+                "{}",
+                body
+            );
+        }
+    }
+
+    #[test]
+    fn body_bool() {
+        let dialect = sqlparser::dialect::SQLiteDialect {};
+        let prog = Program::parse(&dialect, "--? flag: bool\nselect @flag").unwrap();
+        let context = |body: &str| {
+            let body = serde_json::from_str::<HashMap<String, ParamValue>>(body).unwrap();
+            get_context_from_body(&body, &prog, &HashMap::new())
+                .map(|context| context["flag"].clone())
+        };
+        assert_eq!(
+            context(r#"{"flag": true}"#).unwrap(),
+            ParamValue::Bool(true)
+        );
+        // strings of form body, like query string
+        assert_eq!(
+            context(r#"{"flag": "false"}"#).unwrap(),
+            ParamValue::Bool(false)
+        );
+        assert_eq!(context(r#"{"flag": "1"}"#).unwrap(), ParamValue::Bool(true));
+        for body in [
+            r#"{"flag": "yes"}"#,
+            r#"{"flag": 7}"#,
+            r#"{"flag": 1}"#,
+            r#"{"flag": [true]}"#,
+        ] {
+            assert!(
+                matches!(context(body), Err(ApiError::BadRequest(_))),
+                "{}",
+                body
+            );
+        }
+    }
+
+    #[test]
+    fn body_content_type() {
+        let text = "title = 't'\naddress = ['127.0.0.1:0']\n[sqlite_conns]\nlocal = 'sqlite::memory:'\n\
+            [queries.echo]\nconn = 'local'\npath = 'echo'\nmethod = 'POST'\nsql = \"--? n: num = 0\\nselect @n as n\"\n";
+        let plan = toml::from_str::<Plan>(text).unwrap();
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let statuses = rt.block_on(async {
+            let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+            let sqlite_conns = HashMap::from([("local".to_string(), pool)]);
+            let handle =
+                spawn_dynamic_http(plan, HashMap::new(), sqlite_conns, HashMap::new(), None)
+                    .unwrap();
+            let url = format!("http://{}/api/echo", handle.addrs()[0]);
+            let client = reqwest::Client::new();
+            let mut statuses = vec![];
+            for (ty, body) in [
+                ("application/json", r#"{"n": 1}"#),
+                ("application/x-www-form-urlencoded", "n=1"),
+                ("text/plain", "n=1"),
+                ("multipart/form-data; boundary=x", "--x--"),
+                ("text/plain", ""),
+            ] {
+                let resp = client
+                    .post(&url)
+                    .header("content-type", ty)
+                    .body(body)
+                    .send()
+                    .await
+                    .unwrap();
+                statuses.push(resp.status().as_u16());
+            }
+            handle.shutdown().await;
+            statuses
+        });
+        assert_eq!(statuses, [200, 200, 415, 415, 200]);
+    }
+
+    #[test]
+    fn blob_after_param() {
+        let text = "title = 't'\naddress = ['127.0.0.1:0']\n[sqlite_conns]\nlocal = 'sqlite::memory:'\n\
+            [queries.upload]\nconn = 'local'\npath = 'upload'\nmethod = 'POST'\nblob = { param = 'data' }\n\
+            sql = \"--? name: str\\n--? data: raw\\ninsert into files (name, data) values (@name, @data)\"\n\
+            [queries.files]\nconn = 'local'\npath = 'files'\nsql = 'select name, hex(data) as data from files'\n";
+        let plan = toml::from_str::<Plan>(text).unwrap();
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let files = rt.block_on(async {
+            let pool = sqlx::sqlite::SqlitePoolOptions::new()
+                .max_connections(1)
+                .connect("sqlite::memory:")
+                .await
+                .unwrap();
+            sqlx::query("create table files (name TEXT, data BLOB)")
+                .execute(&pool)
+                .await
+                .unwrap();
+            let sqlite_conns = [("local".to_string(), pool)].into_iter().collect();
+            let handle =
+                spawn_dynamic_http(plan, HashMap::new(), sqlite_conns, HashMap::new(), None)
+                    .unwrap();
+            let base = format!("http://{}/api", handle.addrs()[0]);
+            // marker in value of another param is not a placeholder
+            for name in ["photo", "it%27s%20__psql_blob__"] {
+                let resp = reqwest::Client::new()
+                    .post(format!("{}/upload?name={}", base, name))
+                    .body("BYTES")
+                    .send()
+                    .await
+                    .unwrap();
+                assert_eq!(resp.status().as_u16(), 200);
+            }
+            let files = reqwest::get(format!("{}/files", base))
+                .await
+                .unwrap()
+                .text()
+                .await
+                .unwrap();
+            handle.shutdown().await;
+            files
+        });
+        assert_eq!(
+            files,
+            r#"[{"name":"photo","data":"4259544553"},{"name":"it's __psql_blob__","data":"4259544553"}]"#
+        );
+    }
+
+    #[test]
+    fn blob_marker_outside_literals() {
+        let sql = "insert into t values ('__psql_blob__', \"__psql_blob__\", 'it''s', __psql_blob__, __psql_blob__)";
+        assert_eq!(
+            blob_placeholders(sql, &Dialect::Sqlite),
+            (
+                "insert into t values ('__psql_blob__', \"__psql_blob__\", 'it''s', ?, ?)"
+                    .to_string(),
+                2
             )
-            .bind_ephemeral((addr.ip(), addr.port()))
-            .1
-        })
-        .collect::<Vec<_>>();
-    future::join_all(fs).await;
-    Ok(())
+        );
+        assert_eq!(
+            blob_placeholders("select __psql_blob__, '__psql_blob__'", &Dialect::Postgres),
+            ("select $1, '__psql_blob__'".to_string(), 1)
+        );
+    }
+
+    #[test]
+    fn blob_marker_without_blob() {
+        // only blob queries get markers replaced, other queries may render it as a value
+        let text = "title = 't'\naddress = ['127.0.0.1:0']\n[sqlite_conns]\nlocal = 'sqlite::memory:'\n\
+            [queries.pick]\nconn = 'local'\npath = 'pick'\nsql = \"--? col: ident\\nselect 1 as @col\"\n";
+        let plan = toml::from_str::<Plan>(text).unwrap();
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let body = rt.block_on(async {
+            let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+            let sqlite_conns = [("local".to_string(), pool)].into_iter().collect();
+            let handle =
+                spawn_dynamic_http(plan, HashMap::new(), sqlite_conns, HashMap::new(), None)
+                    .unwrap();
+            let url = format!("http://{}/api/pick?col=__psql_blob__", handle.addrs()[0]);
+            let resp = reqwest::get(url).await.unwrap();
+            assert_eq!(resp.status().as_u16(), 200);
+            let body = resp.text().await.unwrap();
+            handle.shutdown().await;
+            body
+        });
+        assert_eq!(body, r#"[{"__psql_blob__":1}]"#);
+    }
+
+    #[test]
+    fn admin_routes_require_api_key() {
+        let text = "title = 't'\naddress = ['127.0.0.1:0']\napi_keys = ['secret']\n";
+        let plan = toml::from_str::<Plan>(text).unwrap();
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let statuses = rt.block_on(async {
+            let handle =
+                spawn_dynamic_http(plan, HashMap::new(), HashMap::new(), HashMap::new(), None)
+                    .unwrap();
+            let base = format!("http://{}/api", handle.addrs()[0]);
+            let client = reqwest::Client::new();
+            let mut statuses = vec![];
+            for key in [None, Some("wrong"), Some("secret")] {
+                for (path, body) in [
+                    ("__util/test_connective", r#"{"uri": "sqlite::memory:"}"#),
+                    (
+                        "add_conn",
+                        r#"[{"name": "local", "uri": "sqlite::memory:"}]"#,
+                    ),
+                    (
+                        "add_query",
+                        r#"[{"name": "one", "conn": "local", "sql": "select 1", "path": "one"}]"#,
+                    ),
+                ] {
+                    let mut req = client
+                        .post(format!("{}/{}", base, path))
+                        .header("content-type", "application/json")
+                        .body(body);
+                    if let Some(key) = key {
+                        req = req.header(plan::API_KEY_HEADER, key);
+                    }
+                    statuses.push(req.send().await.unwrap().status().as_u16());
+                }
+            }
+            handle.shutdown().await;
+            statuses
+        });
+        assert_eq!(statuses, [401, 401, 401, 401, 401, 401, 200, 201, 201]);
+    }
+
+    #[test]
+    fn add_query_checks() {
+        let text =
+            "title = 't'\naddress = ['127.0.0.1:0']\n[sqlite_conns]\nlocal = 'sqlite::memory:'\n";
+        let plan = toml::from_str::<Plan>(text).unwrap();
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+            let sqlite_conns = HashMap::from([("local".to_string(), pool)]);
+            let handle =
+                spawn_dynamic_http(plan, HashMap::new(), sqlite_conns, HashMap::new(), None)
+                    .unwrap();
+            let base = format!("http://{}/api", handle.addrs()[0]);
+            let client = reqwest::Client::new();
+            let add = |body: &'static str| {
+                client
+                    .post(format!("{}/add_query", base))
+                    .header("content-type", "application/json")
+                    .body(body)
+                    .send()
+            };
+            let resp = add(r#"[{"name": "one", "conn": "local", "sql": "select 1", "path": "one"},
+                {"name": "both", "conn": "local", "sql": "select 1", "path": "both", "scalar": true, "multi_result": true}]"#)
+                .await
+                .unwrap();
+            assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+            assert!(resp.text().await.unwrap().contains("query both: multi_result"));
+            let resp = client.get(format!("{}/one", base)).send().await.unwrap();
+            assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+            let resp =
+                add(r#"[{"name": "lost", "conn": "remote", "sql": "select 1", "path": "lost"}]"#)
+                    .await
+                    .unwrap();
+            assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+            assert!(resp.text().await.unwrap().contains("remote"));
+            let resp = add(r#"[{"name": "one", "conn": "local", "sql": "select 1", "path": "one"}]"#)
+                .await
+                .unwrap();
+            assert_eq!(resp.status(), StatusCode::CREATED);
+            handle.shutdown().await;
+        });
+    }
+
+    #[test]
+    fn sse_events() {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            let (event, more) = sse_event(Ok(warp::reply::json(&1).into_response())).await;
+            assert!(more);
+            assert_eq!(event.to_string(), "event:result\ndata:1\n\n");
+            let failed = ApiError::BadRequest("bad".to_string()).into_response();
+            let (event, more) = sse_event(Ok(failed)).await;
+            assert!(more);
+            assert!(event.to_string().starts_with("event:error\n"));
+
+            let rejected = Err(ApiError::BadRequest("no way".to_string()).into());
+            let (event, more) = sse_event(rejected).await;
+            assert!(!more);
+            let event = event.to_string();
+            assert!(event.starts_with("event:error\n"), "{}", event);
+            assert!(event.contains(r#""msg":"no way""#), "{}", event);
+            assert!(event.contains(r#""code":400"#), "{}", event);
+            let (event, more) = sse_event(Err(warp::reject::not_found())).await;
+            assert!(!more);
+            assert!(event.to_string().contains(r#""code":500"#));
+        });
+    }
+
+    #[test]
+    fn render_error_detail() {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let bodies = ["minimal", "full"].map(|detail| {
+            let text = format!(
+                "title = 't'\naddress = ['127.0.0.1:0']\nerror_detail = '{}'\n[sqlite_conns]\nlocal = 'sqlite::memory:'\n\
+                [queries.q]\nconn = 'local'\npath = 'q'\nsql = \"--? cond: raw\\nselect 1 where @cond\"\n",
+                detail
+            );
+            let plan = toml::from_str::<Plan>(&text).unwrap();
+            rt.block_on(async {
+                let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+                let sqlite_conns = HashMap::from([("local".to_string(), pool)]);
+                let handle =
+                    spawn_dynamic_http(plan, HashMap::new(), sqlite_conns, HashMap::new(), None)
+                        .unwrap();
+                let url = format!("http://{}/api/q?cond=%23(((%23", handle.addrs()[0]);
+                let resp = reqwest::get(url).await.unwrap();
+                assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+                let body = resp.text().await.unwrap();
+                handle.shutdown().await;
+                body
+            })
+        });
+        assert!(!bodies[0].contains("ParserError"), "{}", bodies[0]);
+        assert!(bodies[0].contains("can't be rendered"), "{}", bodies[0]);
+        assert!(bodies[1].contains("ParserError"), "{}", bodies[1]);
+    }
+
+    #[test]
+    fn tokens_route_needs_api_keys() {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let statuses = ["", "api_keys = ['secret']\n"].map(|keys| {
+            let text = format!(
+                "title = 't'\naddress = ['127.0.0.1:0']\n{}[sqlite_conns]\nlocal = 'sqlite::memory:'\n\
+                [queries.q]\nconn = 'local'\npath = 'q'\nsql = 'select 1'\n",
+                keys
+            );
+            let plan = toml::from_str::<Plan>(&text).unwrap();
+            rt.block_on(async {
+                let handle =
+                    spawn_dynamic_http(plan, HashMap::new(), HashMap::new(), HashMap::new(), None)
+                        .unwrap();
+                let url = format!("http://{}/api/__util/tokens/q", handle.addrs()[0]);
+                let client = reqwest::Client::new();
+                let mut statuses = vec![];
+                for key in ["", "secret"] {
+                    let resp = client
+                        .get(&url)
+                        .header(plan::API_KEY_HEADER, key)
+                        .send()
+                        .await
+                        .unwrap();
+                    statuses.push(resp.status().as_u16());
+                }
+                handle.shutdown().await;
+                statuses
+            })
+        });
+        assert_eq!(statuses, [[404, 404], [401, 200]]);
+    }
+
+    #[test]
+    fn conn_limit() {
+        let text = "title = 't'\naddress = ['127.0.0.1:0']\n[sqlite_conns]\nlocal = 'sqlite::memory:'\n\
+            [conn_limits.local]\nmax_concurrent = 1\nwait_ms = 50\n\
+            [queries.slow]\nconn = 'local'\npath = 'slow'\n\
+            sql = 'WITH RECURSIVE c(x) AS (SELECT 1 UNION ALL SELECT x + 1 FROM c WHERE x < 3000000) SELECT count(*) AS n FROM c'\n\
+            [queries.fast]\nconn = 'local'\npath = 'fast'\nsql = 'select 1 as n'\n";
+        let plan = toml::from_str::<Plan>(text).unwrap();
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+            let sqlite_conns = HashMap::from([("local".to_string(), pool)]);
+            let handle =
+                spawn_dynamic_http(plan, HashMap::new(), sqlite_conns, HashMap::new(), None)
+                    .unwrap();
+            let url = |path| format!("http://{}/api/{}", handle.addrs()[0], path);
+            let delayed = |url: String, delay| async move {
+                tokio::time::sleep(Duration::from_millis(delay)).await;
+                reqwest::get(&url).await.unwrap()
+            };
+            // fast query waits 50ms for the only slot held by slow one, then gives up
+            let (slow, busy) = tokio::join!(delayed(url("slow"), 0), delayed(url("fast"), 100));
+            assert_eq!(slow.status(), StatusCode::OK);
+            assert_eq!(busy.status(), StatusCode::SERVICE_UNAVAILABLE);
+            assert_eq!(busy.headers()["retry-after"], "1");
+            assert!(busy
+                .text()
+                .await
+                .unwrap()
+                .contains("connection local is busy"));
+            // slot is released with response
+            let fast = reqwest::get(url("fast")).await.unwrap();
+            assert_eq!(fast.status(), StatusCode::OK);
+            handle.shutdown().await;
+        });
+    }
+
+    #[test]
+    fn shutdown_handle() {
+        let text =
+            "title = 't'\naddress = ['127.0.0.1:0']\n[sqlite_conns]\nlocal = 'sqlite::memory:'\n\
+            [queries.one]\nconn = 'local'\npath = 'one'\nsql = 'select 1 as n'\n";
+        let plan = toml::from_str::<Plan>(text).unwrap();
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            let spawn = || async {
+                let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+                let sqlite_conns = HashMap::from([("local".to_string(), pool.clone())]);
+                let handle = spawn_dynamic_http(
+                    plan.clone(),
+                    HashMap::new(),
+                    sqlite_conns,
+                    HashMap::new(),
+                    None,
+                )
+                .unwrap();
+                let url = format!("http://{}/api/one", handle.addrs()[0]);
+                let resp = reqwest::get(&url).await.unwrap();
+                assert_eq!(resp.status(), StatusCode::OK);
+                (handle, pool, url)
+            };
+
+            let (handle, pool, url) = spawn().await;
+            handle.shutdown().await;
+            assert!(pool.is_closed());
+            assert!(reqwest::get(&url).await.is_err());
+
+            // dropped handle stops server in background
+            let (handle, pool, url) = spawn().await;
+            drop(handle);
+            tokio::time::timeout(Duration::from_secs(5), async {
+                while !pool.is_closed() {
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                }
+            })
+            .await
+            .expect("pool is not closed after handle is dropped");
+            assert!(reqwest::get(&url).await.is_err());
+        });
+    }
+
+    #[test]
+    fn options_allow() {
+        let text = "title = 't'\naddress = ['127.0.0.1:0']\napi_keys = ['secret']\n[sqlite_conns]\nlocal = 'sqlite::memory:'\n\
+            [queries.one]\nconn = 'local'\npath = 'one'\nsql = 'select 1 as n'\n\
+            [queries.add]\nconn = 'local'\npath = 'add'\nmethod = 'POST'\nsql = 'select 1 as n'\n";
+        let plan = toml::from_str::<Plan>(text).unwrap();
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+            let sqlite_conns = HashMap::from([("local".to_string(), pool)]);
+            let handle =
+                spawn_dynamic_http(plan, HashMap::new(), sqlite_conns, HashMap::new(), None)
+                    .unwrap();
+            let client = reqwest::Client::new();
+            let options = |path: &str| {
+                client
+                    .request(
+                        reqwest::Method::OPTIONS,
+                        format!("http://{}/api/{}", handle.addrs()[0], path),
+                    )
+                    .send()
+            };
+            // answered without api key
+            for (path, allow) in [("one", "GET, OPTIONS"), ("add", "POST, OPTIONS")] {
+                let resp = options(path).await.unwrap();
+                assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+                assert_eq!(resp.headers()["allow"], allow);
+            }
+            let resp = options("missing").await.unwrap();
+            assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+            handle.shutdown().await;
+        });
+    }
+
+    #[test]
+    fn conn_labels() {
+        let path = std::env::temp_dir().join(format!("psql-labels-{}.jsonl", std::process::id()));
+        let text = format!(
+            "title = 't'\naddress = ['127.0.0.1:0']\n[sqlite_conns]\nlocal = 'sqlite::memory:'\n\
+            [conn_labels]\nlocal = 'local db (memory)'\n[audit]\nsink = '{}'\n\
+            [queries.one]\nconn = 'local'\npath = 'one'\nsql = 'select 1 as n'\n",
+            path.display()
+        );
+        let plan = toml::from_str::<Plan>(&text).unwrap();
+        assert!(plan.validate_conn_labels().is_ok());
+        let mut unknown = plan.clone();
+        unknown
+            .conn_labels
+            .insert("lost".to_string(), "lost db".to_string());
+        assert!(unknown.validate_conn_labels().is_err());
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let conns = rt.block_on(async {
+            let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+            let sqlite_conns = [("local".to_string(), pool)].into_iter().collect();
+            let handle =
+                spawn_dynamic_http(plan, HashMap::new(), sqlite_conns, HashMap::new(), None)
+                    .unwrap();
+            let url = |path| format!("http://{}/api/{}", handle.addrs()[0], path);
+            let resp = reqwest::get(url("one")).await.unwrap();
+            assert_eq!(resp.status().as_u16(), 200);
+            let conns = reqwest::get(url("__util/conns"))
+                .await
+                .unwrap()
+                .text()
+                .await
+                .unwrap();
+            let conns = serde_json::from_str::<serde_json::Value>(&conns).unwrap();
+            handle.shutdown().await;
+            conns
+        });
+        assert_eq!(conns[0]["name"], "local");
+        assert_eq!(conns[0]["label"], "local db (memory)");
+        let written = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+        let entry = serde_json::from_str::<serde_json::Value>(written.trim()).unwrap();
+        assert_eq!(entry["query"], "one");
+        assert_eq!(entry["conn"], "local db (memory)");
+    }
+
+    #[test]
+    fn missing_conn() {
+        // plans with queries of undeclared connections are refused when spawning, the 500
+        // reply is a guard for queries which reach serving anyway
+        let lost = conn_not_found("lost");
+        assert_eq!(lost.status().as_u16(), 500);
+        assert_eq!(lost.msg(), "connection 'lost' not found");
+        let text =
+            "title = 't'\naddress = ['127.0.0.1:0']\n[sqlite_conns]\ndown = 'sqlite::memory:'\n\
+            [queries.lost]\nconn = 'lost'\npath = 'lost'\nsql = 'select 1 as n'\n";
+        let plan = toml::from_str::<Plan>(text).unwrap();
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            let spawned = spawn_dynamic_http(
+                plan.clone(),
+                HashMap::new(),
+                HashMap::new(),
+                HashMap::new(),
+                None,
+            );
+            assert!(spawned.is_err());
+
+            // declared connection without pool
+            let mut plan = plan;
+            plan.queries.get_mut("lost").unwrap().conn = "down".to_string();
+            let handle =
+                spawn_dynamic_http(plan, HashMap::new(), HashMap::new(), HashMap::new(), None)
+                    .unwrap();
+            let url = format!("http://{}/api/lost", handle.addrs()[0]);
+            let resp = reqwest::get(url).await.unwrap();
+            assert_eq!(resp.status().as_u16(), 503);
+            let body =
+                serde_json::from_str::<serde_json::Value>(&resp.text().await.unwrap()).unwrap();
+            assert_eq!(body["msg"], "connection down unavailable");
+            handle.shutdown().await;
+        });
+    }
+
+    #[test]
+    fn query_timeout() {
+        let text = "title = 't'\naddress = ['127.0.0.1:0']\n[sqlite_conns]\nlocal = 'sqlite::memory:'\n\
+            [queries.slow]\nconn = 'local'\npath = 'slow'\ntimeout_ms = 50\n\
+            sql = 'WITH RECURSIVE c(x) AS (SELECT 1 UNION ALL SELECT x + 1 FROM c WHERE x < 3000000) SELECT count(*) AS n FROM c'\n\
+            [queries.fast]\nconn = 'local'\npath = 'fast'\ntimeout_ms = 1000\nsql = 'select 1 as n'\n";
+        let plan = toml::from_str::<Plan>(text).unwrap();
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+            let sqlite_conns = [("local".to_string(), pool)].into_iter().collect();
+            let handle =
+                spawn_dynamic_http(plan, HashMap::new(), sqlite_conns, HashMap::new(), None)
+                    .unwrap();
+            let url = |path| format!("http://{}/api/{}", handle.addrs()[0], path);
+            let resp = reqwest::get(url("slow")).await.unwrap();
+            assert_eq!(resp.status().as_u16(), 504);
+            assert_eq!(resp.headers()["x-timeout-ms"], "50");
+            assert_eq!(resp.headers()["retry-after"], "1");
+            let body =
+                serde_json::from_str::<serde_json::Value>(&resp.text().await.unwrap()).unwrap();
+            assert_eq!(body["msg"], "query doesn't finish in 50 ms");
+
+            let resp = reqwest::get(url("fast")).await.unwrap();
+            assert_eq!(resp.status().as_u16(), 200);
+            assert!(resp.headers().get("x-timeout-ms").is_none());
+            handle.shutdown().await;
+        });
+    }
+
+    /// needs a postgres server, e.g. `PSQL_TEST_PG=postgres://postgres@127.0.0.1/postgres`,
+    /// skipped if not set
+    #[test]
+    fn pg_prepared_params() {
+        let uri = match std::env::var("PSQL_TEST_PG") {
+            Ok(uri) => uri,
+            Err(_) => return,
+        };
+        let text = format!(
+            "title = 't'\naddress = ['127.0.0.1:0']\n[pg_conns]\nmain = '{}'\n\
+            [queries.count]\nconn = 'main'\npath = 'count'\nsql = '''\n\
+            --? day: date\n--? at: datetime\n--? ids: [num]\n--? name: str\n\
+            with t(id, name, day, at) as (values\n\
+              (1, 'a', '2024-01-31'::date, '2024-01-31T08:00:00Z'::timestamptz),\n\
+              (2, 'b', '2024-02-01'::date, '2024-02-01T08:00:00Z'::timestamptz),\n\
+              (3, 'b', '2024-02-02'::date, '2024-02-02T08:00:00Z'::timestamptz))\n\
+            select count(*) as n from t where day >= @day and at < @at and id in @ids and name = @name\n\
+            '''\n",
+            uri
+        );
+        let plan = toml::from_str::<Plan>(&text).unwrap();
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let (status, body) = rt.block_on(async {
+            let pool = PgPool::connect(&uri).await.unwrap();
+            let pg_conns = [("main".to_string(), pool)].into_iter().collect();
+            let handle =
+                spawn_dynamic_http(plan, HashMap::new(), HashMap::new(), pg_conns, None).unwrap();
+            let url = format!(
+                "http://{}/api/count?day=2024-02-01&at=2024-02-02T09:00:00Z&ids=1&ids=2&ids=3&name=b",
+                handle.addrs()[0]
+            );
+            let resp = reqwest::get(url).await.unwrap();
+            let status = resp.status().as_u16();
+            let body = resp.text().await.unwrap();
+            handle.shutdown().await;
+            (status, body)
+        });
+        assert_eq!((status, body.as_str()), (200, r#"[{"n":2}]"#));
+    }
+
+    #[test]
+    fn concurrent_conns() {
+        let text = "title = 't'\naddress = ['127.0.0.1:0']\n[sqlite_conns]\nslow = 'sqlite::memory:'\n\
+            fast = 'sqlite::memory:'\n[queries.slow]\nconn = 'slow'\npath = 'slow'\n\
+            sql = 'WITH RECURSIVE c(x) AS (SELECT 1 UNION ALL SELECT x + 1 FROM c WHERE x < 3000000) SELECT count(*) AS n FROM c'\n\
+            [queries.fast]\nconn = 'fast'\npath = 'fast'\nsql = 'select 1 as n'\n";
+        let plan = toml::from_str::<Plan>(text).unwrap();
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            let mut sqlite_conns = HashMap::new();
+            for name in ["slow", "fast"] {
+                let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+                sqlite_conns.insert(name.to_string(), pool);
+            }
+            let handle =
+                spawn_dynamic_http(plan, HashMap::new(), sqlite_conns, HashMap::new(), None)
+                    .unwrap();
+            let url = |path| format!("http://{}/api/{}", handle.addrs()[0], path);
+            let timed = |url: String, delay| async move {
+                tokio::time::sleep(Duration::from_millis(delay)).await;
+                let resp = reqwest::get(&url).await.unwrap();
+                assert_eq!(resp.status().as_u16(), 200);
+                Instant::now()
+            };
+            // fast query starts while slow one runs on another connection
+            let (slow, fast) = tokio::join!(timed(url("slow"), 0), timed(url("fast"), 100));
+            assert!(fast < slow, "fast query waited for slow one");
+            handle.shutdown().await;
+        });
+    }
 }
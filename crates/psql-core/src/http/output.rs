@@ -1,27 +1,149 @@
 use bigdecimal::BigDecimal;
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, FixedOffset, NaiveDateTime, SecondsFormat, TimeZone, Utc};
+use indexmap::IndexMap;
 use serde::{
-    ser::{SerializeMap, SerializeSeq},
-    Serialize,
+    ser::{Error, SerializeMap, SerializeSeq},
+    Serialize, Serializer,
 };
 use sqlx::{
     mysql::{MySqlColumn, MySqlRow, MySqlValueRef},
+    postgres::{PgColumn, PgRow, PgValue, PgValueRef},
     sqlite::{SqliteColumn, SqliteRow, SqliteValueRef},
     types::time::{Date, Time},
-    Column, Row, TypeInfo, Value, ValueRef,
+    Column, Decode, Row, Type, TypeInfo, Value, ValueRef,
 };
+use std::{collections::HashSet, str::FromStr};
+/// output column description of a statement
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ColumnDesc {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub ty: String,
+    /// `None` if database can not tell
+    pub nullable: Option<bool>,
+}
+
+/// describe output columns of `sql` with prepared statement metadata, without executing it
+pub async fn describe<'c, E: sqlx::Executor<'c>>(
+    executor: E,
+    sql: &str,
+) -> Result<Vec<ColumnDesc>, sqlx::Error> {
+    let desc = executor.describe(sql).await?;
+    Ok(desc
+        .columns()
+        .iter()
+        .enumerate()
+        .map(|(idx, col)| ColumnDesc {
+            name: col.name().to_string(),
+            ty: col.type_info().name().to_string(),
+            nullable: desc.nullable(idx),
+        })
+        .collect())
+}
+
 pub struct QueryOutput<R: Row> {
     pub rows: Vec<R>,
 }
 pub struct PSqlColumn<'a, C: Column, V: ValueRef<'a>> {
     pub col: &'a C,
     pub val_ref: V,
+    /// serialize sqlite `INTEGER` as bool
+    pub as_bool: bool,
+    /// utc offset of mysql session, utc if not set
+    pub offset: Option<FixedOffset>,
+    /// strftime pattern of date time values, RFC 3339 if not set
+    pub datetime_format: Option<&'a str>,
+    /// serialize decimal as number when f64 holds it exactly
+    pub decimal_as_number: bool,
+}
+
+/// output serialization options
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SerOptions<'a> {
+    /// nest columns with dotted names, see [`column_tree`]
+    pub nest: bool,
+    /// sqlite `INTEGER` columns serialized as bool
+    pub bool_columns: &'a [String],
+    /// utc offset of mysql session, `DATETIME` and `TIMESTAMP` values are in it
+    pub offset: Option<FixedOffset>,
+    /// strftime pattern of date time values, see [`Plan::datetime_format`](super::plan::Plan::datetime_format)
+    pub datetime_format: Option<&'a str>,
+    /// decimal values as json numbers, see [`Plan::decimal_as_number`](super::plan::Plan::decimal_as_number)
+    pub decimal_as_number: bool,
+}
+
+impl<'a> SerOptions<'a> {
+    fn column<'r, C: Column, V: ValueRef<'r>>(&self, col: &'r C, val_ref: V) -> PSqlColumn<'r, C, V>
+    where
+        'a: 'r,
+    {
+        PSqlColumn {
+            col,
+            val_ref,
+            as_bool: self.bool_columns.iter().any(|name| name == col.name()),
+            offset: self.offset,
+            datetime_format: self.datetime_format,
+            decimal_as_number: self.decimal_as_number,
+        }
+    }
+}
+
+/// serialize rows as json objects
+///
+/// object keys always follow sql projection order, rows are serialized directly
+/// without going through unordered maps. When nesting, a nested object takes the
+/// position of its first column.
+pub struct QueryOutputMapSer<'a, R: Row>(pub &'a QueryOutput<R>, pub SerOptions<'a>);
+/// serialize one row as json object, like rows of [`QueryOutputMapSer`]
+pub struct PSqlRowMapSer<'a, R: Row>(pub &'a R, pub SerOptions<'a>);
+/// serialize one row as json object nested by column layout, like rows of [`QueryOutputMapSer`]
+/// with `nest` set
+pub(crate) struct PSqlRowNestedSer<'a, R: Row>(
+    pub &'a R,
+    pub &'a IndexMap<String, ColumnNode>,
+    pub SerOptions<'a>,
+);
+
+/// nested column layout node
+pub enum ColumnNode {
+    /// column ordinal
+    Leaf(usize),
+    Branch(IndexMap<String, ColumnNode>),
+}
+
+/// build nested column layout from column names, `user.id` becomes `{"user": {"id": ..}}`
+///
+/// when a prefix is both a leaf and a branch, e.g. `user` and `user.id`, the leaf wins
+/// and the dotted column is kept flat with full name
+pub fn column_tree(names: &[&str]) -> IndexMap<String, ColumnNode> {
+    let all: HashSet<&str> = names.iter().copied().collect();
+    let mut root = IndexMap::new();
+    for (idx, name) in names.iter().enumerate() {
+        let segments: Vec<&str> = name.split('.').collect();
+        let conflict =
+            (1..segments.len()).any(|end| all.contains(segments[..end].join(".").as_str()));
+        if conflict || segments.iter().any(|seg| seg.is_empty()) {
+            root.insert(name.to_string(), ColumnNode::Leaf(idx));
+            continue;
+        }
+        let (last, parents) = segments.split_last().unwrap();
+        let mut level = &mut root;
+        for seg in parents {
+            let node = level
+                .entry(seg.to_string())
+                .or_insert_with(|| ColumnNode::Branch(IndexMap::new()));
+            level = match node {
+                ColumnNode::Branch(children) => children,
+                ColumnNode::Leaf(_) => unreachable!("leaf prefix is handled as conflict"),
+            };
+        }
+        level.insert(last.to_string(), ColumnNode::Leaf(idx));
+    }
+    root
 }
 
-pub struct QueryOutputMapSer<'a, R: Row>(pub &'a QueryOutput<R>);
-struct PSqlRowMapSer<'a, R: Row>(&'a R);
-struct QueryOutputListSer<'a, R: Row>(&'a QueryOutput<R>);
-struct PSqlRowListSer<'a, R: Row>(&'a R);
+/// serialize row as json array of column values
+pub struct PSqlRowListSer<'a, R: Row>(pub &'a R, pub SerOptions<'a>);
 
 macro_rules! impl_query_output_map_ser {
     ($row:ident) => {
@@ -31,8 +153,21 @@ macro_rules! impl_query_output_map_ser {
                 S: serde::Serializer,
             {
                 let mut seq = serializer.serialize_seq(Some(self.0.rows.len()))?;
-                for row in self.0.rows.iter().map(PSqlRowMapSer) {
-                    seq.serialize_element(&row)?;
+                let names: Vec<&str> = self
+                    .0
+                    .rows
+                    .first()
+                    .map(|row| row.columns().iter().map(|c| c.name()).collect())
+                    .unwrap_or_default();
+                if self.1.nest && names.iter().any(|name| name.contains('.')) {
+                    let tree = column_tree(&names);
+                    for row in self.0.rows.iter() {
+                        seq.serialize_element(&PSqlRowNestedSer(row, &tree, self.1))?;
+                    }
+                } else {
+                    for row in self.0.rows.iter() {
+                        seq.serialize_element(&PSqlRowMapSer(row, self.1))?;
+                    }
                 }
                 seq.end()
             }
@@ -42,6 +177,81 @@ macro_rules! impl_query_output_map_ser {
 
 impl_query_output_map_ser!(MySqlRow);
 impl_query_output_map_ser!(SqliteRow);
+impl_query_output_map_ser!(PgRow);
+
+/// serialize result sets as array of row arrays, `[[...], [...]]`, each set is
+/// serialized like [`QueryOutputMapSer`]
+pub struct MultiOutputSer<'a, R: Row>(pub &'a [QueryOutput<R>], pub SerOptions<'a>);
+
+impl<'a, R: Row> Serialize for MultiOutputSer<'a, R>
+where
+    QueryOutputMapSer<'a, R>: Serialize,
+{
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let mut seq = serializer.serialize_seq(Some(self.0.len()))?;
+        for output in self.0.iter() {
+            seq.serialize_element(&QueryOutputMapSer(output, self.1))?;
+        }
+        seq.end()
+    }
+}
+
+/// write rows as csv with a header row of column names
+///
+/// fields are the json values of [`PSqlRowListSer`], so blobs are base64 as in json output,
+/// null is an empty field and json columns are their json text. Fields holding `,`, `"` or
+/// line breaks are quoted. Column names come from the first row, empty result is empty text.
+pub struct QueryOutputCsvSer<'a, R: Row>(pub &'a QueryOutput<R>, pub SerOptions<'a>);
+
+impl<'a, R: Row> QueryOutputCsvSer<'a, R>
+where
+    PSqlRowListSer<'a, R>: Serialize,
+{
+    pub fn to_csv(&self) -> Result<String, serde_json::Error> {
+        let mut out = String::new();
+        let names: Vec<&str> = match self.0.rows.first() {
+            Some(row) => row.columns().iter().map(|c| c.name()).collect(),
+            None => return Ok(out),
+        };
+        write_csv_record(&mut out, names.into_iter());
+        for row in self.0.rows.iter() {
+            let values = match serde_json::to_value(PSqlRowListSer(row, self.1))? {
+                serde_json::Value::Array(values) => values,
+                value => vec![value],
+            };
+            let fields: Vec<String> = values
+                .into_iter()
+                .map(|value| match value {
+                    serde_json::Value::Null => String::new(),
+                    serde_json::Value::String(text) => text,
+                    value => value.to_string(),
+                })
+                .collect();
+            write_csv_record(&mut out, fields.iter().map(String::as_str));
+        }
+        Ok(out)
+    }
+}
+
+/// append one csv line ended by CRLF, as RFC 4180 asks
+fn write_csv_record<'f>(out: &mut String, fields: impl Iterator<Item = &'f str>) {
+    for (idx, field) in fields.enumerate() {
+        if idx > 0 {
+            out.push(',');
+        }
+        if field.contains(&[',', '"', '\n', '\r'][..]) {
+            out.push('"');
+            out.push_str(&field.replace('"', "\"\""));
+            out.push('"');
+        } else {
+            out.push_str(field);
+        }
+    }
+    out.push_str("\r\n");
+}
 
 macro_rules! impl_row_map_ser {
     ($row:ident) => {
@@ -53,7 +263,7 @@ macro_rules! impl_row_map_ser {
                 let mut map = serializer.serialize_map(Some(self.0.len()))?;
                 for col in self.0.columns().iter().map(|c| {
                     let val_ref = self.0.try_get_raw(c.ordinal()).unwrap();
-                    PSqlColumn { col: c, val_ref }
+                    self.1.column(c, val_ref)
                 }) {
                     map.serialize_entry(col.col.name(), &col)?;
                 }
@@ -65,26 +275,37 @@ macro_rules! impl_row_map_ser {
 
 impl_row_map_ser!(MySqlRow);
 impl_row_map_ser!(SqliteRow);
+impl_row_map_ser!(PgRow);
 
-macro_rules! impl_query_output_list_ser {
+macro_rules! impl_row_nested_ser {
     ($row:ident) => {
-        impl<'a> Serialize for QueryOutputListSer<'a, $row> {
+        impl<'a> Serialize for PSqlRowNestedSer<'a, $row> {
             fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
             where
                 S: serde::Serializer,
             {
-                let mut seq = serializer.serialize_seq(Some(self.0.rows.len()))?;
-                for row in self.0.rows.iter().map(PSqlRowListSer) {
-                    seq.serialize_element(&row)?;
+                let mut map = serializer.serialize_map(Some(self.1.len()))?;
+                for (key, node) in self.1.iter() {
+                    match node {
+                        ColumnNode::Leaf(idx) => {
+                            let val_ref = self.0.try_get_raw(*idx).unwrap();
+                            let col = self.2.column(&self.0.columns()[*idx], val_ref);
+                            map.serialize_entry(key, &col)?;
+                        }
+                        ColumnNode::Branch(children) => {
+                            map.serialize_entry(key, &PSqlRowNestedSer(self.0, children, self.2))?;
+                        }
+                    }
                 }
-                seq.end()
+                map.end()
             }
         }
     };
 }
 
-impl_query_output_list_ser!(MySqlRow);
-impl_query_output_list_ser!(SqliteRow);
+impl_row_nested_ser!(MySqlRow);
+impl_row_nested_ser!(SqliteRow);
+impl_row_nested_ser!(PgRow);
 
 macro_rules! impl_row_list_ser {
     ($row:ident) => {
@@ -96,7 +317,7 @@ macro_rules! impl_row_list_ser {
                 let mut seq = serializer.serialize_seq(Some(self.0.len()))?;
                 for col in self.0.columns().iter().map(|c| {
                     let val_ref = self.0.try_get_raw(c.ordinal()).unwrap();
-                    PSqlColumn { col: c, val_ref }
+                    self.1.column(c, val_ref)
                 }) {
                     seq.serialize_element(&col)?;
                 }
@@ -108,6 +329,72 @@ macro_rules! impl_row_list_ser {
 
 impl_row_list_ser!(MySqlRow);
 impl_row_list_ser!(SqliteRow);
+impl_row_list_ser!(PgRow);
+
+/// column of type not handled above, as string if it decodes as one, otherwise
+/// base64 of its raw bytes
+fn serialize_unknown<V, S>(val: &V, col: &impl Column, serializer: S) -> Result<S::Ok, S::Error>
+where
+    V: Value,
+    S: Serializer,
+    for<'r> String: Decode<'r, V::Database> + Type<V::Database>,
+    for<'r> Vec<u8>: Decode<'r, V::Database>,
+{
+    if let Ok(v) = val.try_decode::<String>() {
+        return serializer.serialize_str(&v);
+    }
+    let bytes = val
+        .try_decode_unchecked::<Vec<u8>>()
+        .map_err(|e| decode_error(col, val.type_info().name(), e))?;
+    serializer.serialize_str(&base64::encode(bytes))
+}
+
+/// local date time at utc offset with strftime `format`, or RFC 3339 if not set,
+/// e.g. `2024-01-31T08:00:00+08:00`, utc is written as `Z`
+pub fn format_datetime(
+    local: NaiveDateTime,
+    offset: Option<FixedOffset>,
+    format: Option<&str>,
+) -> String {
+    let offset = offset.unwrap_or_else(|| FixedOffset::east_opt(0).unwrap());
+    let dt = offset
+        .from_local_datetime(&local)
+        .single()
+        .expect("fixed offset maps local time to exactly one instant");
+    match format {
+        Some(format) => dt.format(format).to_string(),
+        None => dt.to_rfc3339_opts(SecondsFormat::AutoSi, true),
+    }
+}
+
+/// decimal as json number if `as_number` is set and it survives a round trip through f64,
+/// otherwise as string to keep precision
+pub fn serialize_decimal<S: Serializer>(
+    v: &BigDecimal,
+    as_number: bool,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    let text = v.to_string();
+    if as_number {
+        if let Ok(num) = serde_json::Number::from_str(&text) {
+            if BigDecimal::from_str(&num.to_string()).ok().as_ref() == Some(v) {
+                return num.serialize(serializer);
+            }
+        }
+    }
+    serializer.serialize_str(&text)
+}
+
+/// value which doesn't decode as its column type claims, e.g. mysql zero date
+/// `0000-00-00` of `DATETIME` column
+fn decode_error<E: Error>(col: &impl Column, ty: &str, e: sqlx::Error) -> E {
+    E::custom(format!(
+        "can not decode column {} of type {}: {}",
+        col.name(),
+        ty,
+        e
+    ))
+}
 
 impl<'a> Serialize for PSqlColumn<'a, MySqlColumn, MySqlValueRef<'a>> {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
@@ -115,77 +402,79 @@ impl<'a> Serialize for PSqlColumn<'a, MySqlColumn, MySqlValueRef<'a>> {
         S: serde::Serializer,
     {
         let val = ValueRef::to_owned(&self.val_ref);
+        let type_info = val.type_info().into_owned();
+        let err = |e| decode_error::<S::Error>(self.col, type_info.name(), e);
         if val.is_null() {
             serializer.serialize_none()
         } else {
-            match val.type_info().name() {
+            match type_info.name() {
                 "BOOLEAN" => {
-                    let v = val.try_decode::<bool>().unwrap();
+                    let v = val.try_decode::<bool>().map_err(err)?;
                     serializer.serialize_bool(v)
                 }
                 "TINYINT UNSIGNED" | "SMALLINT UNSIGNED" | "INT UNSIGNED"
                 | "MEDIUMINT UNSIGNED" | "BIGINT UNSIGNED" => {
-                    let v = val.try_decode::<u64>().unwrap();
+                    let v = val.try_decode::<u64>().map_err(err)?;
                     serializer.serialize_u64(v)
                 }
                 "TINYINT" | "SMALLINT" | "INT" | "MEDIUMINT" | "BIGINT" => {
-                    let v = val.try_decode::<i64>().unwrap();
+                    let v = val.try_decode::<i64>().map_err(err)?;
                     serializer.serialize_i64(v)
                 }
                 "FLOAT" => {
-                    let v = val.try_decode::<f32>().unwrap();
+                    let v = val.try_decode::<f32>().map_err(err)?;
                     serializer.serialize_f32(v)
                 }
                 "DOUBLE" => {
-                    let v = val.try_decode::<f64>().unwrap();
+                    let v = val.try_decode::<f64>().map_err(err)?;
                     serializer.serialize_f64(v)
                 }
                 "NULL" => serializer.serialize_none(),
                 "DATE" => {
-                    let v = val.try_decode::<Date>().unwrap();
+                    let v = val.try_decode::<Date>().map_err(err)?;
                     serializer.serialize_str(&v.to_string())
                 }
                 "TIME" => {
-                    let v = val.try_decode::<Time>().unwrap();
+                    let v = val.try_decode::<Time>().map_err(err)?;
                     serializer.serialize_str(&v.to_string())
                 }
                 "YEAR" => {
-                    let v = val.try_decode::<u64>().unwrap();
+                    let v = val.try_decode::<u64>().map_err(err)?;
                     serializer.serialize_u64(v)
                 }
-                // NOTE not sure for this
-                // ref https://dev.mysql.com/doc/refman/8.0/en/time-zone-support.html
-                "DATETIME" => {
-                    let v = val
-                        .try_decode::<sqlx::types::time::OffsetDateTime>()
-                        .unwrap();
-                    serializer.serialize_str(&v.to_string())
-                }
-                "TIMESTAMP" => {
-                    let v = val.try_decode::<DateTime<Utc>>().unwrap();
-                    serializer.serialize_str(&v.to_string())
+                // `TIMESTAMP` is stored in utc and converted to session time zone when read,
+                // `DATETIME` is returned as written, both are taken as session local time
+                // ref https://dev.mysql.com/doc/refman/8.0/en/datetime.html
+                "DATETIME" | "TIMESTAMP" => {
+                    let v = val.try_decode::<NaiveDateTime>().map_err(err)?;
+                    serializer.serialize_str(&format_datetime(v, self.offset, self.datetime_format))
                 }
+                // mysql only sends enum label in result set, and column metadata doesn't
+                // carry enum definition, select `col + 0` to get 1-based ordinal instead
                 "BIT" | "ENUM" | "SET" => {
-                    let v = val.try_decode::<String>().unwrap();
+                    let v = val.try_decode::<String>().map_err(err)?;
                     serializer.serialize_str(&v)
                 }
                 "DECIMAL" => {
-                    let v = val.try_decode::<BigDecimal>().unwrap();
-                    serializer.serialize_str(&v.to_string())
+                    let v = val.try_decode::<BigDecimal>().map_err(err)?;
+                    serialize_decimal(&v, self.decimal_as_number, serializer)
                 }
                 "GEOMETRY" | "JSON" => {
-                    let v = val.try_decode::<String>().unwrap();
+                    let v = val.try_decode::<String>().map_err(err)?;
                     serializer.serialize_str(&v)
                 }
                 "CHAR" | "VARCHAR" | "TINYTEXT" | "TEXT" | "MEDIUMTEXT" | "LONGTEXT" => {
-                    let v = val.try_decode::<String>().unwrap();
+                    let v = val.try_decode::<String>().map_err(err)?;
                     serializer.serialize_str(&v)
                 }
                 "TINYBLOB" | "BLOB" | "MEDIUMBLOB" | "LONGBLOB" | "BINARY" | "VARBINARY" => {
-                    let b64_str = val.try_decode::<Vec<u8>>().map(base64::encode).unwrap();
+                    let b64_str = val
+                        .try_decode::<Vec<u8>>()
+                        .map_err(err)
+                        .map(base64::encode)?;
                     serializer.serialize_str(&b64_str)
                 }
-                t => unreachable!("{}", t),
+                _ => serialize_unknown(&val, self.col, serializer),
             }
         }
     }
@@ -197,50 +486,471 @@ impl<'a> Serialize for PSqlColumn<'a, SqliteColumn, SqliteValueRef<'a>> {
         S: serde::Serializer,
     {
         let val = ValueRef::to_owned(&self.val_ref);
+        let type_info = val.type_info().into_owned();
+        let err = |e| decode_error::<S::Error>(self.col, type_info.name(), e);
         if val.is_null() {
             serializer.serialize_none()
         } else {
-            match val.type_info().name() {
+            match type_info.name() {
                 "NULL" => serializer.serialize_none(),
+                // sqlite stores date time as text, text of a declared `DATETIME` column is
+                // returned as stored unless a format is set, then it is parsed and taken as utc
+                "TEXT"
+                    if self.datetime_format.is_some()
+                        && self.col.type_info().name() == "DATETIME" =>
+                {
+                    let v = val.try_decode::<NaiveDateTime>().map_err(err)?;
+                    serializer.serialize_str(&format_datetime(v, None, self.datetime_format))
+                }
                 "TEXT" => {
-                    let v = val.try_decode::<String>().unwrap();
+                    let v = val.try_decode::<String>().map_err(err)?;
                     serializer.serialize_str(&v)
                 }
                 "REAL" => {
-                    let v = val.try_decode::<f64>().unwrap();
+                    let v = val.try_decode::<f64>().map_err(err)?;
                     serializer.serialize_f64(v)
                 }
                 "BLOB" => {
-                    let b64_str = val.try_decode::<Vec<u8>>().map(base64::encode).unwrap();
+                    let b64_str = val
+                        .try_decode::<Vec<u8>>()
+                        .map_err(err)
+                        .map(base64::encode)?;
                     serializer.serialize_str(&b64_str)
                 }
+                "INTEGER" if self.as_bool => {
+                    let v = val.try_decode::<i64>().map_err(err)?;
+                    serializer.serialize_bool(v != 0)
+                }
                 "INTEGER" => {
-                    let v = val.try_decode::<i64>().unwrap();
+                    let v = val.try_decode::<i64>().map_err(err)?;
                     serializer.serialize_i64(v)
                 }
                 "NUMERIC" => {
-                    let v = val.try_decode::<String>().unwrap();
-                    serializer.serialize_str(&v)
+                    let v = val.try_decode::<String>().map_err(err)?;
+                    match BigDecimal::from_str(&v) {
+                        Ok(v) => serialize_decimal(&v, self.decimal_as_number, serializer),
+                        Err(_) => serializer.serialize_str(&v),
+                    }
                 }
                 "BOOLEAN" => {
-                    let v = val.try_decode::<bool>().unwrap();
+                    let v = val.try_decode::<bool>().map_err(err)?;
                     serializer.serialize_bool(v)
                 }
                 "DATE" => {
-                    let v = val.try_decode::<String>().unwrap();
+                    let v = val.try_decode::<String>().map_err(err)?;
                     serializer.serialize_str(&v)
                 }
                 "TIME" => {
-                    let v = val.try_decode::<String>().unwrap();
+                    let v = val.try_decode::<String>().map_err(err)?;
                     serializer.serialize_str(&v)
                 }
                 "DATETIME" => {
-                    let v = val.try_decode::<String>().unwrap();
+                    let v = val.try_decode::<String>().map_err(err)?;
                     serializer.serialize_str(&v)
                 }
+                _ => serialize_unknown(&val, self.col, serializer),
+            }
+        }
+    }
+}
+
+/// display scale of binary numeric, sqlx decodes numeric with scale of its base 10000
+/// digits, e.g. `12.50` as `12.5000`
+fn pg_numeric_scale(val: &PgValue) -> Option<i64> {
+    let bytes = val.try_decode_unchecked::<Vec<u8>>().ok()?;
+    let scale = bytes.get(6..8)?;
+    Some(i16::from_be_bytes([scale[0], scale[1]]).into())
+}
 
-                t => unreachable!("{}", t),
+impl<'a> Serialize for PSqlColumn<'a, PgColumn, PgValueRef<'a>> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let val = ValueRef::to_owned(&self.val_ref);
+        let type_info = val.type_info().into_owned();
+        let err = |e| decode_error::<S::Error>(self.col, type_info.name(), e);
+        if val.is_null() {
+            serializer.serialize_none()
+        } else {
+            match type_info.name() {
+                "BOOL" => {
+                    let v = val.try_decode::<bool>().map_err(err)?;
+                    serializer.serialize_bool(v)
+                }
+                "INT2" => {
+                    let v = val.try_decode::<i16>().map_err(err)?;
+                    serializer.serialize_i64(v.into())
+                }
+                "INT4" => {
+                    let v = val.try_decode::<i32>().map_err(err)?;
+                    serializer.serialize_i64(v.into())
+                }
+                "INT8" => {
+                    let v = val.try_decode::<i64>().map_err(err)?;
+                    serializer.serialize_i64(v)
+                }
+                "FLOAT4" => {
+                    let v = val.try_decode::<f32>().map_err(err)?;
+                    serializer.serialize_f64(v.into())
+                }
+                "FLOAT8" => {
+                    let v = val.try_decode::<f64>().map_err(err)?;
+                    serializer.serialize_f64(v)
+                }
+                "NUMERIC" => {
+                    let v = val.try_decode::<BigDecimal>().map_err(err)?;
+                    let v = match pg_numeric_scale(&val) {
+                        Some(scale) => v.with_scale(scale),
+                        None => v,
+                    };
+                    serialize_decimal(&v, self.decimal_as_number, serializer)
+                }
+                "TEXT" | "VARCHAR" | "BPCHAR" | "NAME" => {
+                    let v = val.try_decode::<String>().map_err(err)?;
+                    serializer.serialize_str(&v)
+                }
+                "DATE" => {
+                    let v = val.try_decode::<Date>().map_err(err)?;
+                    serializer.serialize_str(&v.to_string())
+                }
+                "TIME" => {
+                    let v = val.try_decode::<Time>().map_err(err)?;
+                    serializer.serialize_str(&v.to_string())
+                }
+                "TIMESTAMPTZ" => {
+                    let v = val.try_decode::<DateTime<Utc>>().map_err(err)?;
+                    match self.datetime_format {
+                        Some(format) => serializer.collect_str(&v.format(format)),
+                        None => serializer.serialize_str(&v.to_rfc3339()),
+                    }
+                }
+                // timestamp without time zone is taken as utc
+                "TIMESTAMP" => {
+                    let v = val.try_decode::<NaiveDateTime>().map_err(err)?;
+                    match self.datetime_format {
+                        Some(format) => serializer.collect_str(&v.and_utc().format(format)),
+                        None => serializer.serialize_str(&v.and_utc().to_rfc3339()),
+                    }
+                }
+                "UUID" => {
+                    let v = val.try_decode::<sqlx::types::Uuid>().map_err(err)?;
+                    serializer.serialize_str(&v.to_string())
+                }
+                "JSON" | "JSONB" => {
+                    let v = val.try_decode::<serde_json::Value>().map_err(err)?;
+                    v.serialize(serializer)
+                }
+                "BYTEA" => {
+                    let b64_str = val
+                        .try_decode::<Vec<u8>>()
+                        .map_err(err)
+                        .map(base64::encode)?;
+                    serializer.serialize_str(&b64_str)
+                }
+                _ => serialize_unknown(&val, self.col, serializer),
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    #[test]
+    fn datetime_format() {
+        use crate::http::plan::Plan;
+        use chrono::{FixedOffset, NaiveDate};
+        let plan = |format: &str| {
+            toml::from_str::<Plan>(&format!("title = 't'\ndatetime_format = '{}'", format)).unwrap()
+        };
+        assert!(plan("%Y-%m-%d %H:%M:%S").validate_datetime_format().is_ok());
+        assert!(plan("%Y-%Q").validate_datetime_format().is_err());
+
+        let local = NaiveDate::from_ymd_opt(2024, 1, 31)
+            .unwrap()
+            .and_hms_opt(8, 0, 0)
+            .unwrap();
+        let east = FixedOffset::east_opt(8 * 3600).unwrap();
+        assert_eq!(
+            format_datetime(local, Some(east), Some("%d/%m/%Y %H:%M %z")),
+            "31/01/2024 08:00 +0800"
+        );
+
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let rows = rt.block_on(async {
+            let pool = sqlx::SqlitePool::connect("sqlite::memory:").await.unwrap();
+            sqlx::query("create table events (at DATETIME)")
+                .execute(&pool)
+                .await
+                .unwrap();
+            sqlx::query("insert into events values ('2024-01-31 08:00:00'), (NULL)")
+                .execute(&pool)
+                .await
+                .unwrap();
+            sqlx::query("select at from events")
+                .fetch_all(&pool)
+                .await
+                .unwrap()
+        });
+        let output = QueryOutput { rows };
+        let options = SerOptions {
+            datetime_format: Some("%Y/%m/%d %H:%M"),
+            ..Default::default()
+        };
+        assert_eq!(
+            serde_json::to_string(&QueryOutputMapSer(&output, options)).unwrap(),
+            r#"[{"at":"2024/01/31 08:00"},{"at":null}]"#
+        );
+        assert_eq!(
+            serde_json::to_string(&QueryOutputMapSer(&output, SerOptions::default())).unwrap(),
+            r#"[{"at":"2024-01-31 08:00:00"},{"at":null}]"#
+        );
+    }
+
+    #[test]
+    fn decimal_as_number() {
+        use bigdecimal::BigDecimal;
+        use std::str::FromStr;
+        let json = |text: &str, as_number: bool| {
+            let mut out = vec![];
+            let v = BigDecimal::from_str(text).unwrap();
+            serialize_decimal(&v, as_number, &mut serde_json::Serializer::new(&mut out)).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        assert_eq!(json("12.50", false), r#""12.50""#);
+        assert_eq!(json("12.50", true), "12.5");
+        assert_eq!(json("-0.1", true), "-0.1");
+        assert_eq!(json("10", true), "10");
+        assert_eq!(
+            json("12345678901234567890.12", true),
+            r#""12345678901234567890.12""#
+        );
+        assert_eq!(
+            json("0.12345678901234567", true),
+            r#""0.12345678901234567""#
+        );
+    }
+
+    #[test]
+    fn unknown_column_types() {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let row = rt.block_on(async {
+            let pool = sqlx::SqlitePool::connect("sqlite::memory:").await.unwrap();
+            sqlx::query("select 'abc' as t, x'ff00' as b")
+                .fetch_one(&pool)
+                .await
+                .unwrap()
+        });
+        let json = |idx: usize| {
+            let val = ValueRef::to_owned(&row.try_get_raw(idx).unwrap());
+            let mut out = vec![];
+            serialize_unknown(
+                &val,
+                &row.columns()[idx],
+                &mut serde_json::Serializer::new(&mut out),
+            )
+            .unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        assert_eq!(json(0), r#""abc""#);
+        assert_eq!(json(1), r#""/wA=""#);
+    }
+
+    #[test]
+    fn decode_error_response() {
+        use crate::http::{spawn_dynamic_http, Plan};
+        let text = "title = 't'\naddress = ['127.0.0.1:0']\ndatetime_format = '%Y/%m/%d'\n\
+            [sqlite_conns]\nlocal = 'sqlite::memory:'\n\
+            [queries.events]\nconn = 'local'\npath = 'events'\nsql = 'select at from events'\n";
+        let plan = toml::from_str::<Plan>(text).unwrap();
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let (status, body) = rt.block_on(async {
+            let pool = sqlx::sqlite::SqlitePoolOptions::new()
+                .max_connections(1)
+                .connect("sqlite::memory:")
+                .await
+                .unwrap();
+            sqlx::query("create table events (at DATETIME)")
+                .execute(&pool)
+                .await
+                .unwrap();
+            sqlx::query("insert into events values ('0000-00-00 00:00:00')")
+                .execute(&pool)
+                .await
+                .unwrap();
+            let sqlite_conns = [("local".to_string(), pool)].into_iter().collect();
+            let handle =
+                spawn_dynamic_http(plan, HashMap::new(), sqlite_conns, HashMap::new(), None)
+                    .unwrap();
+            let url = format!("http://{}/api/events", handle.addrs()[0]);
+            let resp = reqwest::get(url).await.unwrap();
+            let status = resp.status().as_u16();
+            let body = resp.text().await.unwrap();
+            handle.shutdown().await;
+            (status, body)
+        });
+        assert_eq!(status, 500);
+        assert!(
+            body.contains("can not decode column at of type TEXT"),
+            "{}",
+            body
+        );
+    }
+
+    /// needs a postgres server, e.g. `PSQL_TEST_PG=postgres://postgres@127.0.0.1/postgres`,
+    /// skipped if not set
+    #[test]
+    fn pg_rows() {
+        let uri = match std::env::var("PSQL_TEST_PG") {
+            Ok(uri) => uri,
+            Err(_) => return,
+        };
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let rows = rt.block_on(async {
+            let pool = sqlx::PgPool::connect(&uri).await.unwrap();
+            sqlx::query(
+                "select true as b, 1::int2 as i2, 2::int4 as i4, 3::int8 as i8, \
+                1.5::float4 as f4, 2.5::float8 as f8, 12.50::numeric as n, 'a'::text as t, \
+                'v'::varchar as v, '2024-01-31'::date as d, '08:00:00'::time as tm, \
+                '2024-01-31 08:00:00+08'::timestamptz as tz, '2024-01-31 08:00:00'::timestamp as ts, \
+                '67e55044-10b1-426f-9247-bb680e5fe0c8'::uuid as u, '{\"k\": [1]}'::jsonb as j, \
+                '\\x0102'::bytea as bin, '10.0.0.1'::inet as ip, null::int4 as nothing",
+            )
+            .fetch_all(&pool)
+            .await
+            .unwrap()
+        });
+        let output = QueryOutput { rows };
+        let json = serde_json::to_value(QueryOutputMapSer(&output, SerOptions::default())).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!([{
+                "b": true, "i2": 1, "i4": 2, "i8": 3, "f4": 1.5, "f8": 2.5, "n": "12.50",
+                "t": "a", "v": "v", "d": "2024-01-31", "tm": "8:00", "tz": "2024-01-31T00:00:00+00:00",
+                "ts": "2024-01-31T08:00:00+00:00", "u": "67e55044-10b1-426f-9247-bb680e5fe0c8",
+                "j": {"k": [1]}, "bin": "AQI=", "ip": "AiAABAoAAAE=", "nothing": null
+            }])
+        );
+    }
+
+    #[test]
+    fn sqlite_bool_columns() {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let rows = rt.block_on(async {
+            let pool = sqlx::SqlitePool::connect("sqlite::memory:").await.unwrap();
+            sqlx::query("select 1 as active, 0 as deleted, 2 as level")
+                .fetch_all(&pool)
+                .await
+                .unwrap()
+        });
+        let output = QueryOutput { rows };
+        let bool_columns = ["active".to_string(), "deleted".to_string()];
+        let options = SerOptions {
+            nest: false,
+            bool_columns: &bool_columns,
+            ..Default::default()
+        };
+        assert_eq!(
+            serde_json::to_string(&QueryOutputMapSer(&output, options)).unwrap(),
+            r#"[{"active":true,"deleted":false,"level":2}]"#
+        );
+        assert_eq!(
+            serde_json::to_string(&QueryOutputMapSer(&output, SerOptions::default())).unwrap(),
+            r#"[{"active":1,"deleted":0,"level":2}]"#
+        );
+    }
+
+    #[test]
+    fn csv_output() {
+        use crate::http::{spawn_dynamic_http, Plan};
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let rows = rt.block_on(async {
+            let pool = sqlx::SqlitePool::connect("sqlite::memory:").await.unwrap();
+            sqlx::query("select 1 as id, 'a, \"b\"' as name, null as note, x'0102' as data")
+                .fetch_all(&pool)
+                .await
+                .unwrap()
+        });
+        let output = QueryOutput { rows };
+        assert_eq!(
+            QueryOutputCsvSer(&output, SerOptions::default())
+                .to_csv()
+                .unwrap(),
+            "id,name,note,data\r\n1,\"a, \"\"b\"\"\",,AQI=\r\n"
+        );
+        let empty = QueryOutput::<sqlx::sqlite::SqliteRow> { rows: vec![] };
+        assert_eq!(
+            QueryOutputCsvSer(&empty, SerOptions::default())
+                .to_csv()
+                .unwrap(),
+            ""
+        );
+
+        let text =
+            "title = 't'\naddress = ['127.0.0.1:0']\n[sqlite_conns]\nlocal = 'sqlite::memory:'\n\
+            [queries.pick]\nconn = 'local'\npath = 'pick'\nsql = \"select 1 as a, 'x\\ny' as b\"\n";
+        let plan = toml::from_str::<Plan>(text).unwrap();
+        let responses = rt.block_on(async {
+            let pool = sqlx::SqlitePool::connect("sqlite::memory:").await.unwrap();
+            let sqlite_conns = [("local".to_string(), pool)].into_iter().collect();
+            let handle =
+                spawn_dynamic_http(plan, HashMap::new(), sqlite_conns, HashMap::new(), None)
+                    .unwrap();
+            let url = format!("http://{}/api/pick", handle.addrs()[0]);
+            let client = reqwest::Client::new();
+            let mut responses = vec![];
+            for (qs, accept) in [
+                ("", "application/json"),
+                ("", "text/csv"),
+                ("", "application/json, text/csv"),
+                ("?__format=csv", "application/json"),
+                ("?__format=json", "text/csv"),
+            ] {
+                let resp = client
+                    .get(format!("{}{}", url, qs))
+                    .header("accept", accept)
+                    .send()
+                    .await
+                    .unwrap();
+                let content_type = resp.headers()["content-type"].to_str().unwrap().to_string();
+                responses.push((content_type, resp.text().await.unwrap()));
+            }
+            handle.shutdown().await;
+            responses
+        });
+        let json = (
+            "application/json".to_string(),
+            r#"[{"a":1,"b":"x\ny"}]"#.to_string(),
+        );
+        let csv = (
+            "text/csv; charset=utf-8".to_string(),
+            "a,b\r\n1,\"x\ny\"\r\n".to_string(),
+        );
+        assert_eq!(
+            responses,
+            [json.clone(), csv.clone(), json.clone(), csv, json]
+        );
+    }
+}
@@ -0,0 +1,133 @@
+//! RFC 5988 `Link` header for paginated queries
+use std::collections::HashMap;
+
+use querystring::querify;
+
+use super::{plan::Pagination, RequestInfo};
+use crate::parser::ParamValue;
+
+fn num_value(context: &HashMap<String, ParamValue>, name: &str) -> Option<u64> {
+    match context.get(name) {
+        Some(ParamValue::Num(num)) if *num >= 0.0 => Some(*num as u64),
+        _ => None,
+    }
+}
+
+/// url of current request with offset param replaced
+fn page_url(req: &RequestInfo, offset_param: &str, offset: u64) -> String {
+    let decoded = urlencoding::decode(&req.qs).unwrap_or_default();
+    let mut pairs: Vec<String> = querify(&decoded)
+        .into_iter()
+        .filter(|(k, _)| *k != offset_param)
+        .map(|(k, v)| format!("{}={}", urlencoding::encode(k), urlencoding::encode(v)))
+        .collect();
+    pairs.push(format!("{}={}", urlencoding::encode(offset_param), offset));
+    let host = req.headers.get("host").and_then(|host| host.to_str().ok());
+    let scheme = req
+        .headers
+        .get("x-forwarded-proto")
+        .and_then(|proto| proto.to_str().ok())
+        .unwrap_or("http");
+    match host {
+        Some(host) => format!("{}://{}{}?{}", scheme, host, req.path, pairs.join("&")),
+        None => format!("{}?{}", req.path, pairs.join("&")),
+    }
+}
+
+/// build `Link` header value with `next` and `prev` relations
+///
+/// `next` is emitted when current page is full, `prev` when offset is not 0.
+pub fn link_header(
+    pagination: &Pagination,
+    context: &HashMap<String, ParamValue>,
+    req: &RequestInfo,
+    rows: usize,
+) -> Option<String> {
+    let limit = num_value(context, &pagination.limit).filter(|limit| *limit > 0)?;
+    let offset = num_value(context, &pagination.offset).unwrap_or(0);
+    let mut links = vec![];
+    if rows as u64 >= limit {
+        links.push(format!(
+            "<{}>; rel=\"next\"",
+            page_url(req, &pagination.offset, offset + limit)
+        ));
+    }
+    if offset > 0 {
+        links.push(format!(
+            "<{}>; rel=\"prev\"",
+            page_url(req, &pagination.offset, offset.saturating_sub(limit))
+        ));
+    }
+    if links.is_empty() {
+        None
+    } else {
+        Some(links.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn link_relations() {
+        let pagination = Pagination {
+            limit: "limit".to_string(),
+            offset: "offset".to_string(),
+        };
+        let mut headers = warp::http::HeaderMap::new();
+        headers.insert("host", "api.example.com".parse().unwrap());
+        let req = |qs: &str| RequestInfo {
+            path: "/api/users".to_string(),
+            qs: qs.to_string(),
+            headers: headers.clone(),
+            remote: None,
+        };
+        let context = |limit: f64, offset: f64| {
+            HashMap::from([
+                ("limit".to_string(), ParamValue::Num(limit)),
+                ("offset".to_string(), ParamValue::Num(offset)),
+            ])
+        };
+
+        // full first page only has next page
+        assert_eq!(
+            link_header(
+                &pagination,
+                &context(10.0, 0.0),
+                &req("limit=10&name=a b"),
+                10
+            )
+            .unwrap(),
+            "<http://api.example.com/api/users?limit=10&name=a%20b&offset=10>; rel=\"next\""
+        );
+        // offset of prev page doesn't go below 0
+        assert_eq!(
+            link_header(
+                &pagination,
+                &context(10.0, 5.0),
+                &req("offset=5&limit=10"),
+                3
+            )
+            .unwrap(),
+            "<http://api.example.com/api/users?limit=10&offset=0>; rel=\"prev\""
+        );
+        let both = link_header(&pagination, &context(10.0, 20.0), &req(""), 10).unwrap();
+        assert_eq!(
+            both,
+            "<http://api.example.com/api/users?offset=30>; rel=\"next\", \
+            <http://api.example.com/api/users?offset=10>; rel=\"prev\""
+        );
+        // single short page and missing limit have no links
+        assert!(link_header(&pagination, &context(10.0, 0.0), &req(""), 3).is_none());
+        assert!(link_header(&pagination, &context(0.0, 20.0), &req(""), 3).is_none());
+
+        let mut forwarded = req("");
+        forwarded
+            .headers
+            .insert("x-forwarded-proto", "https".parse().unwrap());
+        assert!(link_header(&pagination, &context(1.0, 0.0), &forwarded, 1)
+            .unwrap()
+            .starts_with("<https://api.example.com/api/users?"));
+    }
+}
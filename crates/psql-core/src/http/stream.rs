@@ -0,0 +1,374 @@
+//! run a query outside of http server and stream serialized rows into a writer
+//!
+//! rows are fetched one at a time and each one is written before the next is fetched,
+//! so a slow writer holds back the database cursor instead of buffering the result set.
+use std::{any::TypeId, collections::HashMap};
+
+use futures::{
+    io::{AsyncWrite, AsyncWriteExt},
+    Stream, StreamExt, TryStreamExt,
+};
+use serde::Serialize;
+use sqlx::{Column, Row};
+use thiserror::Error;
+use tokio::sync::{mpsc, OwnedSemaphorePermit};
+
+use super::{
+    bind_blob, bind_values,
+    output::{column_tree, PSqlRowMapSer, PSqlRowNestedSer, SerOptions},
+    plan::Dialect,
+};
+use crate::{
+    errors::PSqlError,
+    parser::{ParamValue, Program},
+};
+
+/// layout of streamed rows
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamFormat {
+    /// one json array of row objects, like http response
+    JsonArray,
+    /// one row object per line
+    Ndjson,
+}
+
+/// error of [`stream_results`], `rows` is the number of rows already written
+///
+/// rows written before a failure are kept. A json array is then left unclosed, so readers
+/// can tell truncated output, and ndjson output ends at the last complete line.
+#[derive(Debug, Error)]
+pub enum StreamError {
+    #[error("{0}")]
+    Render(#[from] PSqlError),
+    #[error("expect 1 sql statement, got {0}")]
+    StatementCount(usize),
+    #[error("query failed after {rows} rows: {source}")]
+    Database { rows: u64, source: sqlx::Error },
+    #[error("serialize row {rows} failed: {source}")]
+    Serialize {
+        rows: u64,
+        source: serde_json::Error,
+    },
+    #[error("write failed after {rows} rows: {source}")]
+    Write { rows: u64, source: std::io::Error },
+}
+
+/// dialect of sqlx database
+fn dialect_of<DB: sqlx::Database>() -> Dialect {
+    let id = TypeId::of::<DB>();
+    if id == TypeId::of::<sqlx::MySql>() {
+        Dialect::Mysql
+    } else if id == TypeId::of::<sqlx::Postgres>() {
+        Dialect::Postgres
+    } else {
+        Dialect::Sqlite
+    }
+}
+
+/// render program with context, run it on pool and write every row to `writer` as json
+/// object in `format`, returns the number of rows written.
+///
+/// Values are bound as arguments like http api does. `writer` is flushed at the end,
+/// wrap a blocking [`std::io::Write`] with [`futures::io::AllowStdIo`] to use it.
+pub async fn stream_results<DB, W>(
+    pool: &sqlx::Pool<DB>,
+    program: &Program,
+    context: &HashMap<String, ParamValue>,
+    writer: &mut W,
+    format: StreamFormat,
+) -> Result<u64, StreamError>
+where
+    DB: sqlx::Database,
+    W: AsyncWrite + Unpin,
+    for<'c> &'c mut DB::Connection: sqlx::Executor<'c, Database = DB>,
+    for<'a> <DB as sqlx::database::HasArguments<'a>>::Arguments: sqlx::IntoArguments<'a, DB>,
+    for<'q> String: sqlx::Encode<'q, DB> + sqlx::Type<DB>,
+    for<'q> i64: sqlx::Encode<'q, DB> + sqlx::Type<DB>,
+    for<'q> f64: sqlx::Encode<'q, DB> + sqlx::Type<DB>,
+    for<'q> bool: sqlx::Encode<'q, DB> + sqlx::Type<DB>,
+    for<'q> Option<String>: sqlx::Encode<'q, DB> + sqlx::Type<DB>,
+    for<'a> PSqlRowMapSer<'a, DB::Row>: Serialize,
+{
+    let dialect = dialect_of::<DB>().sqlparser_dialect();
+    let stmts = program.render(dialect.as_ref(), context)?;
+    if stmts.len() != 1 {
+        return Err(StreamError::StatementCount(stmts.len()));
+    }
+    let (sql, values) = program.render_prepared(dialect.as_ref(), context)?;
+
+    let mut rows = 0;
+    let write = |rows| move |source| StreamError::Write { rows, source };
+    if format == StreamFormat::JsonArray {
+        writer.write_all(b"[").await.map_err(write(rows))?;
+    }
+    let mut fetched = bind_values(sqlx::query(&sql), &values).fetch(pool);
+    while let Some(row) = fetched
+        .try_next()
+        .await
+        .map_err(|source| StreamError::Database { rows, source })?
+    {
+        let mut buf = vec![];
+        if format == StreamFormat::JsonArray && rows > 0 {
+            buf.push(b',');
+        }
+        serde_json::to_writer(&mut buf, &PSqlRowMapSer(&row, SerOptions::default()))
+            .map_err(|source| StreamError::Serialize { rows, source })?;
+        if format == StreamFormat::Ndjson {
+            buf.push(b'\n');
+        }
+        writer.write_all(&buf).await.map_err(write(rows))?;
+        rows += 1;
+    }
+    if format == StreamFormat::JsonArray {
+        writer.write_all(b"]").await.map_err(write(rows))?;
+    }
+    writer.flush().await.map_err(write(rows))?;
+    Ok(rows)
+}
+
+/// rows sent ahead of a slow client, a full channel holds back the database cursor
+const NDJSON_BUFFER: usize = 16;
+
+/// fetch rows of prepared `sql` on a spawned task and yield each one as a json line,
+/// serialized like http response rows. Waits for the first row, so an error of the query
+/// itself is returned here instead of in the middle of the response.
+///
+/// `permit` of connection limiter is held until the last row is sent. The task stops
+/// when the returned stream is dropped, e.g. client disconnects.
+pub(crate) async fn ndjson_lines<DB>(
+    pool: sqlx::Pool<DB>,
+    sql: String,
+    values: Vec<ParamValue>,
+    blob: Option<(Vec<u8>, usize)>,
+    options: SerOptions<'_>,
+    permit: Option<OwnedSemaphorePermit>,
+) -> Result<impl Stream<Item = Result<Vec<u8>, StreamError>>, StreamError>
+where
+    DB: sqlx::Database,
+    for<'c> &'c mut DB::Connection: sqlx::Executor<'c, Database = DB>,
+    for<'a> <DB as sqlx::database::HasArguments<'a>>::Arguments: sqlx::IntoArguments<'a, DB>,
+    for<'q> String: sqlx::Encode<'q, DB> + sqlx::Type<DB>,
+    for<'q> i64: sqlx::Encode<'q, DB> + sqlx::Type<DB>,
+    for<'q> f64: sqlx::Encode<'q, DB> + sqlx::Type<DB>,
+    for<'q> bool: sqlx::Encode<'q, DB> + sqlx::Type<DB>,
+    for<'q> Option<String>: sqlx::Encode<'q, DB> + sqlx::Type<DB>,
+    for<'q> Vec<u8>: sqlx::Encode<'q, DB> + sqlx::Type<DB>,
+    for<'a> PSqlRowMapSer<'a, DB::Row>: Serialize,
+    for<'a> PSqlRowNestedSer<'a, DB::Row>: Serialize,
+{
+    let (nest, offset, decimal_as_number) =
+        (options.nest, options.offset, options.decimal_as_number);
+    let bool_columns = options.bool_columns.to_vec();
+    let datetime_format = options.datetime_format.map(str::to_string);
+    let (tx, mut rx) = mpsc::channel(NDJSON_BUFFER);
+    tokio::spawn(async move {
+        let _permit = permit;
+        let options = SerOptions {
+            nest,
+            bool_columns: &bool_columns,
+            offset,
+            datetime_format: datetime_format.as_deref(),
+            decimal_as_number,
+        };
+        let blob = blob.as_ref().map(|(blob, count)| (blob, *count));
+        let mut fetched = bind_values(bind_blob(sqlx::query(&sql), blob), &values).fetch(&pool);
+        let mut tree = None;
+        let mut rows = 0;
+        loop {
+            let line = match fetched.try_next().await {
+                Ok(Some(row)) => {
+                    let tree = tree.get_or_insert_with(|| {
+                        let names: Vec<&str> = row.columns().iter().map(|c| c.name()).collect();
+                        (nest && names.iter().any(|name| name.contains('.')))
+                            .then(|| column_tree(&names))
+                    });
+                    let mut buf = match tree {
+                        Some(tree) => serde_json::to_vec(&PSqlRowNestedSer(&row, tree, options)),
+                        None => serde_json::to_vec(&PSqlRowMapSer(&row, options)),
+                    }
+                    .map_err(|source| StreamError::Serialize { rows, source });
+                    if let Ok(buf) = buf.as_mut() {
+                        buf.push(b'\n');
+                    }
+                    buf
+                }
+                Ok(None) => break,
+                Err(source) => Err(StreamError::Database { rows, source }),
+            };
+            let failed = line.is_err();
+            if tx.send(line).await.is_err() || failed {
+                break;
+            }
+            rows += 1;
+        }
+    });
+    let first = match rx.recv().await {
+        Some(Err(e)) => return Err(e),
+        first => first,
+    };
+    let rest = futures::stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|line| (line, rx))
+    });
+    Ok(futures::stream::iter(first).chain(rest))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ndjson_stream() {
+        use crate::http::{spawn_dynamic_http, Plan};
+        let text =
+            "title = 't'\naddress = ['127.0.0.1:0']\n[sqlite_conns]\nlocal = 'sqlite::memory:'\n\
+            [queries.pick]\nconn = 'local'\npath = 'pick'\nnest = true\n\
+            sql = 'select value as \"a.b\", 2 as c from (select 1 as value union all select 3)'\n\
+            [queries.broken]\nconn = 'local'\npath = 'broken'\nsql = 'select * from missing'\n";
+        let plan = toml::from_str::<Plan>(text).unwrap();
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let responses = rt.block_on(async {
+            let pool = sqlx::SqlitePool::connect("sqlite::memory:").await.unwrap();
+            let sqlite_conns = [("local".to_string(), pool)].into_iter().collect();
+            let handle =
+                spawn_dynamic_http(plan, HashMap::new(), sqlite_conns, HashMap::new(), None)
+                    .unwrap();
+            let base = format!("http://{}/api", handle.addrs()[0]);
+            let client = reqwest::Client::new();
+            let mut responses = vec![];
+            for (path, accept) in [
+                ("pick", "application/x-ndjson"),
+                ("pick?__stream=1", "application/json"),
+                ("pick", "application/json, application/x-ndjson"),
+                ("broken", "application/x-ndjson"),
+            ] {
+                let resp = client
+                    .get(format!("{}/{}", base, path))
+                    .header("accept", accept)
+                    .send()
+                    .await
+                    .unwrap();
+                let status = resp.status().as_u16();
+                let content_type = resp.headers()["content-type"].to_str().unwrap().to_string();
+                responses.push((status, content_type, resp.text().await.unwrap()));
+            }
+            handle.shutdown().await;
+            responses
+        });
+        let lines = (
+            200,
+            "application/x-ndjson".to_string(),
+            "{\"a\":{\"b\":1},\"c\":2}\n{\"a\":{\"b\":3},\"c\":2}\n".to_string(),
+        );
+        assert_eq!(responses[0], lines);
+        assert_eq!(responses[1], lines);
+        assert_eq!(
+            responses[2],
+            (
+                200,
+                "application/json".to_string(),
+                r#"[{"a":{"b":1},"c":2},{"a":{"b":3},"c":2}]"#.to_string()
+            )
+        );
+        // query failing before the first row gets the usual json error
+        assert_eq!(responses[3].0, 500);
+        assert_eq!(responses[3].1, "application/json");
+    }
+
+    #[test]
+    fn paginated_not_streamed() {
+        use crate::http::{spawn_dynamic_http, Plan};
+        let path = std::env::temp_dir().join(format!("psql-page-{}.jsonl", std::process::id()));
+        let text = format!(
+            "title = 't'\naddress = ['127.0.0.1:0']\n[sqlite_conns]\nlocal = 'sqlite::memory:'\n\
+            [audit]\nsink = '{}'\n\
+            [queries.page]\nconn = 'local'\npath = 'page'\npagination = {{}}\n\
+            sql = '''--? limit: num\n--? offset: num\n\
+            select value from (select 1 as value union all select 2 union all select 3) \
+            limit @limit offset @offset'''\n",
+            path.display()
+        );
+        let plan = toml::from_str::<Plan>(&text).unwrap();
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let (content_type, link, body) = rt.block_on(async {
+            let pool = sqlx::SqlitePool::connect("sqlite::memory:").await.unwrap();
+            let sqlite_conns = [("local".to_string(), pool)].into_iter().collect();
+            let handle =
+                spawn_dynamic_http(plan, HashMap::new(), sqlite_conns, HashMap::new(), None)
+                    .unwrap();
+            let resp = reqwest::get(format!(
+                "http://{}/api/page?limit=1&offset=1&__stream=1",
+                handle.addrs()[0]
+            ))
+            .await
+            .unwrap();
+            assert_eq!(resp.status().as_u16(), 200);
+            let content_type = resp.headers()["content-type"].to_str().unwrap().to_string();
+            let link = resp.headers()["link"].to_str().unwrap().to_string();
+            let body = resp.text().await.unwrap();
+            handle.shutdown().await;
+            (content_type, link, body)
+        });
+        // pages are buffered so the `Link` header and audit record get the row count
+        assert_eq!(content_type, "application/json");
+        assert_eq!(body, r#"[{"value":2}]"#);
+        assert!(link.contains("rel=\"next\""));
+        assert!(link.contains("rel=\"prev\""));
+        let written = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+        let entry = serde_json::from_str::<serde_json::Value>(written.trim()).unwrap();
+        assert_eq!(entry["rows"], 1);
+    }
+
+    #[test]
+    fn stream_results_formats() {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let dialect = sqlparser::dialect::SQLiteDialect {};
+        let prog = Program::parse(
+            &dialect,
+            "--? n: num\nselect value as v, @n as n from (select 1 as value union all select 2)",
+        )
+        .unwrap();
+        let context = HashMap::from([("n".to_string(), ParamValue::Num(7.0))]);
+        rt.block_on(async {
+            let pool = sqlx::SqlitePool::connect("sqlite::memory:").await.unwrap();
+            let mut out = vec![];
+            let rows = stream_results(&pool, &prog, &context, &mut out, StreamFormat::JsonArray)
+                .await
+                .unwrap();
+            assert_eq!(rows, 2);
+            assert_eq!(
+                String::from_utf8(out).unwrap(),
+                r#"[{"v":1,"n":7},{"v":2,"n":7}]"#
+            );
+            let mut out = futures::io::AllowStdIo::new(vec![]);
+            stream_results(&pool, &prog, &context, &mut out, StreamFormat::Ndjson)
+                .await
+                .unwrap();
+            assert_eq!(
+                String::from_utf8(out.into_inner()).unwrap(),
+                "{\"v\":1,\"n\":7}\n{\"v\":2,\"n\":7}\n"
+            );
+            let two = Program::parse(&dialect, "select 1; select 2").unwrap();
+            let err = stream_results(&pool, &two, &context, &mut vec![], StreamFormat::Ndjson)
+                .await
+                .unwrap_err();
+            assert!(matches!(err, StreamError::StatementCount(2)));
+            let mut out = vec![];
+            let missing = Program::parse(&dialect, "select * from missing").unwrap();
+            let err = stream_results(&pool, &missing, &context, &mut out, StreamFormat::JsonArray)
+                .await
+                .unwrap_err();
+            assert!(matches!(err, StreamError::Database { rows: 0, .. }));
+            assert_eq!(out, b"[");
+        });
+    }
+}
@@ -0,0 +1,285 @@
+//! generate typescript types of plan queries, so front-end gets typed clients
+//! without an openapi generator
+//!
+//! params are typed from their declarations, response rows from sql projection.
+//! Databases decide most column types at runtime, so only literals and `count(..)`
+//! are typed, other columns are `unknown`.
+
+use std::{collections::HashSet, fmt::Write};
+
+use indexmap::IndexMap;
+use sqlparser::ast::{Expr, SelectItem, Statement, Value};
+
+use super::{
+    expand::{column_name, projection},
+    output::{column_tree, ColumnNode},
+    plan::{Method, Plan},
+};
+use crate::{
+    errors::PSqlError,
+    parser::{InnerTy, Param, ParamTy},
+};
+
+/// typescript declarations of every query params and response, plus an `Api` interface
+/// mapping `<METHOD> <path>` to them
+pub fn generate(plan: &Plan) -> Result<String, PSqlError> {
+    let mut out = format!("// generated by psql from plan `{}`\n", plan.title);
+    let mut api = vec![];
+    let mut type_names = HashSet::new();
+    for (name, query) in plan.queries.iter() {
+        let dialect = plan
+            .conn_dialect(&query.conn)
+            .ok_or_else(|| PSqlError::UnknownConn(query.conn.clone()))?;
+        let prog = query.read_sql(&dialect)?;
+        let type_name = type_name(name, &mut type_names);
+        let injected = plan.conn_param_values(&query.conn);
+
+        out.push('\n');
+        if let Some(summary) = &query.summary {
+            writeln!(out, "/** {} */", doc_text(summary)).unwrap();
+        }
+        writeln!(out, "export interface {}Params {{", type_name).unwrap();
+        // blob param is request body, not a field of params
+        let blob = query.blob.as_ref().map(|blob| blob.param.as_str());
+        for p in prog.params.iter().filter(|p| Some(p.name.as_str()) != blob) {
+            let help = p.help_for(plan.locale.as_deref());
+            if !help.is_empty() {
+                writeln!(out, "  /** {} */", doc_text(help)).unwrap();
+            }
+            let optional = !p.required() || injected.contains_key(&p.name);
+            writeln!(
+                out,
+                "  {}{}: {};",
+                prop_name(&p.name),
+                if optional { "?" } else { "" },
+                param_type(p) + if p.nullable { " | null" } else { "" }
+            )
+            .unwrap();
+        }
+        out.push_str("}\n");
+
+        let mut stmts = prog.sample_statements(dialect.sqlparser_dialect().as_ref())?;
+        query.expand_stars(&mut stmts, &dialect);
+        if query.transactional {
+            stmts.drain(..stmts.len().saturating_sub(1));
+        }
+        let response = match stmts.as_slice() {
+            _ if query.response_content_type.is_some() => "string".to_string(),
+            [Statement::Query(q)] if query.scalar => match projection(&q.body) {
+                Some([item]) => column(item).1.to_string(),
+                _ => "unknown".to_string(),
+            },
+            // one row array per statement, write statements give empty sets
+            stmts if query.multi_result => {
+                let sets = stmts
+                    .iter()
+                    .map(|stmt| match stmt {
+                        Statement::Query(q) => match projection(&q.body) {
+                            Some(items) => format!("{}[]", row_type(items, query.nest)),
+                            None => "Record<string, unknown>[]".to_string(),
+                        },
+                        _ => "[]".to_string(),
+                    })
+                    .collect::<Vec<_>>();
+                format!("[{}]", sets.join(", "))
+            }
+            [Statement::Insert { .. } | Statement::Update { .. } | Statement::Delete { .. }] => {
+                "{ rows_affected: number; last_insert_id?: number }".to_string()
+            }
+            [Statement::Query(q)] => {
+                let row = match projection(&q.body) {
+                    Some(items) => row_type(items, query.nest),
+                    None => "Record<string, unknown>".to_string(),
+                };
+                writeln!(out, "\nexport type {}Row = {};", type_name, row).unwrap();
+                format!("{}Row[]", type_name)
+            }
+            _ => "unknown".to_string(),
+        };
+        writeln!(out, "\nexport type {}Response = {};", type_name, response).unwrap();
+        api.push((method_name(&query.method), &query.path, type_name));
+    }
+
+    out.push_str("\nexport interface Api {\n");
+    for (method, path, type_name) in api {
+        writeln!(
+            out,
+            "  \"{} /{}\": {{ params: {}Params; response: {}Response }};",
+            method, path, type_name, type_name
+        )
+        .unwrap();
+    }
+    out.push_str("}\n");
+    Ok(out)
+}
+
+fn method_name(method: &Method) -> &'static str {
+    match method {
+        Method::Get => "GET",
+        Method::Post => "POST",
+        Method::Put => "PUT",
+        Method::Patch => "PATCH",
+        Method::Delete => "DELETE",
+    }
+}
+
+/// choices are typed as union of literals
+fn param_type(p: &Param) -> String {
+    let inner = |ty: &InnerTy| match (&p.choices, ty) {
+        (Some(choices), _) => choices
+            .iter()
+            .map(|choice| serde_json::Value::from(choice.clone()).to_string())
+            .collect::<Vec<_>>()
+            .join(" | "),
+        (
+            None,
+            InnerTy::Str
+            | InnerTy::Raw
+            | InnerTy::Date
+            | InnerTy::Datetime
+            | InnerTy::Subquery
+            | InnerTy::Ident,
+        ) => "string".to_string(),
+        (None, InnerTy::Num) => "number".to_string(),
+        (None, InnerTy::Bool) => "boolean".to_string(),
+    };
+    match &p.ty {
+        ParamTy::Basic(ty) => inner(ty),
+        ParamTy::Array(ty) if p.choices.is_some() => format!("({})[]", inner(ty)),
+        ParamTy::Array(ty) => format!("{}[]", inner(ty)),
+    }
+}
+
+/// column name and type of select item
+fn column(item: &SelectItem) -> (String, &'static str) {
+    let expr = match item {
+        SelectItem::UnnamedExpr(expr) | SelectItem::ExprWithAlias { expr, .. } => expr,
+        SelectItem::Wildcard | SelectItem::QualifiedWildcard(_) => unreachable!(),
+    };
+    let ty = match expr {
+        Expr::Value(Value::Number(..) | Value::Boolean(_)) => "number",
+        Expr::Value(Value::SingleQuotedString(_)) => "string",
+        Expr::Function(f) if f.name.to_string().eq_ignore_ascii_case("count") => "number",
+        _ => "unknown",
+    };
+    (column_name(item), ty)
+}
+
+fn row_type(items: &[SelectItem], nest: bool) -> String {
+    let columns = items.iter().map(column).collect::<Vec<_>>();
+    let names = columns
+        .iter()
+        .map(|(name, _)| name.as_str())
+        .collect::<Vec<_>>();
+    let tree = if nest {
+        column_tree(&names)
+    } else {
+        names
+            .iter()
+            .enumerate()
+            .map(|(idx, name)| (name.to_string(), ColumnNode::Leaf(idx)))
+            .collect()
+    };
+    object_type(&tree, &columns, 1)
+}
+
+fn object_type(
+    nodes: &IndexMap<String, ColumnNode>,
+    columns: &[(String, &str)],
+    depth: usize,
+) -> String {
+    let indent = "  ".repeat(depth);
+    let mut out = "{\n".to_string();
+    for (name, node) in nodes.iter() {
+        let ty = match node {
+            ColumnNode::Leaf(idx) => columns[*idx].1.to_string(),
+            ColumnNode::Branch(children) => object_type(children, columns, depth + 1),
+        };
+        writeln!(out, "{}{}: {};", indent, prop_name(name), ty).unwrap();
+    }
+    out.push_str(&"  ".repeat(depth - 1));
+    out.push('}');
+    out
+}
+
+/// quote property name unless it's a valid identifier
+fn prop_name(name: &str) -> String {
+    let ident = name
+        .chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_' || c == '$')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '$');
+    if ident {
+        name.to_string()
+    } else {
+        serde_json::Value::String(name.to_string()).to_string()
+    }
+}
+
+/// `list_users` to `ListUsers`
+/// pascal case of query name, numbered in order of queries when names like `a_b` and `a-b`
+/// end up the same, so every query gets its own types
+fn type_name(name: &str, used: &mut HashSet<String>) -> String {
+    let pascal = pascal_case(name);
+    let mut type_name = pascal.clone();
+    let mut n = 1;
+    while !used.insert(type_name.clone()) {
+        n += 1;
+        type_name = format!("{}{}", pascal, n);
+    }
+    if n > 1 {
+        log::warn!(
+            "typescript types of query {} are named {} as {} is taken",
+            name,
+            type_name,
+            pascal
+        );
+    }
+    type_name
+}
+
+fn pascal_case(name: &str) -> String {
+    let pascal = name
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .map(|word| {
+            let mut chars = word.chars();
+            chars
+                .next()
+                .map(|c| c.to_ascii_uppercase().to_string() + chars.as_str())
+                .unwrap_or_default()
+        })
+        .collect::<String>();
+    if pascal.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        pascal
+    } else {
+        format!("Query{}", pascal)
+    }
+}
+
+fn doc_text(text: &str) -> String {
+    text.replace("*/", "* /").replace('\n', " ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn type_name_collision() {
+        let text = "title = 't'\n[sqlite_conns]\nlocal = 'sqlite::memory:'\n\
+            [queries.a_b]\nconn = 'local'\npath = 'a_b'\nsql = 'select 1 as n'\n\
+            [queries.a-b]\nconn = 'local'\npath = 'a-b'\nsql = 'select 2 as n'\n\
+            [queries.a_b2]\nconn = 'local'\npath = 'a_b2'\nsql = 'select 3 as n'\n";
+        let plan = toml::from_str::<Plan>(text).unwrap();
+        let out = generate(&plan).unwrap();
+        for name in ["AB", "AB2", "AB22"] {
+            let decl = format!("export interface {}Params {{", name);
+            assert_eq!(out.matches(&decl).count(), 1, "{}\n{}", name, out);
+        }
+        assert!(out.contains("\"GET /a_b\": { params: ABParams; response: ABResponse }"));
+        assert!(out.contains("\"GET /a-b\": { params: AB2Params; response: AB2Response }"));
+        assert!(out.contains("\"GET /a_b2\": { params: AB22Params; response: AB22Response }"));
+    }
+}
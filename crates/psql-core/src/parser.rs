@@ -2,48 +2,65 @@ use crate::{errors::PSqlError, token::VariableToken};
 use indexmap::IndexMap;
 use nom::{
     branch::alt,
-    bytes::complete::{is_not, tag, take_while},
-    character::complete::{alpha1, alphanumeric1, char},
-    combinator::{map, opt, recognize},
+    bytes::complete::{escaped_transform, is_not, tag, take_while, take_while1},
+    character::complete::{alpha1, alphanumeric1, anychar, char, digit1, space1},
+    combinator::{cut, map, not, opt, recognize, value},
     error::context,
     error::{ContextError as NomContextError, ParseError as NomParseError},
-    multi::{many0, separated_list0},
+    multi::{many0, many1, separated_list0, separated_list1},
     number::complete::double as nom_double,
-    sequence::{pair, preceded, terminated, tuple},
+    sequence::{delimited, pair, preceded, terminated, tuple},
     IResult,
 };
 #[cfg(feature = "http")]
 use openapiv3::{
     ArrayType, NumberType, Parameter, ParameterData, ParameterSchemaOrContent, ReferenceOr, Schema,
-    SchemaData, SchemaKind, StringType, Type,
+    SchemaData, SchemaKind, StringFormat, StringType, Type, VariantOrUnknownOrEmpty,
 };
 use openapiv3::{MediaType, ObjectType, RequestBody};
 #[cfg(feature = "http")]
-use serde::Deserialize;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 
+use regex::Regex;
 use sqlparser::{
-    dialect::Dialect,
+    ast::Statement,
+    dialect::{
+        keywords::Keyword, Dialect, GenericDialect, MySqlDialect, PostgreSqlDialect, SQLiteDialect,
+    },
     tokenizer::{Token, Whitespace},
 };
-use std::collections::{HashMap, HashSet};
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+    fmt,
+};
 
-#[derive(Debug, PartialEq, Clone, Deserialize)]
-#[serde(untagged)]
+/// value of param, (de)serialized as plain json, string for `Str`, `Raw` and `Ident`,
+/// number for `Num`, array for `Array` and null for `Null`
+#[derive(Debug, PartialEq, Clone)]
 pub enum ParamValue {
     Str(String),
     Num(f64),
     Raw(String),
+    /// sql identifier, rendered as word
+    Ident(String),
+    Bool(bool),
     Array(Vec<ParamValue>),
+    /// sql `NULL` of nullable param
+    Null,
 }
 
-impl ToString for ParamValue {
-    fn to_string(&self) -> String {
+impl fmt::Display for ParamValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            ParamValue::Str(str) => format!("'{}'", str),
-            ParamValue::Num(num) => num.to_string(),
-            ParamValue::Raw(raw) => raw.clone(),
+            ParamValue::Str(str) => write!(f, "{}", quote_str(str)),
+            ParamValue::Num(num) => write!(f, "{}", num),
+            ParamValue::Raw(raw) | ParamValue::Ident(raw) => write!(f, "{}", raw),
+            ParamValue::Bool(val) => write!(f, "{}", if *val { "TRUE" } else { "FALSE" }),
+            ParamValue::Null => write!(f, "NULL"),
             ParamValue::Array(arr) => {
-                format!(
+                write!(
+                    f,
                     "({})",
                     arr.iter()
                         .map(|i| i.to_string())
@@ -55,7 +72,7 @@ impl ToString for ParamValue {
     }
 }
 
-#[cfg(feature = "http")]
+#[cfg(any(feature = "http", feature = "cli"))]
 impl From<ParamValue> for serde_json::Value {
     fn from(source: ParamValue) -> Self {
         match source {
@@ -63,7 +80,9 @@ impl From<ParamValue> for serde_json::Value {
             ParamValue::Num(num) => {
                 serde_json::Value::Number(serde_json::Number::from_f64(num).unwrap())
             }
-            ParamValue::Raw(raw) => serde_json::Value::String(raw),
+            ParamValue::Raw(raw) | ParamValue::Ident(raw) => serde_json::Value::String(raw),
+            ParamValue::Bool(val) => serde_json::Value::Bool(val),
+            ParamValue::Null => serde_json::Value::Null,
             ParamValue::Array(arr) => serde_json::Value::Array(
                 arr.into_iter()
                     .map(serde_json::Value::from)
@@ -73,14 +92,166 @@ impl From<ParamValue> for serde_json::Value {
     }
 }
 
+#[cfg(feature = "http")]
+impl Serialize for ParamValue {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            ParamValue::Str(val) | ParamValue::Raw(val) | ParamValue::Ident(val) => {
+                serializer.serialize_str(val)
+            }
+            ParamValue::Num(val) => serializer.serialize_f64(*val),
+            ParamValue::Bool(val) => serializer.serialize_bool(*val),
+            ParamValue::Null => serializer.serialize_unit(),
+            ParamValue::Array(arr) => serializer.collect_seq(arr),
+        }
+    }
+}
+
+#[cfg(feature = "http")]
+impl<'de> Deserialize<'de> for ParamValue {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct ValueVisitor;
+
+        impl<'de> de::Visitor<'de> for ValueVisitor {
+            type Value = ParamValue;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a string, a number, a bool, null or an array of them")
+            }
+
+            fn visit_unit<E: de::Error>(self) -> Result<ParamValue, E> {
+                Ok(ParamValue::Null)
+            }
+
+            fn visit_bool<E: de::Error>(self, v: bool) -> Result<ParamValue, E> {
+                Ok(ParamValue::Bool(v))
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<ParamValue, E> {
+                Ok(ParamValue::Str(v.to_string()))
+            }
+
+            fn visit_string<E: de::Error>(self, v: String) -> Result<ParamValue, E> {
+                Ok(ParamValue::Str(v))
+            }
+
+            fn visit_f64<E: de::Error>(self, v: f64) -> Result<ParamValue, E> {
+                Ok(ParamValue::Num(v))
+            }
+
+            fn visit_i64<E: de::Error>(self, v: i64) -> Result<ParamValue, E> {
+                Ok(ParamValue::Num(v as f64))
+            }
+
+            fn visit_u64<E: de::Error>(self, v: u64) -> Result<ParamValue, E> {
+                Ok(ParamValue::Num(v as f64))
+            }
+
+            fn visit_seq<A: de::SeqAccess<'de>>(self, mut seq: A) -> Result<ParamValue, A::Error> {
+                let mut items = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+                while let Some(item) = seq.next_element()? {
+                    items.push(item);
+                }
+                Ok(ParamValue::Array(items))
+            }
+        }
+
+        deserializer.deserialize_any(ValueVisitor)
+    }
+}
+
+/// single quoted sql string literal, embedded single quotes are doubled
+fn quote_str(val: &str) -> String {
+    format!("'{}'", val.replace('\'', "''"))
+}
+
+/// sql text of token, unlike `Display` of token, single quotes of string literal are escaped
+fn token_sql(token: &Token) -> String {
+    match token {
+        Token::SingleQuotedString(val) => quote_str(val),
+        token => token.to_string(),
+    }
+}
+
+/// tokens of ident value used as sort key with collation of param, and tokens of null
+/// ordering which go after sort direction. MySQL has no `NULLS FIRST|LAST`, it sorts on
+/// `<key> IS NULL` before key instead
+fn sort_key_tokens(param: &Param, key: &str, dialect: &dyn Dialect) -> (Vec<Token>, Vec<Token>) {
+    let space = || Token::Whitespace(Whitespace::Space);
+    let mut tokens = vec![];
+    let mut nulls = vec![];
+    match param.nulls {
+        Some(order) if dialect.is::<MySqlDialect>() => {
+            tokens.extend([
+                Token::make_word(key, None),
+                space(),
+                Token::make_keyword("IS"),
+            ]);
+            if order == NullsOrder::First {
+                tokens.extend([space(), Token::make_keyword("NOT")]);
+            }
+            tokens.extend([space(), Token::make_keyword("NULL"), Token::Comma, space()]);
+        }
+        Some(order) => nulls.extend([
+            space(),
+            Token::make_keyword("NULLS"),
+            space(),
+            Token::make_keyword(&order.to_string()),
+        ]),
+        None => {}
+    }
+    tokens.push(Token::make_word(key, None));
+    if let Some(collation) = &param.collation {
+        // postgres folds unquoted names to lower case, its collations are often mixed case
+        let quote = if dialect.is::<MySqlDialect>() || dialect.is::<SQLiteDialect>() {
+            None
+        } else {
+            Some('"')
+        };
+        tokens.extend([
+            space(),
+            Token::make_keyword("COLLATE"),
+            space(),
+            Token::make_word(collation, quote),
+        ]);
+    }
+    (tokens, nulls)
+}
+
+/// whether token is sort direction `ASC` or `DESC`, written in sql or given as ident value
+fn is_sort_direction(token: &VariableToken, context: &HashMap<String, ParamValue>) -> bool {
+    let word = match token {
+        VariableToken::Normal(Token::Word(word)) if word.quote_style.is_none() => &word.value,
+        VariableToken::Var(var) => match context.get(var) {
+            Some(ParamValue::Ident(val)) => val,
+            _ => return false,
+        },
+        _ => return false,
+    };
+    word.eq_ignore_ascii_case("asc") || word.eq_ignore_ascii_case("desc")
+}
+
 impl ParamValue {
-    pub fn into_token<D: Dialect>(self, dialect: &D) -> Vec<Token> {
+    /// tokens of value, string literal keeps raw value and is escaped when statement is
+    /// written back to sql. MySQL also treats backslash as escape char in string literal,
+    /// so backslashes are doubled for it. Empty array is `(NULL)`, as `IN ()` is a syntax
+    /// error, `x IN (NULL)` matches nothing
+    pub fn into_token(self, dialect: &dyn Dialect) -> Vec<Token> {
         match self {
+            ParamValue::Str(val) if dialect.is::<MySqlDialect>() => {
+                vec![Token::SingleQuotedString(val.replace('\\', "\\\\"))]
+            }
             ParamValue::Str(val) => vec![Token::SingleQuotedString(val)],
             ParamValue::Num(val) => vec![Token::Number(val.to_string(), false)],
             ParamValue::Raw(val) => sqlparser::tokenizer::Tokenizer::new(dialect, &val)
                 .tokenize()
                 .unwrap(),
+            ParamValue::Ident(val) => vec![Token::make_word(&val, None)],
+            ParamValue::Bool(val) => vec![Token::make_keyword(if val { "TRUE" } else { "FALSE" })],
+            ParamValue::Null => vec![Token::make_keyword("NULL")],
+            ParamValue::Array(val) if val.is_empty() => {
+                vec![Token::LParen, Token::make_keyword("NULL"), Token::RParen]
+            }
             ParamValue::Array(val) => {
                 let mut tokens = vec![Token::LParen];
                 let length = val.len();
@@ -95,6 +266,21 @@ impl ParamValue {
             }
         }
     }
+    /// plain text of value, string is not quoted and array items are joined by `, `
+    pub fn to_text(&self) -> String {
+        match self {
+            ParamValue::Str(val) | ParamValue::Raw(val) | ParamValue::Ident(val) => val.clone(),
+            ParamValue::Num(val) => val.to_string(),
+            ParamValue::Bool(val) => val.to_string(),
+            ParamValue::Null => "null".to_string(),
+            ParamValue::Array(val) => val
+                .iter()
+                .map(|item| item.to_text())
+                .collect::<Vec<_>>()
+                .join(", "),
+        }
+    }
+
     /// parse from arg string
     ///
     /// **NOTE** string parsed from arg isn't wrapped with `'` or `"`
@@ -110,6 +296,19 @@ impl ParamValue {
                     Err(PSqlError::InvalidArgValue(arg_str.to_string(), ty.clone()))
                 }
             }
+            InnerTy::Date | InnerTy::Datetime => {
+                let value = ParamValue::Str(arg_str.to_string());
+                if ty.accepts(&value) {
+                    Ok(value)
+                } else {
+                    Err(PSqlError::InvalidArgValue(arg_str.to_string(), ty.clone()))
+                }
+            }
+            InnerTy::Bool => match arg_str {
+                "true" | "1" => Ok(ParamValue::Bool(true)),
+                "false" | "0" => Ok(ParamValue::Bool(false)),
+                _ => Err(PSqlError::InvalidArgValue(arg_str.to_string(), ty.clone())),
+            },
             InnerTy::Raw => {
                 let (remain, val) = raw::<nom::error::VerboseError<&str>>(arg_str)
                     .map_err(|e| PSqlError::ParamParseError(e.to_string()))?;
@@ -119,6 +318,24 @@ impl ParamValue {
                     Err(PSqlError::InvalidArgValue(arg_str.to_string(), ty.clone()))
                 }
             }
+            InnerTy::Ident => {
+                let (remain, val) = identifier::<nom::error::VerboseError<&str>>(arg_str)
+                    .map_err(|_| PSqlError::InvalidArgValue(arg_str.to_string(), ty.clone()))?;
+                if remain.is_empty() {
+                    Ok(ParamValue::Ident(val))
+                } else {
+                    Err(PSqlError::InvalidArgValue(arg_str.to_string(), ty.clone()))
+                }
+            }
+            // re-written from parsed query, so comments and trailing statements can't get through
+            InnerTy::Subquery => {
+                let stmts = sqlparser::parser::Parser::parse_sql(&GenericDialect {}, arg_str)
+                    .map_err(|_| PSqlError::InvalidArgValue(arg_str.to_string(), ty.clone()))?;
+                match stmts.as_slice() {
+                    [Statement::Query(query)] => Ok(ParamValue::Raw(format!("({})", query))),
+                    _ => Err(PSqlError::InvalidArgValue(arg_str.to_string(), ty.clone())),
+                }
+            }
         }
     }
 }
@@ -128,15 +345,134 @@ pub enum InnerTy {
     Str,
     Num,
     Raw,
+    Bool,
+    /// `YYYY-MM-DD` string
+    Date,
+    /// RFC 3339 string, e.g. `2024-01-31T08:00:00Z`
+    Datetime,
+    /// single `SELECT` query, rendered in parentheses
+    Subquery,
+    /// sql identifier matching `[A-Za-z_][A-Za-z0-9_]*`, e.g. column of `ORDER BY`, rendered
+    /// unquoted
+    Ident,
+}
+
+impl fmt::Display for InnerTy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InnerTy::Str => write!(f, "str"),
+            InnerTy::Num => write!(f, "num"),
+            InnerTy::Raw => write!(f, "raw"),
+            InnerTy::Bool => write!(f, "bool"),
+            InnerTy::Date => write!(f, "date"),
+            InnerTy::Datetime => write!(f, "datetime"),
+            InnerTy::Subquery => write!(f, "subquery"),
+            InnerTy::Ident => write!(f, "ident"),
+        }
+    }
 }
 
-impl ToString for InnerTy {
-    fn to_string(&self) -> String {
+impl InnerTy {
+    /// whether single value matches type, date and datetime strings must be well formed
+    pub fn accepts(&self, value: &ParamValue) -> bool {
+        match (self, value) {
+            (InnerTy::Str, ParamValue::Str(_))
+            | (InnerTy::Num, ParamValue::Num(_))
+            | (InnerTy::Bool, ParamValue::Bool(_))
+            | (InnerTy::Raw, ParamValue::Str(_) | ParamValue::Raw(_))
+            | (InnerTy::Subquery, ParamValue::Raw(_))
+            | (InnerTy::Ident, ParamValue::Ident(_)) => true,
+            (InnerTy::Date, ParamValue::Str(val)) => valid_date(val),
+            (InnerTy::Datetime, ParamValue::Str(val)) => valid_datetime(val),
+            _ => false,
+        }
+    }
+
+    /// whether type is a formatted date or datetime string
+    pub fn is_temporal(&self) -> bool {
+        matches!(self, InnerTy::Date | InnerTy::Datetime)
+    }
+
+    /// expected format appended to invalid value error
+    pub fn format_hint(&self) -> &'static str {
         match self {
-            InnerTy::Str => "str".to_string(),
-            InnerTy::Num => "num".to_string(),
-            InnerTy::Raw => "raw".to_string(),
+            InnerTy::Date => ", expect YYYY-MM-DD",
+            InnerTy::Datetime => ", expect RFC 3339 datetime like 2024-01-31T08:00:00Z",
+            InnerTy::Subquery => ", expect a single SELECT query",
+            InnerTy::Ident => ", expect identifier matching [A-Za-z_][A-Za-z0-9_]*",
+            _ => "",
+        }
+    }
+}
+
+/// `YYYY-MM-DD` of an existing day
+fn valid_date(text: &str) -> bool {
+    let bytes = text.as_bytes();
+    if bytes.len() != 10 || bytes[4] != b'-' || bytes[7] != b'-' {
+        return false;
+    }
+    let (year, month, day) = match (digits(&text[0..4]), digits(&text[5..7]), digits(&text[8..])) {
+        (Some(year), Some(month), Some(day)) => (year, month, day),
+        _ => return false,
+    };
+    let leap = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+    let days = match month {
+        1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+        4 | 6 | 9 | 11 => 30,
+        2 if leap => 29,
+        2 => 28,
+        _ => return false,
+    };
+    (1..=days).contains(&day)
+}
+
+/// RFC 3339 `YYYY-MM-DDTHH:MM:SS[.frac](Z|+HH:MM|-HH:MM)`
+fn valid_datetime(text: &str) -> bool {
+    if !text.is_ascii() || text.len() < 20 {
+        return false;
+    }
+    let (date, rest) = text.split_at(10);
+    let (sep, rest) = rest.split_at(1);
+    if !valid_date(date) || !matches!(sep, "T" | "t" | " ") {
+        return false;
+    }
+    let (time, rest) = rest.split_at(8);
+    let (time, offset) = match rest.strip_prefix('.') {
+        Some(frac) => {
+            let frac_len = frac.bytes().take_while(u8::is_ascii_digit).count();
+            if frac_len == 0 {
+                return false;
+            }
+            (time, &frac[frac_len..])
+        }
+        None => (time, rest),
+    };
+    let clock = |text: &str, max_hour: u32, max_min: u32| {
+        let bytes = text.as_bytes();
+        bytes[2] == b':'
+            && digits(&text[..2]).is_some_and(|hour| hour <= max_hour)
+            && digits(&text[3..5]).is_some_and(|min| min <= max_min)
+    };
+    let time_ok = clock(&time[..5], 23, 59)
+        && time.as_bytes()[5] == b':'
+        // 60 for leap second
+        && digits(&time[6..]).is_some_and(|sec| sec <= 60);
+    let offset_ok = match offset {
+        "Z" | "z" => true,
+        _ => {
+            offset.len() == 6
+                && matches!(offset.as_bytes()[0], b'+' | b'-')
+                && clock(&offset[1..], 23, 59)
         }
+    };
+    time_ok && offset_ok
+}
+
+fn digits(text: &str) -> Option<u32> {
+    if text.bytes().all(|c| c.is_ascii_digit()) {
+        text.parse().ok()
+    } else {
+        None
     }
 }
 
@@ -150,6 +486,20 @@ impl InnerTy {
                 pattern: Some("^#.*#$".to_string()),
                 ..Default::default()
             })),
+            InnerTy::Bool => SchemaKind::Type(Type::Boolean {}),
+            InnerTy::Date => SchemaKind::Type(Type::String(StringType {
+                format: VariantOrUnknownOrEmpty::Item(StringFormat::Date),
+                ..Default::default()
+            })),
+            InnerTy::Datetime => SchemaKind::Type(Type::String(StringType {
+                format: VariantOrUnknownOrEmpty::Item(StringFormat::DateTime),
+                ..Default::default()
+            })),
+            InnerTy::Subquery => SchemaKind::Type(Type::String(StringType::default())),
+            InnerTy::Ident => SchemaKind::Type(Type::String(StringType {
+                pattern: Some("^[A-Za-z_][A-Za-z0-9_]*$".to_string()),
+                ..Default::default()
+            })),
         }
     }
 }
@@ -160,11 +510,134 @@ pub enum ParamTy {
     Array(InnerTy),
 }
 
-impl ToString for ParamTy {
-    fn to_string(&self) -> String {
+impl ParamTy {
+    /// type of single value or array item
+    pub fn inner(&self) -> &InnerTy {
+        match self {
+            ParamTy::Basic(ty) | ParamTy::Array(ty) => ty,
+        }
+    }
+}
+
+impl fmt::Display for ParamTy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParamTy::Basic(ty) => write!(f, "{}", ty),
+            ParamTy::Array(ty) => write!(f, "[{}]", ty),
+        }
+    }
+}
+
+/// value transform function, applied before value is validated and rendered
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Transform {
+    Upper,
+    Lower,
+    Trim,
+}
+
+impl fmt::Display for Transform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Transform::Upper => write!(f, "upper"),
+            Transform::Lower => write!(f, "lower"),
+            Transform::Trim => write!(f, "trim"),
+        }
+    }
+}
+
+impl Transform {
+    /// apply to string value, array is applied element wise, other values are kept as is
+    pub fn apply(&self, value: ParamValue) -> ParamValue {
+        match value {
+            ParamValue::Str(str) => ParamValue::Str(match self {
+                Transform::Upper => str.to_uppercase(),
+                Transform::Lower => str.to_lowercase(),
+                Transform::Trim => str.trim().to_string(),
+            }),
+            ParamValue::Array(arr) => {
+                ParamValue::Array(arr.into_iter().map(|item| self.apply(item)).collect())
+            }
+            other => other,
+        }
+    }
+}
+
+/// split `en: age // zh: 年龄` into localized help messages
+///
+/// only when there are at least two segments and every segment starts with a
+/// language tag, otherwise the whole text is a plain help message
+fn split_localized_help(help: &str) -> (String, HashMap<String, String>) {
+    let localized = help
+        .split("//")
+        .map(|segment| {
+            let (tag, text) = segment.split_once(':')?;
+            let tag = tag.trim();
+            let primary = tag.split(['-', '_']).next().unwrap_or_default();
+            let valid = primary.len() == 2
+                && primary.chars().all(|c| c.is_ascii_alphabetic())
+                && tag
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+            valid.then(|| {
+                (
+                    tag.replace('_', "-").to_lowercase(),
+                    text.trim().to_string(),
+                )
+            })
+        })
+        .collect::<Option<Vec<_>>>();
+    match localized {
+        Some(localized) if localized.len() > 1 => {
+            let help = localized[0].1.clone();
+            (help, localized.into_iter().collect())
+        }
+        _ => (help.to_string(), HashMap::new()),
+    }
+}
+
+/// regex which str values must match, format `/<regex>/`
+#[derive(Debug, Clone)]
+pub struct Pattern(Regex);
+
+impl Pattern {
+    pub fn new(regex: &str) -> Result<Self, regex::Error> {
+        Regex::new(regex).map(Self)
+    }
+
+    pub fn as_str(&self) -> &str {
+        self.0.as_str()
+    }
+
+    pub fn is_match(&self, text: &str) -> bool {
+        self.0.is_match(text)
+    }
+}
+
+impl PartialEq for Pattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_str() == other.as_str()
+    }
+}
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "/{}/", self.as_str())
+    }
+}
+
+/// null ordering of ident param used as sort key
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NullsOrder {
+    First,
+    Last,
+}
+
+impl fmt::Display for NullsOrder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            ParamTy::Basic(ty) => ty.to_string(),
-            ParamTy::Array(ty) => format!("[{}]", ty.to_string()),
+            NullsOrder::First => write!(f, "FIRST"),
+            NullsOrder::Last => write!(f, "LAST"),
         }
     }
 }
@@ -175,17 +648,284 @@ pub struct Param {
     pub name: String,
     pub ty: ParamTy,
     pub default: Option<ParamValue>,
+    /// name of param whose value is the default, format `= @other`
+    pub default_from: Option<String>,
     pub help: String,
+    /// localized help messages, keyed by lower case language tag, e.g. `en`, `zh-cn`
+    pub localized_help: HashMap<String, String>,
+    /// transforms applied in order to user input
+    pub transforms: Vec<Transform>,
+    /// params must be given along with this param
+    pub requires: Vec<String>,
+    /// params must not be given along with this param
+    pub conflicts: Vec<String>,
+    /// `NULL` is taken when param is omitted and has no default, format `<ty>?`
+    pub nullable: bool,
+    /// allowed values, format `{a, b, c}`, every item of array param must be one of them
+    pub choices: Option<Vec<ParamValue>>,
+    /// inclusive lower bound of num param, format `(<min>..)` or `(<min>..=<max>)`
+    pub min: Option<f64>,
+    /// inclusive upper bound of num param, format `(..=<max>)` or `(<min>..=<max>)`
+    pub max: Option<f64>,
+    /// regex which str value, or every item of array, must match, format `/<regex>/`
+    pub pattern: Option<Pattern>,
+    /// null ordering of ident param used as sort key, format `nulls first` or `nulls last`
+    pub nulls: Option<NullsOrder>,
+    /// collation of ident param used as sort key, format `collate <name>` or `collate "<name>"`
+    pub collation: Option<String>,
+}
+
+impl Param {
+    /// whether value matches param type
+    pub fn accepts(&self, value: &ParamValue) -> bool {
+        match (&self.ty, value) {
+            (_, ParamValue::Null) => self.nullable,
+            (ParamTy::Basic(ty), value) => ty.accepts(value),
+            (ParamTy::Array(ty), ParamValue::Array(items)) => {
+                items.iter().all(|item| ty.accepts(item))
+            }
+            (ParamTy::Array(_), _) => false,
+        }
+    }
+
+    /// whether caller must give a value
+    pub fn required(&self) -> bool {
+        self.default.is_none() && self.default_from.is_none() && !self.nullable
+    }
+
+    /// value taken when caller doesn't give one, `NULL` if param is nullable and has no default,
+    /// `None` if it's required or defaults to another param
+    pub fn omitted_value(&self) -> Option<ParamValue> {
+        match &self.default {
+            Some(default) => Some(default.clone()),
+            None if self.nullable && self.default_from.is_none() => Some(ParamValue::Null),
+            None => None,
+        }
+    }
+
+    /// whether value is one of choices, `NULL` is not restricted by choices
+    pub fn allows(&self, value: &ParamValue) -> bool {
+        match (&self.choices, value) {
+            (None, _) | (_, ParamValue::Null) => true,
+            (Some(choices), ParamValue::Array(items)) => {
+                items.iter().all(|item| choices.contains(item))
+            }
+            (Some(choices), value) => choices.contains(value),
+        }
+    }
+
+    /// check num value, or every item of array, against `min` and `max`
+    pub fn check_range(&self, value: &ParamValue) -> Result<(), PSqlError> {
+        match value {
+            ParamValue::Num(num) if self.min.is_some_and(|min| *num < min) => Err(
+                PSqlError::BelowMin(*num, self.name.clone(), self.min.unwrap_or_default()),
+            ),
+            ParamValue::Num(num) if self.max.is_some_and(|max| *num > max) => Err(
+                PSqlError::AboveMax(*num, self.name.clone(), self.max.unwrap_or_default()),
+            ),
+            ParamValue::Array(items) => items.iter().try_for_each(|item| self.check_range(item)),
+            _ => Ok(()),
+        }
+    }
+
+    /// check str value, or every item of array, against `pattern`, other values than str and
+    /// `NULL` are rejected if param has a pattern
+    pub fn check_pattern(&self, value: &ParamValue) -> Result<(), PSqlError> {
+        match (&self.pattern, value) {
+            (Some(pattern), ParamValue::Str(text)) if !pattern.is_match(text) => {
+                Err(PSqlError::InvalidArgValue(
+                    format!("{} (expect match {})", text, pattern),
+                    self.ty.inner().clone(),
+                ))
+            }
+            (_, ParamValue::Array(items)) => {
+                items.iter().try_for_each(|item| self.check_pattern(item))
+            }
+            (Some(pattern), value) if !matches!(value, ParamValue::Str(_) | ParamValue::Null) => {
+                Err(PSqlError::InvalidArgValue(
+                    format!("{} (expect string matching {})", value, pattern),
+                    self.ty.inner().clone(),
+                ))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// range text, e.g. `1..=100`, empty if param has no bounds
+    pub fn range_text(&self) -> String {
+        match (self.min, self.max) {
+            (None, None) => String::new(),
+            (min, Some(max)) => format!(
+                "{}..={}",
+                min.map(|min| min.to_string()).unwrap_or_default(),
+                max
+            ),
+            (Some(min), None) => format!("{}..", min),
+        }
+    }
+
+    /// choices joined by `, `
+    pub fn choices_text(&self) -> String {
+        self.choices
+            .iter()
+            .flatten()
+            .map(|choice| choice.to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    /// check default value and choices against param constraints
+    pub fn check_default(&self) -> Result<(), String> {
+        fn finite(value: &ParamValue) -> bool {
+            match value {
+                ParamValue::Num(num) => num.is_finite(),
+                ParamValue::Array(items) => items.iter().all(finite),
+                _ => true,
+            }
+        }
+        if (self.min.is_some() || self.max.is_some()) && *self.ty.inner() != InnerTy::Num {
+            return Err(format!("range only applies to num param, got {}", self.ty));
+        }
+        if let (Some(min), Some(max)) = (self.min, self.max) {
+            if min > max {
+                return Err(format!("empty range {}", self.range_text()));
+            }
+        }
+        if let Some(choice) = self
+            .choices
+            .iter()
+            .flatten()
+            .find(|choice| !self.ty.inner().accepts(choice) || !finite(choice))
+        {
+            return Err(format!(
+                "choice {} doesn't match type {}",
+                choice,
+                self.ty.inner()
+            ));
+        }
+        match &self.default {
+            Some(default) if !self.accepts(default) => {
+                Err(format!("{} doesn't match type {}", default, self.ty))
+            }
+            Some(default) if !finite(default) => Err(format!("{} is not a finite number", default)),
+            Some(default) if !self.allows(default) => {
+                Err(format!("{} is not one of {}", default, self.choices_text()))
+            }
+            Some(default) => self
+                .check_range(default)
+                .and_then(|_| self.check_pattern(default))
+                .map_err(|e| e.to_string()),
+            _ => Ok(()),
+        }
+    }
+
+    /// whether ident param used as sort key has null ordering or collation
+    pub fn sort_options(&self) -> bool {
+        self.nulls.is_some() || self.collation.is_some()
+    }
+
+    /// check null ordering and collation against param type and what dialect supports,
+    /// MySQL null ordering is emulated, SQLite only has built-in collations
+    pub fn check_sort_options(&self, dialect: &dyn Dialect) -> Result<(), String> {
+        if !self.sort_options() {
+            return Ok(());
+        }
+        if self.ty != ParamTy::Basic(InnerTy::Ident) {
+            return Err(format!(
+                "nulls and collate only apply to ident param, got {}",
+                self.ty
+            ));
+        }
+        match &self.collation {
+            Some(name)
+                if dialect.is::<SQLiteDialect>()
+                    && !["BINARY", "NOCASE", "RTRIM"]
+                        .iter()
+                        .any(|builtin| builtin.eq_ignore_ascii_case(name)) =>
+            {
+                Err(format!(
+                    "sqlite has no collation {}, expect BINARY, NOCASE or RTRIM",
+                    name
+                ))
+            }
+            Some(name)
+                if dialect.is::<MySqlDialect>()
+                    && !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') =>
+            {
+                Err(format!("mysql collation {} must match [A-Za-z0-9_]+", name))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// pick help message for language preference, such as `zh-CN,zh;q=0.9,en;q=0.8`
+    ///
+    /// fallback to first help message if no language matches
+    pub fn help_for(&self, lang: Option<&str>) -> &str {
+        let tags = lang
+            .unwrap_or_default()
+            .split(',')
+            .filter_map(|item| item.split(';').next())
+            .map(|tag| tag.split('.').next().unwrap_or_default().trim())
+            .filter(|tag| !tag.is_empty() && *tag != "*")
+            .map(|tag| tag.replace('_', "-").to_lowercase());
+        for tag in tags {
+            if let Some(help) = self.localized_help.get(&tag) {
+                return help;
+            }
+            let primary = tag.split('-').next().unwrap_or_default();
+            if let Some((_, help)) = self
+                .localized_help
+                .iter()
+                .find(|(key, _)| key.split('-').next() == Some(primary))
+            {
+                return help;
+            }
+        }
+        &self.help
+    }
+
+    /// apply transforms to user input value
+    pub fn transform(&self, value: ParamValue) -> ParamValue {
+        self.transforms
+            .iter()
+            .fold(value, |value, transform| transform.apply(value))
+    }
 }
 
 #[cfg(feature = "http")]
 impl Param {
     pub fn to_openapi_schema(&self) -> Schema {
+        // choices are listed as enum of item schema, so swagger ui renders a dropdown
+        let item_kind = |inner_ty: &InnerTy| {
+            let mut kind = inner_ty.to_openapi_schema_kind();
+            let choices = self.choices.iter().flatten();
+            match &mut kind {
+                SchemaKind::Type(Type::String(ty)) => {
+                    if let Some(pattern) = &self.pattern {
+                        ty.pattern = Some(pattern.as_str().to_string());
+                    }
+                    ty.enumeration = choices.map(|choice| choice.to_text()).collect()
+                }
+                SchemaKind::Type(Type::Number(ty)) => {
+                    ty.minimum = self.min;
+                    ty.maximum = self.max;
+                    ty.enumeration = choices
+                        .filter_map(|choice| match choice {
+                            ParamValue::Num(num) => Some(*num),
+                            _ => None,
+                        })
+                        .collect()
+                }
+                _ => {}
+            }
+            kind
+        };
         let schema_kind = match &self.ty {
-            ParamTy::Basic(inner_ty) => inner_ty.to_openapi_schema_kind(),
+            ParamTy::Basic(inner_ty) => item_kind(inner_ty),
             ParamTy::Array(inner_ty) => SchemaKind::Type(Type::Array(ArrayType {
                 items: ReferenceOr::Item(Box::new(Schema {
-                    schema_kind: inner_ty.to_openapi_schema_kind(),
+                    schema_kind: item_kind(inner_ty),
                     schema_data: SchemaData {
                         default: None,
                         ..Default::default()
@@ -200,18 +940,19 @@ impl Param {
         Schema {
             schema_data: SchemaData {
                 default,
+                nullable: self.nullable,
                 ..Default::default()
             },
             schema_kind,
         }
     }
 
-    pub fn to_openapi_param(&self) -> Parameter {
+    pub fn to_openapi_param(&self, lang: Option<&str>) -> Parameter {
         Parameter::Query {
             parameter_data: ParameterData {
                 name: self.name.clone(),
-                description: Some(self.help.clone()),
-                required: self.default.is_none(),
+                description: Some(self.help_for(lang).to_string()),
+                required: self.required(),
                 deprecated: None,
                 format: ParameterSchemaOrContent::Schema(ReferenceOr::Item(
                     self.to_openapi_schema(),
@@ -228,29 +969,42 @@ impl Param {
     }
 }
 
+/// unescaped content of quoted string, `\'`, `\"`, `\\`, `\n` and `\t` are recognized
+fn quoted_content<'a, E: NomParseError<&'a str> + NomContextError<&'a str>>(
+    input: &'a str,
+    stop: &'static str,
+) -> IResult<&'a str, String, E> {
+    map(
+        opt(escaped_transform(
+            is_not(stop),
+            '\\',
+            alt((
+                value("\\", char('\\')),
+                value("'", char('\'')),
+                value("\"", char('"')),
+                value("\n", char('n')),
+                value("\t", char('t')),
+            )),
+        )),
+        Option::unwrap_or_default,
+    )(input)
+}
+
 fn double_quote_str<'a, E: NomParseError<&'a str> + NomContextError<&'a str>>(
     input: &'a str,
-) -> IResult<&'a str, &'a str, E> {
-    let not_quote_slash = is_not("\"\\");
+) -> IResult<&'a str, String, E> {
     context(
         "double quote str",
-        map(
-            tuple((tag("\""), not_quote_slash, tag("\""))),
-            |(_, str, _)| str,
-        ),
+        delimited(char('"'), |input| quoted_content(input, "\"\\"), char('"')),
     )(input)
 }
 
 fn single_quote_str<'a, E: NomParseError<&'a str> + NomContextError<&'a str>>(
     input: &'a str,
-) -> IResult<&'a str, &'a str, E> {
-    let not_quote_slash = is_not("'\\");
+) -> IResult<&'a str, String, E> {
     context(
         "single quote str",
-        map(
-            tuple((tag("'"), not_quote_slash, tag("'"))),
-            |(_, str, _)| str,
-        ),
+        delimited(char('\''), |input| quoted_content(input, "'\\"), char('\'')),
     )(input)
 }
 
@@ -259,21 +1013,31 @@ fn str<'a, E: NomParseError<&'a str> + NomContextError<&'a str>>(
 ) -> IResult<&'a str, ParamValue, E> {
     context(
         "str",
-        map(alt((single_quote_str, double_quote_str)), |val: &str| {
-            ParamValue::Str(val.to_string())
-        }),
+        map(alt((single_quote_str, double_quote_str)), ParamValue::Str),
     )(input)
 }
 
 fn double<'a, E: NomParseError<&'a str> + NomContextError<&'a str>>(
     input: &'a str,
-) -> IResult<&str, ParamValue, E> {
+) -> IResult<&'a str, ParamValue, E> {
     context("double", map(nom_double, ParamValue::Num))(input)
 }
 
+fn boolean<'a, E: NomParseError<&'a str> + NomContextError<&'a str>>(
+    input: &'a str,
+) -> IResult<&'a str, ParamValue, E> {
+    context(
+        "bool",
+        alt((
+            map(tag("true"), |_| ParamValue::Bool(true)),
+            map(tag("false"), |_| ParamValue::Bool(false)),
+        )),
+    )(input)
+}
+
 fn raw<'a, E: NomParseError<&'a str> + NomContextError<&'a str>>(
     input: &'a str,
-) -> IResult<&str, ParamValue, E> {
+) -> IResult<&'a str, ParamValue, E> {
     let not_quote_slash = is_not("#\\");
     context(
         "raw val",
@@ -286,7 +1050,7 @@ fn raw<'a, E: NomParseError<&'a str> + NomContextError<&'a str>>(
 
 fn no_newline_sp<'a, E: NomParseError<&'a str> + NomContextError<&'a str>>(
     input: &'a str,
-) -> IResult<&str, &str, E> {
+) -> IResult<&'a str, &'a str, E> {
     let chars = " \t";
     take_while(move |c| chars.contains(c))(input)
 }
@@ -298,7 +1062,7 @@ fn parse_array<
 >(
     input: &'a str,
     f: F,
-) -> IResult<&str, ParamValue, E> {
+) -> IResult<&'a str, ParamValue, E> {
     context(
         "array",
         map(
@@ -316,7 +1080,7 @@ fn parse_array<
 
 fn identifier<'a, E: NomParseError<&'a str> + NomContextError<&'a str>>(
     input: &'a str,
-) -> IResult<&str, String, E> {
+) -> IResult<&'a str, String, E> {
     context(
         "identifier",
         map(
@@ -329,22 +1093,34 @@ fn identifier<'a, E: NomParseError<&'a str> + NomContextError<&'a str>>(
     )(input)
 }
 
+fn ident<'a, E: NomParseError<&'a str> + NomContextError<&'a str>>(
+    input: &'a str,
+) -> IResult<&'a str, ParamValue, E> {
+    context("ident", map(identifier, ParamValue::Ident))(input)
+}
+
 fn basic_ty<'a, E: NomParseError<&'a str> + NomContextError<&'a str>>(
     input: &'a str,
-) -> IResult<&str, InnerTy, E> {
+) -> IResult<&'a str, InnerTy, E> {
     context(
         "basic ty",
         alt((
             map(tag("str"), |_| InnerTy::Str),
             map(tag("num"), |_| InnerTy::Num),
             map(tag("raw"), |_| InnerTy::Raw),
+            map(tag("bool"), |_| InnerTy::Bool),
+            map(tag("subquery"), |_| InnerTy::Subquery),
+            map(tag("ident"), |_| InnerTy::Ident),
+            // `datetime` goes first, otherwise `date` matches its prefix
+            map(tag("datetime"), |_| InnerTy::Datetime),
+            map(tag("date"), |_| InnerTy::Date),
         )),
     )(input)
 }
 
 fn parse_ty<'a, E: NomParseError<&'a str> + NomContextError<&'a str>>(
     input: &'a str,
-) -> IResult<&str, ParamTy, E> {
+) -> IResult<&'a str, ParamTy, E> {
     alt((
         context(
             "array ty",
@@ -372,29 +1148,226 @@ fn parse_default<'a, E: NomParseError<&'a str> + NomContextError<&'a str>>(
             InnerTy::Str => str(input),
             InnerTy::Num => double(input),
             InnerTy::Raw => raw(input),
+            InnerTy::Bool => boolean(input),
+            InnerTy::Date | InnerTy::Datetime | InnerTy::Subquery => str(input),
+            InnerTy::Ident => ident(input),
         },
         ParamTy::Array(inner_ty) => match inner_ty {
             InnerTy::Str => parse_array(input, str),
             InnerTy::Num => parse_array(input, double),
             InnerTy::Raw => parse_array(input, raw),
+            InnerTy::Bool => parse_array(input, boolean),
+            InnerTy::Date | InnerTy::Datetime | InnerTy::Subquery => parse_array(input, str),
+            InnerTy::Ident => parse_array(input, ident),
         },
     }
 }
 
-fn take_eq<'a, E: NomParseError<&'a str> + NomContextError<&'a str>>(
+fn transform<'a, E: NomParseError<&'a str> + NomContextError<&'a str>>(
     input: &'a str,
-) -> IResult<&'a str, (), E> {
-    map(
-        recognize(tuple((no_newline_sp, tag("="), no_newline_sp))),
-        |_| (),
-    )(input)
+) -> IResult<&'a str, Transform, E> {
+    context(
+        "transform",
+        alt((
+            map(tag("upper"), |_| Transform::Upper),
+            map(tag("lower"), |_| Transform::Lower),
+            map(tag("trim"), |_| Transform::Trim),
+        )),
+    )(input)
+}
+
+/// transforms list, format `|trim|upper|`
+fn transforms<'a, E: NomParseError<&'a str> + NomContextError<&'a str>>(
+    input: &'a str,
+) -> IResult<&'a str, Vec<Transform>, E> {
+    context(
+        "transforms",
+        preceded(
+            tuple((no_newline_sp, tag("|"))),
+            terminated(
+                separated_list1(tag("|"), delimited(no_newline_sp, transform, no_newline_sp)),
+                tag("|"),
+            ),
+        ),
+    )(input)
+}
+
+/// allowed values of param, format `{a, 'b c', d}`, str items may be unquoted
+fn choices<'a, E: NomParseError<&'a str> + NomContextError<&'a str>>(
+    input: &'a str,
+    ty: &InnerTy,
+) -> IResult<&'a str, Vec<ParamValue>, E> {
+    let item = |input: &'a str| match ty {
+        InnerTy::Str | InnerTy::Date | InnerTy::Datetime | InnerTy::Subquery => alt((
+            str,
+            map(is_not(",} \t'\"\r\n"), |val: &str| {
+                ParamValue::Str(val.to_string())
+            }),
+        ))(input),
+        InnerTy::Num => double(input),
+        InnerTy::Raw => raw(input),
+        InnerTy::Bool => boolean(input),
+        InnerTy::Ident => ident(input),
+    };
+    context(
+        "choices",
+        preceded(
+            tuple((no_newline_sp, tag("{"), no_newline_sp)),
+            // items must match param type once brace is open
+            cut(terminated(
+                separated_list1(tuple((no_newline_sp, tag(","), no_newline_sp)), item),
+                tuple((no_newline_sp, tag("}"))),
+            )),
+        ),
+    )(input)
+}
+
+/// choices list of param line which failed to parse, e.g. `{}` or `{id, 'name'}` of ident param
+fn failed_choices<'a>(err: &nom::Err<nom::error::VerboseError<&'a str>>) -> Option<&'a str> {
+    let errors = match err {
+        nom::Err::Error(e) | nom::Err::Failure(e) => &e.errors,
+        nom::Err::Incomplete(_) => return None,
+    };
+    errors.iter().find_map(|(input, kind)| match kind {
+        nom::error::VerboseErrorKind::Context("choices") => {
+            let line = input.trim_start().lines().next().unwrap_or_default();
+            Some(line.find('}').map_or(line, |end| &line[..=end]).trim_end())
+        }
+        _ => None,
+    })
+}
+
+/// name of param line `? <name>: ...`, for errors raised before the line is parsed
+fn param_name(line: &str) -> &str {
+    line.trim_start_matches('?')
+        .split(':')
+        .next()
+        .unwrap_or_default()
+        .trim()
+}
+
+/// regex of str param, format `/^[A-Z]{3}$/`, `\/` stands for `/`. `//` starts help
+/// message instead
+fn pattern<'a, E: NomParseError<&'a str> + NomContextError<&'a str>>(
+    input: &'a str,
+) -> IResult<&'a str, String, E> {
+    context(
+        "pattern",
+        map(
+            preceded(
+                tuple((no_newline_sp, char('/'), not(char('/')))),
+                cut(terminated(
+                    recognize(many1(alt((
+                        recognize(pair(char('\\'), anychar)),
+                        is_not("/\\\r\n"),
+                    )))),
+                    char('/'),
+                )),
+            ),
+            |regex: &str| regex.replace("\\/", "/"),
+        ),
+    )(input)
+}
+
+/// bound of range, unlike `double` it doesn't take `1.` of `1..`
+fn bound<'a, E: NomParseError<&'a str> + NomContextError<&'a str>>(
+    input: &'a str,
+) -> IResult<&'a str, f64, E> {
+    map(
+        recognize(tuple((
+            opt(char('-')),
+            digit1,
+            opt(pair(char('.'), digit1)),
+        ))),
+        |num: &str| num.parse().unwrap(),
+    )(input)
+}
+
+/// inclusive range of num param, format `(1..=100)`, `(1..)` or `(..=100)`
+fn range<'a, E: NomParseError<&'a str> + NomContextError<&'a str>>(
+    input: &'a str,
+) -> IResult<&'a str, (Option<f64>, Option<f64>), E> {
+    context(
+        "range",
+        preceded(
+            tuple((no_newline_sp, tag("("), no_newline_sp)),
+            cut(terminated(
+                alt((
+                    map(tuple((bound, tag("..="), bound)), |(min, _, max)| {
+                        (Some(min), Some(max))
+                    }),
+                    map(tuple((tag("..="), bound)), |(_, max)| (None, Some(max))),
+                    map(tuple((bound, tag(".."))), |(min, _)| (Some(min), None)),
+                )),
+                tuple((no_newline_sp, tag(")"))),
+            )),
+        ),
+    )(input)
+}
+
+/// null ordering of sort key, format `nulls first` or `nulls last`
+fn nulls_order<'a, E: NomParseError<&'a str> + NomContextError<&'a str>>(
+    input: &'a str,
+) -> IResult<&'a str, NullsOrder, E> {
+    context(
+        "nulls",
+        preceded(
+            tuple((space1, tag("nulls"), space1)),
+            cut(alt((
+                value(NullsOrder::First, tag("first")),
+                value(NullsOrder::Last, tag("last")),
+            ))),
+        ),
+    )(input)
+}
+
+/// collation of sort key, format `collate <name>` or `collate "<name>"` for names which
+/// aren't plain words, e.g. `collate "en-US-x-icu"`
+fn collation<'a, E: NomParseError<&'a str> + NomContextError<&'a str>>(
+    input: &'a str,
+) -> IResult<&'a str, String, E> {
+    context(
+        "collate",
+        preceded(
+            tuple((space1, tag("collate"), space1)),
+            cut(map(
+                alt((
+                    delimited(tag("\""), is_not("\"\r\n"), tag("\"")),
+                    take_while1(|c: char| c.is_ascii_alphanumeric() || c == '_'),
+                )),
+                |name: &str| name.to_string(),
+            )),
+        ),
+    )(input)
 }
 
-/// parse param line
+/// param constraint, format `requires a, b` or `conflicts a, b`
+fn constraint<'a, E: NomParseError<&'a str> + NomContextError<&'a str>>(
+    keyword: &'static str,
+) -> impl FnMut(&'a str) -> IResult<&'a str, Vec<String>, E> {
+    context(
+        keyword,
+        preceded(
+            tuple((space1, tag(keyword), space1)),
+            separated_list1(tuple((no_newline_sp, tag(","), no_newline_sp)), identifier),
+        ),
+    )
+}
+
+fn take_eq<'a, E: NomParseError<&'a str> + NomContextError<&'a str>>(
+    input: &'a str,
+) -> IResult<&'a str, (), E> {
+    map(
+        recognize(tuple((no_newline_sp, tag("="), no_newline_sp))),
+        |_| (),
+    )(input)
+}
+
+/// parse param line, regex of pattern is returned as is and compiled by caller
 fn param<'a, E: NomParseError<&'a str> + NomContextError<&'a str>>(
     input: &'a str,
-) -> IResult<&'a str, Param, E> {
-    let (input, (name, ty)) = map(
+) -> IResult<&'a str, (Param, Option<String>), E> {
+    let (input, (name, ty, nullable)) = map(
         tuple((
             tag("?"),
             no_newline_sp,
@@ -403,16 +1376,29 @@ fn param<'a, E: NomParseError<&'a str> + NomContextError<&'a str>>(
             tag(":"),
             no_newline_sp,
             parse_ty,
+            opt(char('?')),
         )),
-        |(_, _, name, _, _, _, ty)| (name, ty),
+        |(_, _, name, _, _, _, ty, nullable)| (name, ty, nullable.is_some()),
     )(input)?;
-    let (input, default) = match take_eq::<nom::error::VerboseError<&str>>(input) {
-        Ok((input, _)) => {
-            let (input, default) = parse_default(input, &ty)?;
-            (input, Some(default))
-        }
-        Err(_) => (input, None),
+    let (input, transforms) = opt(transforms)(input)?;
+    let (input, choices) = opt(|input| choices(input, ty.inner()))(input)?;
+    let (input, pattern) = opt(pattern)(input)?;
+    let (input, (default, default_from)) = match take_eq::<nom::error::VerboseError<&str>>(input) {
+        Ok((input, _)) => match preceded(tag("@"), identifier::<E>)(input) {
+            Ok((input, from)) => (input, (None, Some(from))),
+            Err(_) => {
+                let (input, default) = parse_default(input, &ty)?;
+                (input, (Some(default), None))
+            }
+        },
+        Err(_) => (input, (None, None)),
     };
+    let (input, range) = opt(range)(input)?;
+    let (min, max) = range.unwrap_or_default();
+    let (input, nulls) = opt(nulls_order)(input)?;
+    let (input, collation) = opt(collation)(input)?;
+    let (input, requires) = opt(constraint("requires"))(input)?;
+    let (input, conflicts) = opt(constraint("conflicts"))(input)?;
     let (input, help) = context(
         "help",
         opt(map(
@@ -420,13 +1406,905 @@ fn param<'a, E: NomParseError<&'a str> + NomContextError<&'a str>>(
             |(_, _, _, help)| help.to_string(),
         )),
     )(input)?;
+    let (help, localized_help) = split_localized_help(&help.unwrap_or_default());
     let param = Param {
         name,
         ty,
         default,
-        help: help.unwrap_or_default(),
+        default_from,
+        help,
+        localized_help,
+        transforms: transforms.unwrap_or_default(),
+        requires: requires.unwrap_or_default(),
+        conflicts: conflicts.unwrap_or_default(),
+        nullable,
+        choices,
+        min,
+        max,
+        pattern: None,
+        nulls,
+        collation,
+    };
+    Ok((input, (param, pattern)))
+}
+
+#[cfg(feature = "http")]
+#[test]
+fn param_value_json() {
+    let body: HashMap<String, ParamValue> =
+        serde_json::from_str(r#"{"age": 10, "name": "x", "ids": [1, 2.5], "tags": ["a"]}"#)
+            .unwrap();
+    assert_eq!(body["age"], ParamValue::Num(10.0));
+    assert_eq!(body["name"], ParamValue::Str("x".to_string()));
+    assert_eq!(
+        body["ids"],
+        ParamValue::Array(vec![ParamValue::Num(1.0), ParamValue::Num(2.5)])
+    );
+    assert_eq!(
+        body["tags"],
+        ParamValue::Array(vec![ParamValue::Str("a".to_string())])
+    );
+    assert_eq!(
+        serde_json::from_str::<ParamValue>("null").unwrap(),
+        ParamValue::Null
+    );
+    let err = serde_json::from_str::<ParamValue>(r#"{"a": 1}"#).unwrap_err();
+    assert!(err
+        .to_string()
+        .contains("a string, a number, a bool, null or an array"));
+    assert_eq!(
+        serde_json::to_string(&ParamValue::Array(vec![
+            ParamValue::Num(1.5),
+            ParamValue::Raw("now()".to_string())
+        ]))
+        .unwrap(),
+        r#"[1.5,"now()"]"#
+    );
+}
+
+#[test]
+fn param_transform() {
+    let (_, (p, _)) =
+        param::<nom::error::VerboseError<&str>>("? code: [str] |trim|upper| // code").unwrap();
+    assert_eq!(p.transforms, vec![Transform::Trim, Transform::Upper]);
+    assert_eq!(
+        p.transform(ParamValue::Array(vec![ParamValue::Str(" ab ".to_string())])),
+        ParamValue::Array(vec![ParamValue::Str("AB".to_string())])
+    );
+    let dialect = sqlparser::dialect::MySqlDialect {};
+    assert!(Program::parse(&dialect, "--? n: num |upper|\nselect @n").is_err());
+}
+
+#[test]
+fn quoted_default() {
+    let default = |input: &str| {
+        let (_, (p, _)) = param::<nom::error::VerboseError<&str>>(input).unwrap();
+        p.default.unwrap()
+    };
+    let str_value = |val: &str| ParamValue::Str(val.to_string());
+    assert_eq!(default(r"? name: str = 'O\'Brien'"), str_value("O'Brien"));
+    assert_eq!(
+        default(r#"? name: str = "say \"hi\"""#),
+        str_value("say \"hi\"")
+    );
+    assert_eq!(default(r#"? name: str = "it's""#), str_value("it's"));
+    assert_eq!(
+        default(r"? path: str = 'a\\b\tc\nd'"),
+        str_value("a\\b\tc\nd")
+    );
+    assert_eq!(default("? name: str = ''"), str_value(""));
+    assert_eq!(default(r#"? name: str = """#), str_value(""));
+    assert_eq!(
+        default(r"? names: [str] = ['', 'O\'Brien']"),
+        ParamValue::Array(vec![str_value(""), str_value("O'Brien")])
+    );
+    let dialect = sqlparser::dialect::MySqlDialect {};
+    let prog = Program::parse(&dialect, "--? name: str = 'O\\'Brien'\nselect @name").unwrap();
+    let context = HashMap::from([("name".to_string(), prog.params[0].default.clone().unwrap())]);
+    let stmts = prog.render(&dialect, &context).unwrap();
+    assert_eq!(stmts[0].to_string(), "SELECT 'O''Brien'");
+    assert!(param::<nom::error::VerboseError<&str>>(r"? name: str = 'bad\q'").is_err());
+}
+
+#[test]
+fn render_prepared() {
+    let sql = "--? name: str\n--? ids: [num]\n--? order: raw = #id#\n--? active: bool = true\nselect id from users where name = @name and id in @ids and active = @active order by @order";
+    let context = |name: &str| {
+        HashMap::from([
+            ("name".to_string(), ParamValue::Str(name.to_string())),
+            (
+                "ids".to_string(),
+                ParamValue::Array(vec![ParamValue::Num(1.0), ParamValue::Num(2.0)]),
+            ),
+            ("order".to_string(), ParamValue::Raw("id".to_string())),
+            ("active".to_string(), ParamValue::Bool(true)),
+        ])
+    };
+    let mysql = sqlparser::dialect::MySqlDialect {};
+    let prog = Program::parse(&mysql, sql).unwrap();
+    let (text, values) = prog
+        .render_prepared(&mysql, &context("'; DROP TABLE users; --"))
+        .unwrap();
+    assert_eq!(
+        text,
+        "select id from users where name = ? and id in (?, ?) and active = TRUE order by id"
+    );
+    assert_eq!(
+        values,
+        vec![
+            ParamValue::Str("'; DROP TABLE users; --".to_string()),
+            ParamValue::Num(1.0),
+            ParamValue::Num(2.0),
+        ]
+    );
+
+    let pg = sqlparser::dialect::PostgreSqlDialect {};
+    let prog = Program::parse(&pg, "--? a: str\n--? b: num\nselect @a, @b").unwrap();
+    let context = HashMap::from([
+        ("a".to_string(), ParamValue::Str("x".to_string())),
+        ("b".to_string(), ParamValue::Num(2.0)),
+    ]);
+    let (text, values) = prog.render_prepared(&pg, &context).unwrap();
+    assert_eq!(text, "select $1, $2");
+    assert_eq!(values.len(), 2);
+    let prog = Program::parse(
+        &pg,
+        "--? day: date\n--? at: [datetime]\nselect * from t where day >= @day and at in @at",
+    )
+    .unwrap();
+    let context = HashMap::from([
+        ("day".to_string(), ParamValue::Str("2024-01-31".to_string())),
+        (
+            "at".to_string(),
+            ParamValue::Array(vec![ParamValue::Str("2024-01-31T08:00:00Z".to_string())]),
+        ),
+    ]);
+    let (text, _) = prog.render_prepared(&pg, &context).unwrap();
+    assert_eq!(
+        text,
+        "select * from t where day >= $1::date and at in ($2::timestamptz)"
+    );
+    let (text, _) = prog.render_prepared(&mysql, &context).unwrap();
+    assert_eq!(text, "select * from t where day >= ? and at in (?)");
+    let prog = Program::parse(&pg, "--? id: str\nselect * from t where id = @id::uuid").unwrap();
+    let context = HashMap::from([("id".to_string(), ParamValue::Str("x".to_string()))]);
+    let (text, _) = prog.render_prepared(&pg, &context).unwrap();
+    assert_eq!(text, "select * from t where id = $1::uuid");
+    assert!(matches!(
+        prog.render_prepared(&pg, &HashMap::new()),
+        Err(PSqlError::MissingContextValue(_))
+    ));
+}
+
+#[test]
+fn empty_array_value() {
+    let dialect = sqlparser::dialect::MySqlDialect {};
+    let sql = "--? ids: [num]\n--? names: [str]\nselect @names from users where age in @ids";
+    let prog = Program::parse(&dialect, sql).unwrap();
+    let context = HashMap::from([
+        ("ids".to_string(), ParamValue::Array(vec![])),
+        ("names".to_string(), ParamValue::Array(vec![])),
+    ]);
+    assert_eq!(
+        prog.render(&dialect, &context).unwrap()[0].to_string(),
+        "SELECT (NULL) FROM users WHERE age IN (NULL)"
+    );
+    let (text, values) = prog.render_prepared(&dialect, &context).unwrap();
+    assert_eq!(text, "select (NULL) from users where age in (NULL)");
+    assert!(values.is_empty());
+}
+
+#[test]
+fn escape_str_value() {
+    let str_value = |val: &str| ParamValue::Str(val.to_string());
+    assert_eq!(str_value("o'brien").to_string(), "'o''brien'");
+    let sql = "--? name: str\nselect id from users where name = @name";
+    let render = |dialect: &dyn Dialect, name: &str| {
+        let prog = Program::parse(dialect, sql).unwrap();
+        let context = HashMap::from([("name".to_string(), str_value(name))]);
+        let stmts = prog.render(dialect, &context).unwrap();
+        assert_eq!(stmts.len(), 1);
+        stmts[0].to_string()
+    };
+    let mysql = sqlparser::dialect::MySqlDialect {};
+    let sqlite = sqlparser::dialect::SQLiteDialect {};
+    for dialect in [&mysql as &dyn Dialect, &sqlite] {
+        assert_eq!(
+            render(dialect, "'; DROP TABLE users; --"),
+            "SELECT id FROM users WHERE name = '''; DROP TABLE users; --'"
+        );
+    }
+    // mysql reads `\'` as an escaped quote, backslash must not eat the closing quote
+    assert_eq!(
+        render(&mysql, "\\'; DROP TABLE users; --"),
+        "SELECT id FROM users WHERE name = '\\\\''; DROP TABLE users; --'"
+    );
+    assert_eq!(
+        render(&sqlite, "a\\b"),
+        "SELECT id FROM users WHERE name = 'a\\b'"
+    );
+}
+
+#[test]
+fn subquery_param() {
+    let ty = InnerTy::Subquery;
+    assert_eq!(
+        ParamValue::from_arg_str(&ty, "select id from vip where level > 3 -- top").unwrap(),
+        ParamValue::Raw("(SELECT id FROM vip WHERE level > 3)".to_string())
+    );
+    for invalid in [
+        "select id from vip; drop table users",
+        "delete from users",
+        "id from vip",
+        "",
+    ] {
+        assert!(matches!(
+            ParamValue::from_arg_str(&ty, invalid),
+            Err(PSqlError::InvalidArgValue(..))
+        ));
+    }
+
+    let dialect = sqlparser::dialect::MySqlDialect {};
+    let sql = "--? ids: subquery = 'select id from vip'\nselect name from users where id in @ids";
+    let prog = Program::parse(&dialect, sql).unwrap();
+    let context = HashMap::from([("ids".to_string(), prog.params[0].default.clone().unwrap())]);
+    assert_eq!(
+        prog.render(&dialect, &context).unwrap()[0].to_string(),
+        "SELECT name FROM users WHERE id IN (SELECT id FROM vip)"
+    );
+    assert!(matches!(
+        Program::parse(
+            &dialect,
+            "--? ids: subquery = 'drop table t'\nselect 1 in @ids"
+        ),
+        Err(PSqlError::InvalidDefault(..))
+    ));
+    assert!(Program::parse(&dialect, "--? ids: [subquery]\nselect 1 in @ids").is_err());
+}
+
+#[test]
+fn ident_param() {
+    let ty = InnerTy::Ident;
+    assert_eq!(
+        ParamValue::from_arg_str(&ty, "created_at").unwrap(),
+        ParamValue::Ident("created_at".to_string())
+    );
+    for invalid in ["id; drop table users", "1st", "a-b", "`id`", "'id'", ""] {
+        assert!(matches!(
+            ParamValue::from_arg_str(&ty, invalid),
+            Err(PSqlError::InvalidArgValue(..))
+        ));
+    }
+
+    let dialect = sqlparser::dialect::MySqlDialect {};
+    let sql =
+        "--? col: ident = id\n--? name: str\nselect * from users where name = @name order by @col";
+    let prog = Program::parse(&dialect, sql).unwrap();
+    assert_eq!(
+        prog.params[0].default,
+        Some(ParamValue::Ident("id".to_string()))
+    );
+    let context = HashMap::from([
+        (
+            "col".to_string(),
+            ParamValue::Ident("created_at".to_string()),
+        ),
+        ("name".to_string(), ParamValue::Str("a".to_string())),
+    ]);
+    assert_eq!(
+        prog.render(&dialect, &context).unwrap()[0].to_string(),
+        "SELECT * FROM users WHERE name = 'a' ORDER BY created_at"
+    );
+    let (text, values) = prog.render_prepared(&dialect, &context).unwrap();
+    assert_eq!(
+        text,
+        "select * from users where name = ? order by created_at"
+    );
+    assert_eq!(values, vec![ParamValue::Str("a".to_string())]);
+    assert!(Program::parse(&dialect, "--? col: ident = 'id'\nselect @col").is_err());
+    assert!(Program::parse(&dialect, "--? cols: [ident]\nselect @cols").is_err());
+}
+
+#[test]
+fn ident_sort_options() {
+    let sql = "--? sort: ident {id, name} = name nulls last collate \"C\"\n\
+        --? dir: ident {asc, desc} = asc\nselect * from t order by @sort @dir, id";
+    let context = HashMap::from([
+        ("sort".to_string(), ParamValue::Ident("name".to_string())),
+        ("dir".to_string(), ParamValue::Ident("desc".to_string())),
+    ]);
+    let pg = sqlparser::dialect::PostgreSqlDialect {};
+    let prog = Program::parse(&pg, sql).unwrap();
+    assert_eq!(prog.params[0].nulls, Some(NullsOrder::Last));
+    assert_eq!(prog.params[0].collation.as_deref(), Some("C"));
+    assert_eq!(
+        prog.render(&pg, &context).unwrap()[0].to_string(),
+        r#"SELECT * FROM t ORDER BY name COLLATE "C" DESC NULLS LAST, id"#
+    );
+    assert_eq!(
+        prog.render_prepared(&pg, &context).unwrap().0,
+        r#"select * from t order by name COLLATE "C" desc NULLS LAST, id"#
+    );
+
+    // mysql sorts on `IS NULL` first, no matter of direction
+    let mysql = sqlparser::dialect::MySqlDialect {};
+    let sql = "--? sort: ident = name nulls first collate utf8mb4_bin\nselect * from t order by @sort DESC limit 5";
+    let prog = Program::parse(&mysql, sql).unwrap();
+    let context = HashMap::from([("sort".to_string(), ParamValue::Ident("name".to_string()))]);
+    assert_eq!(
+        prog.render_prepared(&mysql, &context).unwrap().0,
+        "select * from t order by name IS NOT NULL, name COLLATE utf8mb4_bin DESC limit 5"
+    );
+    assert_eq!(
+        prog.render(&mysql, &context).unwrap()[0].to_string(),
+        "SELECT * FROM t ORDER BY name IS NOT NULL, name COLLATE utf8mb4_bin DESC LIMIT 5"
+    );
+
+    let sqlite = sqlparser::dialect::SQLiteDialect {};
+    let sql =
+        "--? sort: ident = name collate nocase nulls last\nselect * from t order by @sort limit 5";
+    assert!(Program::parse(&sqlite, sql).is_err());
+    let sql =
+        "--? sort: ident = name nulls last collate nocase\nselect * from t order by @sort limit 5";
+    let prog = Program::parse(&sqlite, sql).unwrap();
+    assert_eq!(
+        prog.render_prepared(&sqlite, &context).unwrap().0,
+        "select * from t order by name COLLATE nocase NULLS LAST limit 5"
+    );
+    assert_eq!(
+        prog.render(&sqlite, &context).unwrap()[0].to_string(),
+        "SELECT * FROM t ORDER BY name COLLATE nocase NULLS LAST LIMIT 5"
+    );
+
+    for (dialect, sql) in [
+        (
+            &sqlite as &dyn Dialect,
+            "--? sort: ident collate \"C\"\nselect @sort",
+        ),
+        (&mysql, "--? sort: ident collate \"en-US\"\nselect @sort"),
+        (&pg, "--? sort: str nulls last\nselect @sort"),
+    ] {
+        assert!(
+            matches!(
+                Program::parse(dialect, sql),
+                Err(PSqlError::InvalidSortOption(..))
+            ),
+            "{}",
+            sql
+        );
+    }
+    assert!(Program::parse(&pg, "--? sort: ident nulls middle\nselect @sort").is_err());
+}
+
+#[test]
+fn ident_choices() {
+    let dialect = sqlparser::dialect::MySqlDialect {};
+    let sql = "--? sort_col: ident {id, name, created_at} = id // sort column\nselect * from t order by @sort_col";
+    let prog = Program::parse(&dialect, sql).unwrap();
+    let p = &prog.params[0];
+    assert_eq!(p.choices_text(), "id, name, created_at");
+    assert_eq!(p.help, "sort column");
+    let given = ["sort_col"].into_iter().collect();
+    let context = |col: &str| {
+        HashMap::from([(
+            "sort_col".to_string(),
+            ParamValue::from_arg_str(&InnerTy::Ident, col).unwrap(),
+        )])
+    };
+    assert!(prog.check_values(&context("name"), &given).is_ok());
+    let err = prog.check_values(&context("password"), &given).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "invalid value password for sort_col, expect one of id, name, created_at"
+    );
+    #[cfg(feature = "http")]
+    match p.to_openapi_schema().schema_kind {
+        SchemaKind::Type(Type::String(ty)) => {
+            assert_eq!(ty.enumeration, vec!["id", "name", "created_at"])
+        }
+        kind => panic!("unexpected schema {:?}", kind),
+    }
+
+    for (sql, list) in [
+        ("--? c: ident {}\nselect @c", "{}"),
+        ("--? c: ident { }\nselect @c", "{ }"),
+        ("--? c: ident {id, }\nselect @c", "{id, }"),
+        (
+            "--? c: ident {id, 'name'} // sort\nselect @c",
+            "{id, 'name'}",
+        ),
+        ("--? c: ident {id, name\nselect @c", "{id, name"),
+    ] {
+        match Program::parse(&dialect, sql) {
+            Err(PSqlError::InvalidChoices(text, name)) => {
+                assert_eq!((text.as_str(), name.as_str()), (list, "c"))
+            }
+            other => panic!("{} parsed as {:?}", sql, other),
+        }
+    }
+    assert!(matches!(
+        Program::parse(&dialect, "--? c: ident {id, name} = age\nselect @c"),
+        Err(PSqlError::InvalidDefault(..))
+    ));
+}
+
+#[test]
+fn localized_help() {
+    let (_, (p, _)) =
+        param::<nom::error::VerboseError<&str>>("? age: num // en: age // zh-CN: 年龄").unwrap();
+    assert_eq!(p.help, "age");
+    assert_eq!(p.help_for(Some("zh-CN,zh;q=0.9")), "年龄");
+    assert_eq!(p.help_for(Some("zh_CN.UTF-8")), "年龄");
+    assert_eq!(p.help_for(Some("fr")), "age");
+    let (_, (p, _)) =
+        param::<nom::error::VerboseError<&str>>("? url: str // see: http://a").unwrap();
+    assert_eq!(p.help, "see: http://a");
+    assert!(p.localized_help.is_empty());
+}
+
+#[test]
+fn conflicting_usage() {
+    let dialect = sqlparser::dialect::MySqlDialect {};
+    let sql = "--? x: [num]\nselect * from t where a in @x or b = @x";
+    assert!(matches!(
+        Program::parse(&dialect, sql),
+        Err(PSqlError::ConflictingUsage(name)) if name == "x"
+    ));
+    let sql = "--? x: [num]\nselect * from t where a in @x or b not in @x";
+    assert!(Program::parse(&dialect, sql).is_ok());
+    let sql = "--? x: num\nselect @x as v from t where a = @x";
+    assert!(Program::parse(&dialect, sql).is_ok());
+}
+
+#[cfg(feature = "cli")]
+#[test]
+fn cli_matches() {
+    let dialect = sqlparser::dialect::MySqlDialect {};
+    let sql = "--? age: num = 10 // age\n--? name: str // name\nselect * from t where age = @age and name = @name";
+    let prog = Program::parse(&dialect, sql).unwrap();
+    let mut opts = getopts::Options::new();
+    prog.add_options(&mut opts);
+    let usage = prog.usage_string(&opts);
+    assert!(usage.contains("--age") && usage.contains("--name"));
+    let args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+    assert!(matches!(
+        prog.get_matches(&opts, &args(&["--age", "3"])),
+        Err(getopts::Fail::OptionMissing(name)) if name == "name"
+    ));
+    let values = prog.get_matches(&opts, &args(&["--name", "bob"])).unwrap();
+    assert_eq!(values["age"], ParamValue::Num(10.0));
+    assert_eq!(values["name"], ParamValue::Str("bob".to_string()));
+}
+
+#[test]
+fn bool_param() {
+    let dialect = sqlparser::dialect::MySqlDialect {};
+    let sql = "--? active: bool = true // only active rows\n--? flags: [bool] = [true, false]\nselect * from t where active = @active and flag in @flags";
+    let prog = Program::parse(&dialect, sql).unwrap();
+    assert_eq!(prog.params[0].default, Some(ParamValue::Bool(true)));
+    let mut context = HashMap::new();
+    context.insert("active".to_string(), ParamValue::Bool(false));
+    context.insert("flags".to_string(), prog.params[1].default.clone().unwrap());
+    let stmts = prog.render(&dialect, &context).unwrap();
+    assert_eq!(
+        stmts[0].to_string(),
+        "SELECT * FROM t WHERE active = false AND flag IN (true, false)"
+    );
+    assert_eq!(
+        ParamValue::from_arg_str(&InnerTy::Bool, "1").unwrap(),
+        ParamValue::Bool(true)
+    );
+    assert_eq!(
+        ParamValue::from_arg_str(&InnerTy::Bool, "false").unwrap(),
+        ParamValue::Bool(false)
+    );
+    assert!(matches!(
+        ParamValue::from_arg_str(&InnerTy::Bool, "yes"),
+        Err(PSqlError::InvalidArgValue(val, InnerTy::Bool)) if val == "yes"
+    ));
+}
+
+#[cfg(feature = "cli")]
+#[test]
+fn cli_bool_flag() {
+    let dialect = sqlparser::dialect::MySqlDialect {};
+    let sql = "--? active: bool // only active rows\nselect * from t where active = @active";
+    let prog = Program::parse(&dialect, sql).unwrap();
+    let mut opts = getopts::Options::new();
+    prog.add_options(&mut opts);
+    let values = prog.get_matches(&opts, &["--active".to_string()]).unwrap();
+    assert_eq!(values["active"], ParamValue::Bool(true));
+    let values = prog.get_matches(&opts, &[]).unwrap();
+    assert_eq!(values["active"], ParamValue::Bool(false));
+}
+
+#[test]
+fn nullable_param() {
+    let dialect = sqlparser::dialect::MySqlDialect {};
+    let sql = "--? note: str? // optional note\n--? tag: str? = 'x'\ninsert into t (note, tag) values (@note, @tag)";
+    let prog = Program::parse(&dialect, sql).unwrap();
+    assert!(prog.params.iter().all(|p| p.nullable && !p.required()));
+    assert_eq!(prog.params[0].omitted_value(), Some(ParamValue::Null));
+    // default wins when omitted
+    assert_eq!(
+        prog.params[1].omitted_value(),
+        Some(ParamValue::Str("x".to_string()))
+    );
+    assert!(prog.params[0].accepts(&ParamValue::Null));
+    let context = prog
+        .params
+        .iter()
+        .map(|p| (p.name.clone(), p.omitted_value().unwrap()))
+        .collect();
+    let stmts = prog.render(&dialect, &context).unwrap();
+    assert_eq!(
+        stmts[0].to_string(),
+        "INSERT INTO t (note, tag) VALUES (NULL, 'x')"
+    );
+    let (_, (p, _)) = param::<nom::error::VerboseError<&str>>("? ids: [num]? // ids").unwrap();
+    assert!(p.nullable && p.accepts(&ParamValue::Null));
+    let (_, (p, _)) =
+        param::<nom::error::VerboseError<&str>>("? note: str // not nullable").unwrap();
+    assert!(!p.nullable && p.required());
+    assert!(!p.accepts(&ParamValue::Null));
+}
+
+#[test]
+fn date_param() {
+    let dialect = sqlparser::dialect::MySqlDialect {};
+    let sql = "--? from: date = '2024-02-29' // start day\n--? at: datetime\nselect * from t where day >= @from and ts < @at";
+    let prog = Program::parse(&dialect, sql).unwrap();
+    assert_eq!(prog.params[0].ty, ParamTy::Basic(InnerTy::Date));
+    assert_eq!(prog.params[1].ty, ParamTy::Basic(InnerTy::Datetime));
+    let mut context = HashMap::new();
+    context.insert("from".to_string(), prog.params[0].default.clone().unwrap());
+    context.insert(
+        "at".to_string(),
+        ParamValue::from_arg_str(&InnerTy::Datetime, "2024-01-31T08:00:00.5+08:00").unwrap(),
+    );
+    let stmts = prog.render(&dialect, &context).unwrap();
+    assert_eq!(
+        stmts[0].to_string(),
+        "SELECT * FROM t WHERE day >= '2024-02-29' AND ts < '2024-01-31T08:00:00.5+08:00'"
+    );
+    for valid in ["2024-01-31T08:00:00Z", "2024-01-31 08:00:59-05:30"] {
+        assert!(ParamValue::from_arg_str(&InnerTy::Datetime, valid).is_ok());
+    }
+    for invalid in ["2024-01-31", "2024-01-31T24:00:00Z", "2024-01-31T08:00:00"] {
+        assert!(ParamValue::from_arg_str(&InnerTy::Datetime, invalid).is_err());
+    }
+    for invalid in ["2024-13-40", "2023-02-29", "24-01-01", "2024-1-1"] {
+        assert!(ParamValue::from_arg_str(&InnerTy::Date, invalid).is_err());
+    }
+    let err = ParamValue::from_arg_str(&InnerTy::Date, "2024-13-40").unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "invalid arg value 2024-13-40 for Date, expect YYYY-MM-DD"
+    );
+    let sql = "--? from: date = '2023-02-29'\nselect * from t where day = @from";
+    assert!(matches!(
+        Program::parse(&dialect, sql),
+        Err(PSqlError::InvalidDefault(name, _)) if name == "from"
+    ));
+}
+
+#[test]
+fn render_text() {
+    let dialect = sqlparser::dialect::MySqlDialect {};
+    let prog = Program::parse(
+        &dialect,
+        "--? name: str\n--? items: [num]\nDear @name, your orders @items are shipped",
+    )
+    .unwrap();
+    let mut context = HashMap::new();
+    context.insert("name".to_string(), ParamValue::Str("Bob".to_string()));
+    context.insert(
+        "items".to_string(),
+        ParamValue::Array(vec![ParamValue::Num(1.0), ParamValue::Num(2.0)]),
+    );
+    assert_eq!(
+        prog.render_text(&context).unwrap(),
+        "Dear Bob, your orders 1, 2 are shipped"
+    );
+    assert!(Program::parse(&dialect, "Dear @name, don't worry").is_err());
+}
+
+#[test]
+fn misplaced_param_mark() {
+    let dialect = sqlparser::dialect::MySqlDialect {};
+    assert!(matches!(
+        Program::parse(&dialect, "-- ? age: num\nselect @age"),
+        Err(PSqlError::MisplacedParamMark(_))
+    ));
+}
+
+#[test]
+fn bom_and_crlf() {
+    let dialect = sqlparser::dialect::MySqlDialect {};
+    let lf = Program::parse(
+        &dialect,
+        "--? a: num = 1 // first\n--? b: num = 2\nselect @a, @b\n",
+    )
+    .unwrap();
+    for sql in [
+        "\u{feff}--? a: num = 1 // first\r\n--? b: num = 2\r\nselect @a, @b\r\n",
+        "--? a: num = 1 // first\r--? b: num = 2\rselect @a, @b\r",
+    ] {
+        let prog = Program::parse(&dialect, sql).unwrap();
+        assert_eq!(prog.params, lf.params);
+        let context = prog
+            .params
+            .iter()
+            .map(|p| (p.name.clone(), p.default.clone().unwrap()))
+            .collect();
+        assert!(prog.render(&dialect, &context).is_ok());
+    }
+}
+
+#[test]
+fn crlf_in_literal() {
+    let dialect = sqlparser::dialect::MySqlDialect {};
+    let sql = "--? a: num = 1 // don't\r\n/* it's */\rselect 'x\r\ny\rz' as s, @a\r\n";
+    let prog = Program::parse(&dialect, sql).unwrap();
+    assert_eq!(prog.params[0].help, "don't");
+    let context = HashMap::from([("a".to_string(), ParamValue::Num(1.0))]);
+    let stmts = prog.render(&dialect, &context).unwrap();
+    assert_eq!(stmts.len(), 1);
+    assert!(stmts[0].to_string().contains("'x\r\ny\rz'"), "{}", stmts[0]);
+}
+
+#[test]
+fn param_constraints() {
+    let dialect = sqlparser::dialect::MySqlDialect {};
+    let sql = "--? a: str = 'x' conflicts b // a\n--? b: str = 'y' // b\n--? c: num = 0 requires a, b\nselect @a, @b, @c";
+    let prog = Program::parse(&dialect, sql).unwrap();
+    assert_eq!(prog.params[0].conflicts, vec!["b"]);
+    assert_eq!(prog.params[0].help, "a");
+    assert_eq!(prog.params[2].requires, vec!["a", "b"]);
+    let given = |names: &[&'static str]| names.iter().copied().collect::<HashSet<&str>>();
+    assert!(prog.check_constraints(&given(&["a"])).is_ok());
+    assert!(matches!(
+        prog.check_constraints(&given(&["a", "b"])),
+        Err(PSqlError::ParamConflicts(..))
+    ));
+    assert!(matches!(
+        prog.check_constraints(&given(&["b", "c"])),
+        Err(PSqlError::ParamRequires(..))
+    ));
+    assert!(matches!(
+        Program::parse(&dialect, "--? a: num = 1 requires z\nselect @a"),
+        Err(PSqlError::UnknownParamRef(..))
+    ));
+}
+
+#[test]
+fn default_ref() {
+    let dialect = sqlparser::dialect::MySqlDialect {};
+    let sql = "--? start: str = '2020' // start\n--? end: str = @start // end\n--? last: str = @end\nselect @start, @end, @last";
+    let prog = Program::parse(&dialect, sql).unwrap();
+    assert_eq!(prog.params[1].default_from.as_deref(), Some("start"));
+    assert_eq!(prog.params[1].help, "end");
+    assert!(!prog.params[2].required());
+    let mut context = HashMap::new();
+    context.insert("start".to_string(), ParamValue::Str("2021".to_string()));
+    prog.resolve_defaults(&mut context).unwrap();
+    assert_eq!(context["last"], ParamValue::Str("2021".to_string()));
+    assert!(matches!(
+        Program::parse(&dialect, "--? a: num = @b\n--? b: num = @a\nselect @a, @b"),
+        Err(PSqlError::DefaultCycle(_))
+    ));
+    assert!(matches!(
+        Program::parse(&dialect, "--? a: num = @b\n--? b: str\nselect @a, @b"),
+        Err(PSqlError::InvalidDefault(..))
+    ));
+}
+
+#[test]
+fn param_choices() {
+    let dialect = sqlparser::dialect::MySqlDialect {};
+    let sql = "--? status: str {pending, 'shipped', cancelled} = 'pending' // order status\n--? sizes: [num] {1, 2.5}\nselect * from t where status = @status and size in @sizes";
+    let prog = Program::parse(&dialect, sql).unwrap();
+    assert_eq!(
+        prog.params[0].choices,
+        Some(vec![
+            ParamValue::Str("pending".to_string()),
+            ParamValue::Str("shipped".to_string()),
+            ParamValue::Str("cancelled".to_string()),
+        ])
+    );
+    assert_eq!(prog.params[0].help, "order status");
+    let mut context = HashMap::new();
+    context.insert("status".to_string(), ParamValue::Str("lost".to_string()));
+    context.insert(
+        "sizes".to_string(),
+        ParamValue::Array(vec![ParamValue::Num(2.5)]),
+    );
+    let given = ["status", "sizes"].into_iter().collect();
+    assert!(matches!(
+        prog.check_values(&context, &given),
+        Err(PSqlError::InvalidChoice(_, name, choices))
+            if name == "status" && choices == "'pending', 'shipped', 'cancelled'"
+    ));
+    context.insert("status".to_string(), ParamValue::Str("shipped".to_string()));
+    assert!(prog.check_values(&context, &given).is_ok());
+    // choices must match param type
+    assert!(Program::parse(&dialect, "--? n: num {1, a}\nselect @n").is_err());
+    assert!(Program::parse(&dialect, "--? d: date {2024-13-01}\nselect @d").is_err());
+    assert!(matches!(
+        Program::parse(&dialect, "--? s: str {a, b} = 'c'\nselect @s"),
+        Err(PSqlError::InvalidDefault(..))
+    ));
+}
+
+#[test]
+fn param_range() {
+    let dialect = sqlparser::dialect::MySqlDialect {};
+    let sql = "--? limit: num = 20 (1..=100) // page size\n--? ids: [num] (0..)\n--? off: num (..=-1.5)\nselect * from t where id in @ids and x < @off limit @limit";
+    let prog = Program::parse(&dialect, sql).unwrap();
+    assert_eq!(
+        (prog.params[0].min, prog.params[0].max),
+        (Some(1.0), Some(100.0))
+    );
+    assert_eq!(prog.params[0].help, "page size");
+    assert_eq!((prog.params[1].min, prog.params[1].max), (Some(0.0), None));
+    assert_eq!((prog.params[2].min, prog.params[2].max), (None, Some(-1.5)));
+    let mut context = HashMap::new();
+    context.insert("limit".to_string(), ParamValue::Num(500.0));
+    context.insert(
+        "ids".to_string(),
+        ParamValue::Array(vec![ParamValue::Num(1.0), ParamValue::Num(-1.0)]),
+    );
+    let given = ["limit"].into_iter().collect();
+    assert!(matches!(
+        prog.check_values(&context, &given),
+        Err(PSqlError::AboveMax(value, name, max)) if value == 500.0 && name == "limit" && max == 100.0
+    ));
+    let given = ["ids"].into_iter().collect();
+    assert!(matches!(
+        prog.check_values(&context, &given),
+        Err(PSqlError::BelowMin(value, name, _)) if value == -1.0 && name == "ids"
+    ));
+    assert!(matches!(
+        Program::parse(&dialect, "--? n: num = 0 (1..=10)\nselect @n"),
+        Err(PSqlError::InvalidDefault(..))
+    ));
+    assert!(Program::parse(&dialect, "--? n: str (1..=10)\nselect @n").is_err());
+    assert!(Program::parse(&dialect, "--? n: num (10..=1)\nselect @n").is_err());
+    assert!(Program::parse(&dialect, "--? n: num (1..10)\nselect @n").is_err());
+}
+
+#[test]
+fn param_pattern() {
+    let dialect = sqlparser::dialect::MySqlDialect {};
+    let sql = "--? code: str |upper| /^[A-Z]{3}$/ = 'USD' // ISO currency\n--? paths: [str] /^a\\/\\w+$/\nselect @code, @paths";
+    let prog = Program::parse(&dialect, sql).unwrap();
+    assert_eq!(
+        prog.params[0].pattern.as_ref().unwrap().as_str(),
+        "^[A-Z]{3}$"
+    );
+    assert_eq!(prog.params[0].help, "ISO currency");
+    assert_eq!(
+        prog.params[1].pattern.as_ref().unwrap().as_str(),
+        "^a/\\w+$"
+    );
+    let check = |name: &str, value: ParamValue| {
+        let context = HashMap::from([(name.to_string(), value)]);
+        prog.check_values(&context, &HashSet::from([name]))
     };
-    Ok((input, param))
+    assert!(check("code", ParamValue::Str("EUR".to_string())).is_ok());
+    let err = check("code", ParamValue::Str("EURO".to_string())).unwrap_err();
+    assert!(matches!(err, PSqlError::InvalidArgValue(..)));
+    assert!(err.to_string().contains("/^[A-Z]{3}$/"), "{}", err);
+    assert!(check("code", ParamValue::Num(12345.0)).is_err());
+    assert!(check("code", ParamValue::Null).is_ok());
+    let paths = |items: &[&str]| {
+        ParamValue::Array(
+            items
+                .iter()
+                .map(|item| ParamValue::Str(item.to_string()))
+                .collect(),
+        )
+    };
+    assert!(check("paths", paths(&["a/b", "a/c_1"])).is_ok());
+    assert!(check("paths", paths(&["a/b", "b/c"])).is_err());
+    assert!(check("paths", ParamValue::Array(vec![ParamValue::Num(1.0)])).is_err());
+
+    assert!(matches!(
+        Program::parse(&dialect, "--? code: str /[A-Z/\nselect @code"),
+        Err(PSqlError::InvalidPattern(..))
+    ));
+    assert!(matches!(
+        Program::parse(&dialect, "--? code: str /^[A-Z]+$/ = 'usd'\nselect @code"),
+        Err(PSqlError::InvalidDefault(..))
+    ));
+    assert!(Program::parse(&dialect, "--? n: num /^1$/\nselect @n").is_err());
+    assert!(Program::parse(&dialect, "--? code: str // no pattern\nselect @code").is_ok());
+}
+
+#[test]
+fn required_params() {
+    let dialect = sqlparser::dialect::MySqlDialect {};
+    let sql = "--? id: num\n--? name: str = 'x'\n--? alias: str = @name\n--? note: str?\n--? tags: [str]\nselect @id, @name, @alias, @note from t where tag in @tags";
+    let prog = Program::parse(&dialect, sql).unwrap();
+    let names = |params: Vec<&Param>| params.iter().map(|p| p.name.clone()).collect::<Vec<_>>();
+    assert_eq!(names(prog.required_params()), vec!["id", "tags"]);
+    assert_eq!(names(prog.optional_params()), vec!["name", "alias", "note"]);
+}
+
+#[test]
+fn statement_kinds() {
+    let dialect = sqlparser::dialect::SQLiteDialect {};
+    let sql = "--? id: num\n--? name: str\nBEGIN;\nupdate users set name = @name where id = @id;\ninsert into logs (user_id) values (@id);\ncreate index if not exists idx on logs (user_id);\nselect name from users where id = @id;\nCOMMIT";
+    let prog = Program::parse(&dialect, sql).unwrap();
+    assert_eq!(
+        prog.statement_kinds(&dialect).unwrap(),
+        vec![
+            (StatementKind::Transaction, false),
+            (StatementKind::Update, true),
+            (StatementKind::Insert, true),
+            (StatementKind::Ddl, true),
+            (StatementKind::Select, false),
+            (StatementKind::Transaction, false),
+        ]
+    );
+}
+
+#[test]
+fn sql_fragment() {
+    let dialect = sqlparser::dialect::SQLiteDialect {};
+    let prog = Program::parse(
+        &dialect,
+        "--? name: str\n--? id: num\nupdate users set name = @name where id = @id",
+    )
+    .unwrap();
+    let hook = prog
+        .fragment(
+            &dialect,
+            "insert into log (msg) values ('rename ' || @name)",
+        )
+        .unwrap();
+    let context = HashMap::from([
+        ("name".to_string(), ParamValue::Str("o'brien".to_string())),
+        ("id".to_string(), ParamValue::Num(1.0)),
+    ]);
+    let stmts = hook.render(&dialect, &context).unwrap();
+    assert_eq!(
+        stmts[0].to_string(),
+        "INSERT INTO log (msg) VALUES ('rename ' || 'o''brien')"
+    );
+    assert!(matches!(
+        prog.fragment(&dialect, "select @other"),
+        Err(PSqlError::MissingParams(_))
+    ));
+    assert!(matches!(
+        prog.fragment(&dialect, "--? other: str\nselect @other"),
+        Err(PSqlError::ParamParseError(_))
+    ));
+}
+
+#[test]
+fn invalid_default() {
+    let dialect = sqlparser::dialect::MySqlDialect {};
+    assert!(matches!(
+        Program::parse(&dialect, "--? a: num = inf\nselect @a"),
+        Err(PSqlError::InvalidDefault(..))
+    ));
+    assert!(matches!(
+        Program::parse(&dialect, "--? a: num = 10abc\nselect @a"),
+        Err(PSqlError::ParamParseError(_))
+    ));
+    assert!(Program::parse(&dialect, "--? a: [num] = [1, 2] // ok\nselect @a").is_ok());
+}
+
+#[test]
+fn param_limit() {
+    let dialect = sqlparser::dialect::MySqlDialect {};
+    let sql = "--? a: num\n--? b: num\nselect @a, @b";
+    assert!(Program::parse_with_limit(&dialect, sql, 2).is_ok());
+    assert!(matches!(
+        Program::parse_with_limit(&dialect, sql, 1),
+        Err(PSqlError::TooManyParams(1))
+    ));
 }
 
 #[test]
@@ -450,7 +2328,12 @@ fn parse_param() {
             "? arr: [num] = [ 1, 2, 3 ] // array param",
         ),
         ("no default", "? age: num // help msg"),
+        (
+            "transform",
+            "? code: str |trim|upper| = ' ab ' // product code",
+        ),
         ("no help msg", "? age: num = 10"),
+        ("localized help", "? age: num = 10 // en: age // zh: 年龄"),
         ("simple", "? age: num"),
         ("invalid num", "? age: num = gx"),
         ("invalid num", "? age: num = "),
@@ -465,16 +2348,150 @@ fn parse_param() {
     }
 }
 
+/// kind of sql statement
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum StatementKind {
+    Select,
+    Insert,
+    Update,
+    Delete,
+    /// create, alter, drop or truncate table, view, index, schema or database
+    Ddl,
+    /// start, commit or rollback transaction
+    Transaction,
+    /// set, show, explain and others
+    Other,
+}
+
+impl StatementKind {
+    pub fn of(stmt: &Statement) -> Self {
+        match stmt {
+            Statement::Query(_) => Self::Select,
+            Statement::Insert { .. } | Statement::Copy { .. } | Statement::Directory { .. } => {
+                Self::Insert
+            }
+            Statement::Update { .. } => Self::Update,
+            Statement::Delete { .. } => Self::Delete,
+            Statement::CreateView { .. }
+            | Statement::CreateTable { .. }
+            | Statement::CreateVirtualTable { .. }
+            | Statement::CreateIndex { .. }
+            | Statement::AlterTable { .. }
+            | Statement::Drop { .. }
+            | Statement::Truncate { .. }
+            | Statement::CreateSchema { .. }
+            | Statement::CreateDatabase { .. } => Self::Ddl,
+            Statement::StartTransaction { .. }
+            | Statement::SetTransaction { .. }
+            | Statement::Commit { .. }
+            | Statement::Rollback { .. } => Self::Transaction,
+            _ => Self::Other,
+        }
+    }
+
+    /// whether statement modifies rows or schema
+    pub fn is_write(&self) -> bool {
+        matches!(self, Self::Insert | Self::Update | Self::Delete | Self::Ddl)
+    }
+}
+
 /// a sql file, may contains multi statements
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Program {
     pub params: Vec<Param>,
     pub tokens: Vec<VariableToken>,
 }
 
+/// how a variable is used, inferred from the token before it
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum VarUsage {
+    Single,
+    Array,
+}
+
+impl VarUsage {
+    fn after(token: &Token) -> Option<Self> {
+        match token {
+            Token::Word(word) if word.keyword == Keyword::IN => Some(VarUsage::Array),
+            Token::Word(word) if word.keyword == Keyword::LIKE => Some(VarUsage::Single),
+            Token::Eq
+            | Token::DoubleEq
+            | Token::Neq
+            | Token::Lt
+            | Token::Gt
+            | Token::LtEq
+            | Token::GtEq => Some(VarUsage::Single),
+            _ => None,
+        }
+    }
+}
+
+/// default max number of params in one program
+pub const DEFAULT_MAX_PARAMS: usize = 256;
+
+/// where [`normalize_source`] is, quotes in comments don't start a literal
+enum Scan {
+    Code,
+    Quoted(char),
+    LineComment,
+    BlockComment,
+}
+
+/// strip leading utf-8 BOM and convert CRLF or CR line endings to LF, so files saved on
+/// windows parse the same. Line endings in quoted literals are part of values and kept
+fn normalize_source(program: &str) -> String {
+    let program = program.strip_prefix('\u{feff}').unwrap_or(program);
+    let mut out = String::with_capacity(program.len());
+    let mut scan = Scan::Code;
+    let mut chars = program.chars().peekable();
+    while let Some(c) = chars.next() {
+        match scan {
+            // doubled quote closes and reopens literal, which keeps the state right
+            Scan::Quoted(q) if c == q => scan = Scan::Code,
+            Scan::Quoted(_) => {}
+            _ if c == '\r' => {
+                chars.next_if_eq(&'\n');
+                if let Scan::LineComment = scan {
+                    scan = Scan::Code;
+                }
+                out.push('\n');
+                continue;
+            }
+            Scan::LineComment if c == '\n' => scan = Scan::Code,
+            Scan::BlockComment if c == '*' && chars.peek() == Some(&'/') => {
+                out.push(c);
+                out.extend(chars.next());
+                scan = Scan::Code;
+                continue;
+            }
+            Scan::Code if c == '-' && chars.peek() == Some(&'-') => scan = Scan::LineComment,
+            Scan::Code if c == '/' && chars.peek() == Some(&'*') => {
+                out.push(c);
+                out.extend(chars.next());
+                scan = Scan::BlockComment;
+                continue;
+            }
+            Scan::Code if matches!(c, '\'' | '"' | '`') => scan = Scan::Quoted(c),
+            _ => {}
+        }
+        out.push(c);
+    }
+    out
+}
+
 impl Program {
-    pub fn parse(dialect: &impl Dialect, program: &str) -> Result<Program, PSqlError> {
-        let tokens = sqlparser::tokenizer::Tokenizer::new(dialect, program)
+    pub fn parse(dialect: &dyn Dialect, program: &str) -> Result<Program, PSqlError> {
+        Self::parse_with_limit(dialect, program, DEFAULT_MAX_PARAMS)
+    }
+
+    /// tokenize program into variable tokens, and parse param definitions from `--?` comments
+    fn tokenize(
+        dialect: &dyn Dialect,
+        program: &str,
+        max_params: usize,
+    ) -> Result<(Vec<Param>, Vec<VariableToken>), PSqlError> {
+        let program = normalize_source(program);
+        let tokens = sqlparser::tokenizer::Tokenizer::new(dialect, &program)
             .tokenize()
             .map_err(PSqlError::TokenizeError)?;
         let mut processed = vec![];
@@ -500,9 +2517,81 @@ impl Program {
                 Token::Whitespace(ws) => match ws {
                     Whitespace::SingleLineComment { comment, prefix } => {
                         if comment.starts_with('?') {
-                            let (_, param) = param::<nom::error::VerboseError<&str>>(&comment)
-                                .map_err(|e| PSqlError::ParamParseError(format!("{:#?}", e)))?;
+                            let (rest, (mut param, pattern)) =
+                                param::<nom::error::VerboseError<&str>>(&comment).map_err(|e| {
+                                    match failed_choices(&e) {
+                                        Some(list) => PSqlError::InvalidChoices(
+                                            list.to_string(),
+                                            param_name(&comment).to_string(),
+                                        ),
+                                        None => PSqlError::ParamParseError(format!("{:#?}", e)),
+                                    }
+                                })?;
+                            if !rest.trim().is_empty() {
+                                return Err(PSqlError::ParamParseError(format!(
+                                    "unexpected `{}` in param {}",
+                                    rest.trim(),
+                                    param.name
+                                )));
+                            }
+                            let str_param = matches!(
+                                param.ty,
+                                ParamTy::Basic(InnerTy::Str) | ParamTy::Array(InnerTy::Str)
+                            );
+                            if let Some(pattern) = pattern {
+                                if !str_param {
+                                    return Err(PSqlError::ParamParseError(format!(
+                                        "pattern only applies to str param, got {}: {}",
+                                        param.name, param.ty
+                                    )));
+                                }
+                                param.pattern = Some(Pattern::new(&pattern).map_err(|e| {
+                                    PSqlError::InvalidPattern(param.name.clone(), e.to_string())
+                                })?);
+                            }
+                            param
+                                .check_sort_options(dialect)
+                                .map_err(|e| PSqlError::InvalidSortOption(param.name.clone(), e))?;
+                            match (&param.ty, &param.default) {
+                                // arrays render in parentheses, which identifiers can't be
+                                (ParamTy::Array(ty @ (InnerTy::Subquery | InnerTy::Ident)), _) => {
+                                    return Err(PSqlError::ParamParseError(format!(
+                                        "{} param {} can't be array",
+                                        ty, param.name
+                                    )));
+                                }
+                                // subquery default is written as str and checked like input
+                                (ParamTy::Basic(ty), Some(ParamValue::Str(text)))
+                                    if *ty == InnerTy::Subquery =>
+                                {
+                                    let default =
+                                        ParamValue::from_arg_str(ty, text).map_err(|e| {
+                                            PSqlError::InvalidDefault(
+                                                param.name.clone(),
+                                                e.to_string(),
+                                            )
+                                        })?;
+                                    param.default = Some(default);
+                                }
+                                _ => {}
+                            }
+                            param
+                                .check_default()
+                                .map_err(|e| PSqlError::InvalidDefault(param.name.clone(), e))?;
+                            if !param.transforms.is_empty() && !str_param {
+                                return Err(PSqlError::ParamParseError(format!(
+                                    "transforms only apply to str param, got {}: {}",
+                                    param.name, param.ty
+                                )));
+                            }
+                            if params.len() >= max_params {
+                                return Err(PSqlError::TooManyParams(max_params));
+                            }
                             params.push(param);
+                        } else if comment.trim_start().starts_with('?') {
+                            return Err(PSqlError::MisplacedParamMark(
+                                comment.trim_end().to_string(),
+                            ));
                         } else {
                             processed.push(VariableToken::Normal(Token::Whitespace(
                                 Whitespace::SingleLineComment { comment, prefix },
@@ -520,6 +2609,16 @@ impl Program {
                 }
             }
         }
+        Ok((params, processed))
+    }
+
+    /// parse program, error if it defines more than `max_params` params
+    pub fn parse_with_limit(
+        dialect: &dyn Dialect,
+        program: &str,
+        max_params: usize,
+    ) -> Result<Program, PSqlError> {
+        let (params, processed) = Self::tokenize(dialect, program, max_params)?;
         // validation check
         let param_names_vec = params.iter().map(|p| p.name.clone());
         // 1. check duplication
@@ -529,6 +2628,36 @@ impl Program {
                 return Err(PSqlError::DuplicatedParam(p));
             }
         }
+        for p in params.iter() {
+            for other in p.requires.iter().chain(p.conflicts.iter()) {
+                if other == &p.name || !param_names.contains(other) {
+                    return Err(PSqlError::UnknownParamRef(p.name.clone(), other.clone()));
+                }
+            }
+            if let Some(from) = &p.default_from {
+                match params.iter().find(|other| &other.name == from) {
+                    None => return Err(PSqlError::UnknownParamRef(p.name.clone(), from.clone())),
+                    Some(other) if other.ty != p.ty => {
+                        return Err(PSqlError::InvalidDefault(
+                            p.name.clone(),
+                            format!("@{} is {}, expect {}", from, other.ty, p.ty),
+                        ))
+                    }
+                    Some(_) => {}
+                }
+            }
+        }
+        // default references must not form a cycle, e.g. `a = @b` and `b = @a`
+        for p in params.iter() {
+            let mut visited = HashSet::new();
+            let mut current = p;
+            while let Some(from) = &current.default_from {
+                if !visited.insert(from.as_str()) {
+                    return Err(PSqlError::DefaultCycle(p.name.clone()));
+                }
+                current = params.iter().find(|other| &other.name == from).unwrap();
+            }
+        }
         let mut var_names = HashSet::new();
         for t in processed.iter() {
             if let VariableToken::Var(name) = t {
@@ -545,23 +2674,354 @@ impl Program {
         if !unused.is_empty() {
             return Err(PSqlError::UnusedParams(unused));
         }
+        // 4. check variable is not used as both single value and array
+        let mut usages: HashMap<&str, VarUsage> = HashMap::new();
+        let mut prev = None;
+        for t in processed.iter() {
+            match t {
+                VariableToken::Var(name) => {
+                    if let Some(usage) = prev.and_then(VarUsage::after) {
+                        if *usages.entry(name).or_insert(usage) != usage {
+                            return Err(PSqlError::ConflictingUsage(name.clone()));
+                        }
+                    }
+                    prev = None;
+                }
+                VariableToken::Normal(Token::Whitespace(_)) => {}
+                VariableToken::Normal(token) => prev = Some(token),
+            }
+        }
         Ok(Program {
             tokens: processed,
             params,
         })
     }
 
+    /// params caller must give, see [`Param::required`]
+    pub fn required_params(&self) -> Vec<&Param> {
+        self.params.iter().filter(|p| p.required()).collect()
+    }
+
+    /// params which have a default, default to another param or are nullable
+    pub fn optional_params(&self) -> Vec<&Param> {
+        self.params.iter().filter(|p| !p.required()).collect()
+    }
+
+    /// check `requires` and `conflicts` of params against names of params given by caller,
+    /// params filled by default don't count as given
+    pub fn check_constraints(&self, given: &HashSet<&str>) -> Result<(), PSqlError> {
+        for p in self
+            .params
+            .iter()
+            .filter(|p| given.contains(p.name.as_str()))
+        {
+            if let Some(other) = p.requires.iter().find(|r| !given.contains(r.as_str())) {
+                return Err(PSqlError::ParamRequires(p.name.clone(), other.clone()));
+            }
+            if let Some(other) = p.conflicts.iter().find(|c| given.contains(c.as_str())) {
+                return Err(PSqlError::ParamConflicts(p.name.clone(), other.clone()));
+            }
+        }
+        Ok(())
+    }
+
+    /// check values of params given by caller against their choices and ranges
+    pub fn check_values(
+        &self,
+        context: &HashMap<String, ParamValue>,
+        given: &HashSet<&str>,
+    ) -> Result<(), PSqlError> {
+        for p in self
+            .params
+            .iter()
+            .filter(|p| given.contains(p.name.as_str()))
+        {
+            // report the offending item of array
+            let invalid = match context.get(&p.name) {
+                Some(ParamValue::Array(items)) => items.iter().find(|item| !p.allows(item)),
+                value => value.filter(|value| !p.allows(value)),
+            };
+            if let Some(value) = invalid {
+                return Err(PSqlError::InvalidChoice(
+                    value.to_string(),
+                    p.name.clone(),
+                    p.choices_text(),
+                ));
+            }
+            if let Some(value) = context.get(&p.name) {
+                p.check_range(value)?;
+                p.check_pattern(value)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// fill params which are not given and default to other params, `context` should already
+    /// contain given values and plain defaults, references are followed until all resolved
+    pub fn resolve_defaults(
+        &self,
+        context: &mut HashMap<String, ParamValue>,
+    ) -> Result<(), PSqlError> {
+        loop {
+            let pending = self
+                .params
+                .iter()
+                .filter(|p| !context.contains_key(&p.name))
+                .filter_map(|p| Some((p, p.default_from.as_ref()?)))
+                .collect::<Vec<_>>();
+            if pending.is_empty() {
+                return Ok(());
+            }
+            let mut resolved = false;
+            for (p, from) in pending.iter() {
+                if let Some(value) = context.get(*from).cloned() {
+                    context.insert(p.name.clone(), value);
+                    resolved = true;
+                }
+            }
+            if !resolved {
+                let (p, from) = pending[0];
+                return match pending.iter().any(|(other, _)| &other.name == from) {
+                    true => Err(PSqlError::DefaultCycle(p.name.clone())),
+                    false => Err(PSqlError::MissingContextValue(from.clone())),
+                };
+            }
+        }
+    }
+
+    /// substitute variables with plain text of context values, without parsing result as sql
+    ///
+    /// **NOTE** template is still tokenized as sql by [`Program::parse`], so it can't have
+    /// unmatched quotes, e.g. a single apostrophe of `don't` fails parsing
+    pub fn render_text(&self, context: &HashMap<String, ParamValue>) -> Result<String, PSqlError> {
+        let mut text = String::new();
+        for t in self.tokens.iter() {
+            match t {
+                VariableToken::Var(var) => match context.get(var) {
+                    Some(val) => text.push_str(&val.to_text()),
+                    None => return Err(PSqlError::MissingContextValue(var.clone())),
+                },
+                VariableToken::Normal(t) => text.push_str(&t.to_string()),
+            }
+        }
+        Ok(text)
+    }
+
+    /// render with param defaults, or placeholder values of param type if there is no default,
+    /// for inspecting statement structure which doesn't depend on param values
+    pub fn sample_statements(
+        &self,
+        dialect: &dyn Dialect,
+    ) -> Result<Vec<sqlparser::ast::Statement>, PSqlError> {
+        let placeholder = |ty: &InnerTy| match ty {
+            InnerTy::Str => ParamValue::Str(String::new()),
+            InnerTy::Num => ParamValue::Num(0.0),
+            InnerTy::Raw => ParamValue::Raw("NULL".to_string()),
+            InnerTy::Bool => ParamValue::Bool(false),
+            InnerTy::Date => ParamValue::Str("1970-01-01".to_string()),
+            InnerTy::Datetime => ParamValue::Str("1970-01-01T00:00:00Z".to_string()),
+            InnerTy::Subquery => ParamValue::Raw("(SELECT NULL)".to_string()),
+            InnerTy::Ident => ParamValue::Ident("_".to_string()),
+        };
+        let context = self
+            .params
+            .iter()
+            .map(|p| {
+                let value = match (&p.default, &p.ty) {
+                    (Some(default), _) => default.clone(),
+                    (None, ParamTy::Basic(ty)) => placeholder(ty),
+                    (None, ParamTy::Array(ty)) => ParamValue::Array(vec![placeholder(ty)]),
+                };
+                (p.name.clone(), value)
+            })
+            .collect();
+        self.render(dialect, &context)
+    }
+
+    /// parse sql fragment which shares params of this program, e.g. a hook running along
+    /// with it. Fragment can't define params, its variables must be params of this program
+    pub fn fragment(&self, dialect: &dyn Dialect, text: &str) -> Result<Program, PSqlError> {
+        let (params, tokens) = Self::tokenize(dialect, text, usize::MAX)?;
+        if let Some(p) = params.first() {
+            return Err(PSqlError::ParamParseError(format!(
+                "param {} can't be defined in sql fragment",
+                p.name
+            )));
+        }
+        let missing: HashSet<String> = tokens
+            .iter()
+            .filter_map(|t| match t {
+                VariableToken::Var(name) if !self.params.iter().any(|p| &p.name == name) => {
+                    Some(name.clone())
+                }
+                _ => None,
+            })
+            .collect();
+        if !missing.is_empty() {
+            return Err(PSqlError::MissingParams(missing));
+        }
+        Ok(Program {
+            params: self.params.clone(),
+            tokens,
+        })
+    }
+
+    /// kind of every statement in order and whether it writes, statements are rendered by
+    /// [`Program::sample_statements`], e.g. to find the final `SELECT` of a mixed file
+    pub fn statement_kinds(
+        &self,
+        dialect: &dyn Dialect,
+    ) -> Result<Vec<(StatementKind, bool)>, PSqlError> {
+        Ok(self
+            .sample_statements(dialect)?
+            .iter()
+            .map(|stmt| {
+                let kind = StatementKind::of(stmt);
+                (kind, kind.is_write())
+            })
+            .collect())
+    }
+
+    /// render as prepared statement text, variables become placeholders, `?` or `$n` for
+    /// postgres, and values to bind are returned in placeholder order. Array takes one
+    /// placeholder per item, `raw` values are interpolated as [`Program::render`] does,
+    /// `NULL` and bools are written as keywords
+    /// tokens with ident sort keys written out with their collation and null ordering, null
+    /// ordering follows sort direction if key has one, e.g. `@sort DESC` renders as
+    /// `name DESC NULLS LAST`
+    fn expand_sort_keys(
+        &self,
+        dialect: &dyn Dialect,
+        context: &HashMap<String, ParamValue>,
+    ) -> Cow<'_, [VariableToken]> {
+        if !self.params.iter().any(Param::sort_options) {
+            return Cow::Borrowed(&self.tokens);
+        }
+        let mut expanded = vec![];
+        // null ordering of last key and spaces after key, held until direction is seen
+        let mut nulls = vec![];
+        let mut spaces = vec![];
+        for t in self.tokens.iter() {
+            if !nulls.is_empty() {
+                match t {
+                    VariableToken::Normal(Token::Whitespace(
+                        Whitespace::Space | Whitespace::Tab | Whitespace::Newline,
+                    )) => {
+                        spaces.push(t.clone());
+                        continue;
+                    }
+                    t if is_sort_direction(t, context) => {
+                        expanded.append(&mut spaces);
+                        expanded.push(t.clone());
+                        expanded.extend(nulls.drain(..).map(VariableToken::Normal));
+                        continue;
+                    }
+                    _ => {
+                        expanded.extend(nulls.drain(..).map(VariableToken::Normal));
+                        expanded.append(&mut spaces);
+                    }
+                }
+            }
+            let key = match t {
+                VariableToken::Var(var) => match context.get(var) {
+                    Some(ParamValue::Ident(key)) => self
+                        .params
+                        .iter()
+                        .find(|p| &p.name == var && p.sort_options())
+                        .map(|p| (p, key)),
+                    _ => None,
+                },
+                VariableToken::Normal(_) => None,
+            };
+            match key {
+                Some((p, key)) => {
+                    let (tokens, after) = sort_key_tokens(p, key, dialect);
+                    expanded.extend(tokens.into_iter().map(VariableToken::Normal));
+                    nulls = after;
+                }
+                None => expanded.push(t.clone()),
+            }
+        }
+        expanded.extend(nulls.into_iter().map(VariableToken::Normal));
+        expanded.append(&mut spaces);
+        Cow::Owned(expanded)
+    }
+
+    pub fn render_prepared(
+        &self,
+        dialect: &dyn Dialect,
+        context: &HashMap<String, ParamValue>,
+    ) -> Result<(String, Vec<ParamValue>), PSqlError> {
+        fn push_value(
+            value: &ParamValue,
+            dialect: &dyn Dialect,
+            cast: &str,
+            sql: &mut String,
+            values: &mut Vec<ParamValue>,
+        ) {
+            match value {
+                ParamValue::Str(_) | ParamValue::Num(_) => {
+                    values.push(value.clone());
+                    if dialect.is::<PostgreSqlDialect>() {
+                        sql.push_str(&format!("${}{}", values.len(), cast));
+                    } else {
+                        sql.push('?');
+                    }
+                }
+                ParamValue::Array(items) if items.is_empty() => sql.push_str("(NULL)"),
+                ParamValue::Array(items) => {
+                    sql.push('(');
+                    for (idx, item) in items.iter().enumerate() {
+                        if idx > 0 {
+                            sql.push_str(", ");
+                        }
+                        push_value(item, dialect, cast, sql, values);
+                    }
+                    sql.push(')');
+                }
+                value => sql.extend(value.clone().into_token(dialect).iter().map(token_sql)),
+            }
+        }
+        let mut sql = String::new();
+        let mut values = vec![];
+        for t in self.expand_sort_keys(dialect, context).iter() {
+            match t {
+                VariableToken::Var(var) => match context.get(var) {
+                    Some(val) => {
+                        // strings are bound as text on postgres, which isn't compared with
+                        // date or timestamp columns without a cast
+                        let ty = self.params.iter().find(|p| &p.name == var).map(|p| &p.ty);
+                        let cast = match ty {
+                            Some(ParamTy::Basic(InnerTy::Date) | ParamTy::Array(InnerTy::Date)) => {
+                                "::date"
+                            }
+                            Some(
+                                ParamTy::Basic(InnerTy::Datetime)
+                                | ParamTy::Array(InnerTy::Datetime),
+                            ) => "::timestamptz",
+                            _ => "",
+                        };
+                        push_value(val, dialect, cast, &mut sql, &mut values)
+                    }
+                    None => return Err(PSqlError::MissingContextValue(var.clone())),
+                },
+                VariableToken::Normal(t) => sql.push_str(&token_sql(t)),
+            }
+        }
+        Ok((sql, values))
+    }
+
     /// take parameter values and return parsed sql statement
     ///
     /// **NOTE** this method don't handle parameter wih default value
     /// so you should pass default value in context
-    pub fn render<D: Dialect>(
+    pub fn render(
         &self,
-        dialect: &D,
+        dialect: &dyn Dialect,
         context: &HashMap<String, ParamValue>,
     ) -> Result<Vec<sqlparser::ast::Statement>, PSqlError> {
         let mut transformed = vec![];
-        for t in self.tokens.iter() {
+        for t in self.expand_sort_keys(dialect, context).iter() {
             match t {
                 VariableToken::Var(var) => {
                     if let Some(val) = context.get(var) {
@@ -573,13 +3033,7 @@ impl Program {
                 VariableToken::Normal(t) => transformed.push(t.clone()),
             }
         }
-        log::info!(
-            "{}",
-            transformed
-                .iter()
-                .map(|t| t.to_string())
-                .collect::<String>()
-        );
+        log::info!("{}", transformed.iter().map(token_sql).collect::<String>());
         let mut parser = sqlparser::parser::Parser::new(transformed, dialect);
         let mut stmts = Vec::new();
         let mut expecting_statement_delimiter = false;
@@ -606,11 +3060,11 @@ impl Program {
 
 #[cfg(feature = "http")]
 impl Program {
-    /// generate open api doc parameters
-    pub fn generate_params(&self) -> Vec<ReferenceOr<Parameter>> {
+    /// generate open api doc parameters, help message is picked by `lang`
+    pub fn generate_params(&self, lang: Option<&str>) -> Vec<ReferenceOr<Parameter>> {
         self.params
             .iter()
-            .map(|p| ReferenceOr::Item(p.to_openapi_param()))
+            .map(|p| ReferenceOr::Item(p.to_openapi_param(lang)))
             .collect()
     }
 
@@ -650,16 +3104,42 @@ impl Program {
 
 #[cfg(feature = "cli")]
 impl Program {
-    /// add command line options
+    /// add command line options, help message is picked by `LANG` env
     pub fn add_options(&self, opts: &mut getopts::Options) {
+        let lang = std::env::var("LANG").ok();
         for p in self.params.iter() {
-            match (&p.default, &p.ty) {
+            let help = p.help_for(lang.as_deref());
+            let default = p
+                .default
+                .as_ref()
+                .map(|default| default.to_string())
+                .or_else(|| p.default_from.as_ref().map(|from| format!("@{}", from)));
+            match (&default, &p.ty) {
+                (None, ParamTy::Basic(InnerTy::Bool)) if !p.nullable => {
+                    opts.optflag("", &p.name, help);
+                }
+                (None, ParamTy::Basic(_)) if p.nullable => {
+                    opts.optopt(
+                        "",
+                        &p.name,
+                        help,
+                        &format!("[{}] {}? NULL", p.name.to_uppercase(), p.ty),
+                    );
+                }
+                (None, ParamTy::Array(_)) if p.nullable => {
+                    opts.optmulti(
+                        "",
+                        &p.name,
+                        help,
+                        &format!("<{}> {}? NULL", p.name.to_uppercase(), p.ty),
+                    );
+                }
                 (None, ParamTy::Basic(_)) => {
                     opts.reqopt(
                         "",
                         &p.name,
-                        &p.help,
-                        &format!("*<{}> {}", p.name.to_uppercase(), p.ty.to_string()),
+                        help,
+                        &format!("*<{}> {}", p.name.to_uppercase(), p.ty),
                     );
                 }
                 (None, ParamTy::Array(_)) => {
@@ -667,58 +3147,93 @@ impl Program {
                         "",
                         &p.name,
                         &p.name,
-                        &format!("*<{}> {}", p.name.to_uppercase(), p.ty.to_string()),
+                        &format!("*<{}> {}", p.name.to_uppercase(), p.ty),
                     );
                 }
                 (Some(default), ParamTy::Basic(_)) => {
                     opts.optopt(
                         "",
                         &p.name,
-                        &p.help,
-                        &format!(
-                            "[{}] {} {}",
-                            p.name.to_uppercase(),
-                            p.ty.to_string(),
-                            default.to_string()
-                        ),
+                        help,
+                        &format!("[{}] {} {}", p.name.to_uppercase(), p.ty, default),
                     );
                 }
                 (Some(default), ParamTy::Array(_)) => {
                     opts.optmulti(
                         "",
                         &p.name,
-                        &p.help,
-                        &format!(
-                            "<{}> {} {}",
-                            p.name.to_uppercase(),
-                            p.ty.to_string(),
-                            default.to_string()
-                        ),
+                        help,
+                        &format!("<{}> {} {}", p.name.to_uppercase(), p.ty, default),
                     );
                 }
             }
         }
     }
 
-    /// read from args
+    /// params spec as json array, for tools wrapping cli
+    pub fn params_json(&self) -> serde_json::Value {
+        self.params
+            .iter()
+            .map(|p| {
+                serde_json::json!({
+                    "name": p.name,
+                    "type": p.ty.to_string(),
+                    "default": p.default.clone().map(serde_json::Value::from),
+                    "default_from": p.default_from,
+                    "help": p.help,
+                    "required": p.required(),
+                    "nullable": p.nullable,
+                    "choices": p.choices.clone().map(|choices| {
+                        choices.into_iter().map(serde_json::Value::from).collect::<Vec<_>>()
+                    }),
+                    "pattern": p.pattern.as_ref().map(|pattern| pattern.as_str()),
+                    "requires": p.requires,
+                    "conflicts": p.conflicts,
+                })
+            })
+            .collect()
+    }
+
+    /// usage message of options added by [`Program::add_options`]
+    pub fn usage_string(&self, opts: &getopts::Options) -> String {
+        opts.usage("psql")
+    }
+
+    /// read param values from args
+    ///
+    /// **NOTE** `-h` and `--dump-params` are not handled, caller should check them
+    /// and print [`Program::usage_string`] or [`Program::params_json`] as it likes
     pub fn get_matches(
         &self,
         opts: &getopts::Options,
         args: &[String],
     ) -> Result<HashMap<String, ParamValue>, getopts::Fail> {
-        use std::process::exit;
-        if args.contains(&"-h".to_string()) || args.contains(&"--help".to_string()) {
-            println!("{}", opts.usage("psql"));
-            exit(0)
-        }
         match opts.parse(args) {
             Ok(matches) => {
+                let given = self
+                    .params
+                    .iter()
+                    .map(|p| p.name.as_str())
+                    .filter(|name| matches.opt_present(name))
+                    .collect();
+                self.check_constraints(&given)
+                    .map_err(|e| getopts::Fail::UnexpectedArgument(e.to_string()))?;
                 let mut values = HashMap::new();
                 for p in self.params.iter() {
                     match &p.ty {
+                        // bool without default is a flag
+                        ParamTy::Basic(InnerTy::Bool)
+                            if p.default.is_none() && p.default_from.is_none() && !p.nullable =>
+                        {
+                            values.insert(
+                                p.name.clone(),
+                                ParamValue::Bool(matches.opt_present(&p.name)),
+                            );
+                        }
                         ParamTy::Basic(ty) => {
                             let ocr: Option<String> = matches.opt_str(&p.name);
-                            match (ocr, p.default.clone()) {
+                            match (ocr, p.omitted_value()) {
+                                (None, None) if p.default_from.is_some() => {}
                                 (None, None) => {
                                     return Err(getopts::Fail::OptionMissing(p.name.clone()));
                                 }
@@ -728,7 +3243,7 @@ impl Program {
                                 (Some(arg_str), _) => {
                                     match ParamValue::from_arg_str(ty, &arg_str) {
                                         Ok(val) => {
-                                            values.insert(p.name.clone(), val);
+                                            values.insert(p.name.clone(), p.transform(val));
                                         }
                                         Err(e) => {
                                             return Err(getopts::Fail::UnexpectedArgument(
@@ -741,7 +3256,8 @@ impl Program {
                         }
                         ParamTy::Array(ty) => {
                             let ocrs = matches.opt_strs(&p.name);
-                            match (ocrs.is_empty(), p.default.clone()) {
+                            match (ocrs.is_empty(), p.omitted_value()) {
+                                (true, None) if p.default_from.is_some() => {}
                                 (true, None) => {
                                     return Err(getopts::Fail::OptionMissing(p.name.clone()));
                                 }
@@ -752,7 +3268,7 @@ impl Program {
                                     let mut vals = vec![];
                                     for arg_str in ocrs.iter() {
                                         match ParamValue::from_arg_str(ty, arg_str) {
-                                            Ok(val) => vals.push(val),
+                                            Ok(val) => vals.push(p.transform(val)),
                                             Err(e) => {
                                                 return Err(getopts::Fail::UnexpectedArgument(
                                                     format!("{}, {}", p.name, e),
@@ -766,6 +3282,10 @@ impl Program {
                         }
                     }
                 }
+                self.check_values(&values, &given)
+                    .map_err(|e| getopts::Fail::UnexpectedArgument(e.to_string()))?;
+                self.resolve_defaults(&mut values)
+                    .map_err(|e| getopts::Fail::UnexpectedArgument(e.to_string()))?;
                 Ok(values)
             }
             Err(e) => Err(e),